@@ -0,0 +1,266 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::cache::now_secs;
+use crate::config::{AlertSinkConfig, AlertsConfig};
+use crate::thumbnail::BrowserEvent;
+
+/// A condition worth telling someone about. Each fires once, repeats while it persists, and
+/// sends a resolved alert when it clears.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    ErrorRate,
+    Browser,
+    CacheDisk,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// The JSON body webhook sinks receive.
+#[derive(Debug, Clone, Serialize)]
+struct Alert {
+    kind: AlertKind,
+    status: AlertStatus,
+    instance: String,
+    message: String,
+    at: u64,
+}
+
+/// Render outcomes in one second of the error rate window.
+struct Bucket {
+    second: u64,
+    requests: u64,
+    failures: u64,
+}
+
+/// Watches for a wedged renderer and sends alerts to the configured sinks.
+pub struct Alerter {
+    config: AlertsConfig,
+    client: reqwest::Client,
+    instance: String,
+    /// When each firing alert was last sent.
+    firing: Mutex<HashMap<AlertKind, Instant>>,
+    outcomes: Mutex<VecDeque<Bucket>>,
+}
+
+impl Alerter {
+    pub fn new(config: &AlertsConfig) -> anyhow::Result<Arc<Self>> {
+        let instance = config.instance.clone().or_else(hostname).unwrap_or_else(|| "tin".to_string());
+        if !config.sinks.is_empty() {
+            info!("Sending alerts for {} to {} sink(s)", instance, config.sinks.len());
+        }
+        Ok(Arc::new(Self {
+            config: config.clone(),
+            client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?,
+            instance,
+            firing: Mutex::new(HashMap::new()),
+            outcomes: Mutex::new(VecDeque::new()),
+        }))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.config.sinks.is_empty()
+    }
+
+    /// Counts a thumbnail request towards the error rate.
+    pub fn record(&self, failed: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let second = now_secs();
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        match outcomes.back_mut() {
+            Some(bucket) if bucket.second == second => {
+                bucket.requests += 1;
+                bucket.failures += failed as u64;
+            }
+            _ => outcomes.push_back(Bucket { second, requests: 1, failures: failed as u64 }),
+        }
+    }
+
+    /// Requests and failures within the window.
+    fn error_counts(&self) -> (u64, u64) {
+        let since = now_secs().saturating_sub(self.config.error_rate_window_secs);
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        while outcomes.front().is_some_and(|bucket| bucket.second <= since) {
+            outcomes.pop_front();
+        }
+        outcomes.iter().fold((0, 0), |(requests, failures), bucket| (requests + bucket.requests, failures + bucket.failures))
+    }
+
+    /// Periodically checks the error rate, the free space where the cache lives, and the
+    /// renderer's health, which catches a browser that hangs without exiting.
+    pub async fn run_checks<F, Fut>(self: Arc<Self>, cache_path: PathBuf, is_healthy: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.check_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            self.check_error_rate();
+            self.check_disk(&cache_path);
+            if is_healthy().await {
+                self.resolve(AlertKind::Browser, "Renderer is passing health checks again".to_string());
+            } else {
+                self.fire(AlertKind::Browser, "Renderer is failing health checks".to_string());
+            }
+        }
+    }
+
+    fn check_error_rate(&self) {
+        let threshold = self.config.error_rate_threshold;
+        if threshold <= 0.0 {
+            return;
+        }
+        let (requests, failures) = self.error_counts();
+        let window = self.config.error_rate_window_secs;
+        if requests < self.config.error_rate_min_requests.max(1) {
+            // Traffic stopping doesn't mean renders are fixed, but nothing is failing any more.
+            self.resolve(AlertKind::ErrorRate, format!("Only {} thumbnail requests in the last {}s", requests, window));
+            return;
+        }
+        let rate = failures as f64 / requests as f64;
+        if rate > threshold {
+            let message = format!(
+                "{} of {} thumbnail requests ({:.0}%) failed in the last {}s",
+                failures,
+                requests,
+                rate * 100.0,
+                window
+            );
+            self.fire(AlertKind::ErrorRate, message);
+        } else {
+            self.resolve(AlertKind::ErrorRate, format!("Error rate back to {:.0}% over the last {}s", rate * 100.0, window));
+        }
+    }
+
+    fn check_disk(&self, path: &Path) {
+        let threshold = self.config.disk_usage_threshold;
+        if threshold <= 0.0 {
+            return;
+        }
+        let (total, available) = match (fs2::total_space(path), fs2::available_space(path)) {
+            (Ok(total), Ok(available)) if total > 0 => (total, available),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to measure free space for {:?}: {}", path, e);
+                return;
+            }
+            _ => return,
+        };
+        let used = 1.0 - available as f64 / total as f64;
+        let gb = |bytes: u64| bytes as f64 / 1e9;
+        if used > threshold {
+            let message = format!(
+                "Cache disk at {:?} is {:.0}% full ({:.1} GB free of {:.1} GB)",
+                path,
+                used * 100.0,
+                gb(available),
+                gb(total)
+            );
+            self.fire(AlertKind::CacheDisk, message);
+        } else {
+            self.resolve(AlertKind::CacheDisk, format!("Cache disk at {:?} is back to {:.0}% full", path, used * 100.0));
+        }
+    }
+
+    /// Alerts on the browser exiting or dropping its connection until the receiver closes.
+    pub async fn watch_browser(self: Arc<Self>, mut events: broadcast::Receiver<BrowserEvent>) {
+        if !self.is_enabled() {
+            return;
+        }
+        loop {
+            match events.recv().await {
+                Ok(BrowserEvent::Exited) => self.fire(
+                    AlertKind::Browser,
+                    "Browser exited; only cached and browserless thumbnails are available until restart".to_string(),
+                ),
+                Ok(BrowserEvent::Disconnected) => {
+                    self.fire(AlertKind::Browser, "Lost the connection to the remote browser; reconnecting".to_string())
+                }
+                Ok(BrowserEvent::Reconnected { downtime }) => self.resolve(
+                    AlertKind::Browser,
+                    format!("Reconnected to the remote browser after {}s", downtime.as_secs()),
+                ),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Sends a firing alert, unless it was sent less than `repeat_after_secs` ago.
+    fn fire(&self, kind: AlertKind, message: String) {
+        let repeat_after = Duration::from_secs(self.config.repeat_after_secs);
+        {
+            let mut firing = self.firing.lock().unwrap_or_else(|e| e.into_inner());
+            if firing.get(&kind).is_some_and(|sent| sent.elapsed() < repeat_after) {
+                return;
+            }
+            firing.insert(kind, Instant::now());
+        }
+        warn!("Alert: {}", message);
+        self.send(kind, AlertStatus::Firing, message);
+    }
+
+    /// Sends a resolved alert if the kind was firing.
+    fn resolve(&self, kind: AlertKind, message: String) {
+        if self.firing.lock().unwrap_or_else(|e| e.into_inner()).remove(&kind).is_none() {
+            return;
+        }
+        info!("Alert resolved: {}", message);
+        self.send(kind, AlertStatus::Resolved, message);
+    }
+
+    /// Delivers to every sink in the background; failures are only logged.
+    fn send(&self, kind: AlertKind, status: AlertStatus, message: String) {
+        let alert = Alert { kind, status, instance: self.instance.clone(), message, at: now_secs() };
+        for sink in &self.config.sinks {
+            let body = match sink {
+                AlertSinkConfig::Webhook { .. } => serde_json::to_vec(&alert),
+                AlertSinkConfig::Slack { .. } => {
+                    let icon = match status {
+                        AlertStatus::Firing => ":rotating_light:",
+                        AlertStatus::Resolved => ":white_check_mark:",
+                    };
+                    let text = format!("{} *tin on {}*: {}", icon, alert.instance, alert.message);
+                    serde_json::to_vec(&serde_json::json!({ "text": text }))
+                }
+            };
+            let (AlertSinkConfig::Webhook { url } | AlertSinkConfig::Slack { url }) = sink;
+            let request = self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.unwrap_or_default());
+            let url = url.clone();
+            tokio::spawn(async move {
+                if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+                    warn!("Failed to send alert to {}: {}", url, e);
+                }
+            });
+        }
+    }
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::cache::now_secs;
+
+/// What came of a request, as recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// The page was fetched and rendered.
+    Rendered,
+    /// Served from the cache without contacting the site.
+    CacheHit,
+    /// Rendering failed and a placeholder was served; the site's favicon may have been fetched.
+    Placeholder,
+    /// Refused or failed; `code` says why.
+    Error,
+    /// Something fetched while serving a request, such as an `og:image` or robots.txt.
+    Fetched,
+}
+
+/// Who a request was made for: the tenant and the fingerprint of the key used.
+#[derive(Debug, Clone, Copy)]
+pub struct Requester<'a> {
+    pub tenant: &'a str,
+    pub key: &'a str,
+}
+
+impl Requester<'static> {
+    /// Admin endpoints, which take the admin key rather than a tenant's.
+    pub const ADMIN: Self = Self { tenant: "admin", key: "admin" };
+}
+
+/// One request for a page, appended when it completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix seconds.
+    pub at: u64,
+    pub tenant: String,
+    /// Fingerprint of the API key, or `-` without one.
+    pub key: String,
+    /// `thumbnail` for all thumbnail APIs, including collage tiles, or `full_page` or `debug_dom`.
+    /// Fetches made while serving them are `og_image`, `robots`, `probe`, `page`, `favicon`, or
+    /// `hook`.
+    pub endpoint: String,
+    pub url: String,
+    /// Where the page ended up after redirects, when it differs from `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    pub outcome: AuditOutcome,
+    /// HTTP status the request got; for fetches, 200 or 502 when the fetch failed.
+    pub status: u16,
+    /// Error code for failed requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// For fetches, the bytes received.
+    pub bytes_served: u64,
+}
+
+impl AuditEntry {
+    /// A fetch of `url` made while serving `requester`; `received` is `None` when it failed.
+    pub fn fetch(requester: Requester, endpoint: &str, url: &str, received: Option<u64>) -> Self {
+        Self {
+            at: now_secs(),
+            tenant: requester.tenant.to_string(),
+            key: requester.key.to_string(),
+            endpoint: endpoint.to_string(),
+            url: url.to_string(),
+            final_url: None,
+            outcome: if received.is_some() { AuditOutcome::Fetched } else { AuditOutcome::Error },
+            status: if received.is_some() { 200 } else { 502 },
+            code: received.is_none().then(|| "fetch_failed".to_string()),
+            bytes_served: received.unwrap_or(0),
+        }
+    }
+}
+
+/// Append-only record of every page request, for accounting for what the service fetched on
+/// whose behalf. Keys are the time then a sequence number, so entries stay in order and a time
+/// range is a contiguous key range. Entries are JSON so older ones stay readable as fields are
+/// added.
+pub struct AuditLog {
+    tree: Tree,
+    enabled: bool,
+    next: AtomicU64,
+}
+
+impl AuditLog {
+    pub fn new(tree: Tree, enabled: bool) -> anyhow::Result<Self> {
+        let next = match tree.last()? {
+            Some((key, _)) => sequence(&key).map_or(0, |seq| seq + 1),
+            None => 0,
+        };
+        Ok(Self { tree, enabled, next: AtomicU64::new(next) })
+    }
+
+    /// Appends `entry` unless auditing is turned off.
+    pub fn record(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut key = entry.at.to_be_bytes().to_vec();
+        key.extend_from_slice(&seq.to_be_bytes());
+        self.tree.insert(key, serde_json::to_vec(entry)?)?;
+        Ok(())
+    }
+
+    /// Entries in `[from, to)` in the order they were recorded, optionally for one tenant, with
+    /// the key to continue after when there are more than `limit`.
+    pub fn query(
+        &self,
+        from: u64,
+        to: u64,
+        tenant: Option<&str>,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<AuditEntry>, Option<Vec<u8>>)> {
+        let start = from.to_be_bytes().to_vec();
+        let lower = match after.filter(|after| *after >= start.as_slice()) {
+            Some(after) => Bound::Excluded(after.to_vec()),
+            None => Bound::Included(start),
+        };
+        let upper = Bound::Excluded(to.to_be_bytes().to_vec());
+
+        let mut entries = Vec::new();
+        let mut last_key = None;
+        for item in self.tree.range::<Vec<u8>, _>((lower, upper)) {
+            let (key, value) = item?;
+            let entry: AuditEntry = serde_json::from_slice(&value)
+                .map_err(|e| anyhow::anyhow!("Audit entry deserialization failed: {}", e))?;
+            if tenant.is_some_and(|t| t != entry.tenant) {
+                continue;
+            }
+            if entries.len() == limit {
+                return Ok((entries, last_key));
+            }
+            entries.push(entry);
+            last_key = Some(key.to_vec());
+        }
+        Ok((entries, None))
+    }
+
+    /// Removes entries recorded before `before`, returning how many.
+    pub fn purge_before(&self, before: u64) -> anyhow::Result<usize> {
+        let mut removed = 0;
+        for item in self.tree.range(..before.to_be_bytes()) {
+            let (key, _) = item?;
+            self.tree.remove(key)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+fn sequence(key: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(key.get(8..16)?.try_into().ok()?))
+}
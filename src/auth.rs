@@ -0,0 +1,233 @@
+use axum::http::{header, HeaderMap};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{AuthConfig, TenantConfig};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+pub struct TenantUsage {
+    requests: AtomicU64,
+    cache_hits: AtomicU64,
+    renders: AtomicU64,
+    errors: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageSnapshot {
+    pub requests: u64,
+    pub cache_hits: u64,
+    pub renders: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+}
+
+impl TenantUsage {
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_render(&self) {
+        self.renders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        UsageSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            renders: self.renders.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// An isolated consumer of the service, identified by one or more API keys.
+#[derive(Debug)]
+pub struct Tenant {
+    pub name: String,
+    pub namespace: String,
+    /// Hosts this tenant may render; empty means any.
+    pub allowed_domains: Vec<String>,
+    /// Overrides the global robots.txt setting when set.
+    pub respect_robots: Option<bool>,
+    /// May ask for certificate errors to be ignored.
+    pub allow_insecure_tls: bool,
+    /// Shared with the tenant of the same name after a reload, so counts carry over.
+    pub usage: Arc<TenantUsage>,
+    requests_per_minute: usize,
+    recent: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl Tenant {
+    fn new(config: &TenantConfig, previous: Option<&Tenant>) -> Self {
+        Self {
+            name: config.name.clone(),
+            namespace: config.cache_namespace.clone().unwrap_or_else(|| config.name.clone()),
+            allowed_domains: config.allowed_domains.iter().map(|d| d.to_ascii_lowercase()).collect(),
+            respect_robots: config.respect_robots,
+            allow_insecure_tls: config.allow_insecure_tls,
+            usage: previous.map(|t| t.usage.clone()).unwrap_or_default(),
+            requests_per_minute: config.requests_per_minute,
+            recent: previous.map(|t| t.recent.clone()).unwrap_or_default(),
+        }
+    }
+
+    pub fn allows_host(&self, host: &str) -> bool {
+        self.allowed_domains.is_empty() || self.allowed_domains.iter().any(|pattern| domain_matches(pattern, host))
+    }
+
+    /// Records a request against the per-minute limit, returning false once it's exhausted.
+    pub fn try_acquire_rate(&self) -> bool {
+        if self.requests_per_minute == 0 {
+            return true;
+        }
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        while recent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() >= self.requests_per_minute {
+            self.usage.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+}
+
+/// `example.com` matches the domain and its subdomains; `*.example.com` only subdomains.
+pub fn domain_matches(pattern: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+        None => host == pattern || host.ends_with(&format!(".{}", pattern)),
+    }
+}
+
+/// An authenticated key and the tenant it belongs to.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub tenant: Arc<Tenant>,
+    /// Short fingerprint of the key, safe to log and report.
+    pub id: String,
+}
+
+pub enum AuthError {
+    MissingKey,
+    InvalidKey,
+}
+
+/// Maps API keys to tenants. With no tenants configured the API is open, as before.
+pub struct Auth {
+    tenants: Vec<Arc<Tenant>>,
+    /// Keyed by SHA-256 of the API key, so lookups don't compare secrets directly.
+    by_key_hash: HashMap<String, ApiKey>,
+    admin_key_hash: Option<String>,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self::reloaded(config, None)
+    }
+
+    /// Builds the keys and tenants from `config`, keeping the usage counts and rate windows of
+    /// tenants in `previous` with the same name.
+    pub fn reloaded(config: &AuthConfig, previous: Option<&Auth>) -> Self {
+        let mut tenants = Vec::new();
+        let mut by_key_hash = HashMap::new();
+        for tenant_config in &config.tenants {
+            let before = previous.and_then(|auth| auth.tenants.iter().find(|t| t.name == tenant_config.name));
+            let tenant = Arc::new(Tenant::new(tenant_config, before.map(Arc::as_ref)));
+            for key in &tenant_config.api_keys {
+                let hash = hash_key(key);
+                let id = hash[..12].to_string();
+                by_key_hash.insert(hash, ApiKey { tenant: tenant.clone(), id });
+            }
+            tenants.push(tenant);
+        }
+        Self {
+            tenants,
+            by_key_hash,
+            admin_key_hash: config.admin_key.as_deref().map(hash_key),
+        }
+    }
+
+    pub fn tenants(&self) -> &[Arc<Tenant>] {
+        &self.tenants
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.by_key_hash.len()
+    }
+
+    /// Resolves the caller's key; `None` when auth is disabled.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<ApiKey>, AuthError> {
+        if self.tenants.is_empty() {
+            return Ok(None);
+        }
+        let key = presented_key(headers, "x-api-key").ok_or(AuthError::MissingKey)?;
+        self.by_key_hash.get(&hash_key(key)).cloned().map(Some).ok_or(AuthError::InvalidKey)
+    }
+
+    pub fn is_admin(&self, headers: &HeaderMap) -> bool {
+        match (&self.admin_key_hash, presented_key(headers, "x-admin-key")) {
+            (Some(expected), Some(key)) => *expected == hash_key(key),
+            _ => false,
+        }
+    }
+}
+
+/// Reads a key from the named header or an `Authorization: Bearer` header.
+fn presented_key<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+}
+
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_patterns_match_the_domain_and_its_subdomains() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("example.com", "WWW.Example.com"));
+        assert!(!domain_matches("example.com", "notexample.com"));
+        assert!(!domain_matches("example.com", "example.com.evil.test"));
+    }
+
+    #[test]
+    fn wildcard_patterns_match_subdomains_only() {
+        assert!(domain_matches("*.example.com", "a.example.com"));
+        assert!(domain_matches("*.example.com", "a.b.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "aexample.com"));
+    }
+}
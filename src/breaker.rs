@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::CircuitBreakerConfig;
+
+/// Hosts tracked before entries with expired cooldowns are pruned.
+const MAX_TRACKED_HOSTS: usize = 10_000;
+
+#[derive(Default)]
+struct HostCircuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Stops rendering hosts that keep failing, so a dead upstream can't tie up browser slots.
+///
+/// After `failure_threshold` consecutive failures the circuit opens for the cooldown. Once it
+/// passes, requests go through again, and a single further failure reopens it.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    hosts: Mutex<HashMap<String, HostCircuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            cooldown: Duration::from_secs(config.cooldown_secs),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long until `host` may be tried again, or `None` if its circuit is closed.
+    pub fn check(&self, host: &str) -> Option<Duration> {
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let open_until = hosts.get(host)?.open_until?;
+        open_until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    }
+
+    pub fn record_success(&self, host: &str) {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner()).remove(host);
+    }
+
+    pub fn record_failure(&self, host: &str) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        if hosts.len() >= MAX_TRACKED_HOSTS && !hosts.contains_key(host) {
+            let now = Instant::now();
+            hosts.retain(|_, circuit| circuit.open_until.is_some_and(|until| until > now));
+        }
+
+        let circuit = hosts.entry(host.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        if circuit.consecutive_failures >= self.failure_threshold {
+            warn!(
+                "Opening circuit for {} for {:?} after {} consecutive failures",
+                host, self.cooldown, circuit.consecutive_failures
+            );
+            circuit.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::info;
+
+use crate::config::DownloadConfig;
+
+/// Large enough for any Chromium archive.
+const MAX_ARCHIVE_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Returns the pinned Chromium build, downloading, verifying, and unpacking it into
+/// `config.dir` first if it isn't there yet. An installed build is only reused once it has
+/// been verified, so an interrupted install is started over.
+pub async fn install(config: &DownloadConfig) -> anyhow::Result<PathBuf> {
+    let platform = platform()?;
+    let target = config.dir.join(&config.version);
+    let binary = target.join(binary_path(platform));
+    if target.join(".verified").exists() && binary.exists() {
+        info!("Using downloaded Chromium {} at {:?}", config.version, binary);
+        return Ok(binary);
+    }
+    let expected = config.sha256.get(platform).map(|digest| digest.trim().to_ascii_lowercase()).ok_or_else(|| {
+        anyhow::anyhow!("No checksum for {} in browser.download.sha256, so Chromium can't be downloaded safely", platform)
+    })?;
+
+    tokio::fs::create_dir_all(&config.dir).await?;
+    let url = format!("{}/{}/{}/chrome-{}.zip", config.base_url.trim_end_matches('/'), config.version, platform, platform);
+    let archive = config.dir.join(format!("{}-{}.zip", config.version, std::process::id()));
+    let staging = config.dir.join(format!("{}.partial-{}", config.version, std::process::id()));
+    let result = async {
+        info!("Downloading Chromium {} from {}", config.version, url);
+        let digest = download(&url, &archive).await?;
+        if digest != expected {
+            anyhow::bail!("Checksum mismatch for {}: expected {}, got {}", url, expected, digest);
+        }
+        unpack(&archive, &staging).await?;
+        // Chrome on Windows doesn't print its version.
+        if !cfg!(target_os = "windows") {
+            check_version(&staging.join(binary_path(platform)), &config.version).await?;
+        }
+        tokio::fs::write(staging.join(".verified"), &digest).await?;
+        if target.exists() {
+            tokio::fs::remove_dir_all(&target).await?;
+        }
+        tokio::fs::rename(&staging, &target).await?;
+        Ok(())
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&archive).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+    }
+    result?;
+    info!("Installed Chromium {} at {:?}", config.version, binary);
+    Ok(binary)
+}
+
+/// The Chrome for Testing name of the platform this service runs on.
+fn platform() -> anyhow::Result<&'static str> {
+    Ok(match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "linux64",
+        ("linux", "aarch64") => "linux-arm64",
+        ("macos", "x86_64") => "mac-x64",
+        ("macos", "aarch64") => "mac-arm64",
+        ("windows", "x86_64") => "win64",
+        ("windows", "x86") => "win32",
+        (os, arch) => anyhow::bail!("No Chromium download is available for {}-{}", os, arch),
+    })
+}
+
+/// The browser binary within an unpacked archive.
+fn binary_path(platform: &str) -> PathBuf {
+    let dir = PathBuf::from(format!("chrome-{}", platform));
+    if platform.starts_with("mac") {
+        dir.join("Google Chrome for Testing.app/Contents/MacOS/Google Chrome for Testing")
+    } else if platform.starts_with("win") {
+        dir.join("chrome.exe")
+    } else {
+        dir.join("chrome")
+    }
+}
+
+/// Streams `url` to `path`, returning the SHA-256 of what was written.
+async fn download(url: &str, path: &Path) -> anyhow::Result<String> {
+    let client = reqwest::Client::builder().connect_timeout(Duration::from_secs(30)).build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+    while let Some(chunk) = response.chunk().await? {
+        written += chunk.len() as u64;
+        if written > MAX_ARCHIVE_BYTES {
+            anyhow::bail!("{} is larger than {} bytes", url, MAX_ARCHIVE_BYTES);
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Unpacks a zip archive with the system's own tool, keeping file modes.
+async fn unpack(archive: &Path, into: &Path) -> anyhow::Result<()> {
+    if into.exists() {
+        tokio::fs::remove_dir_all(into).await?;
+    }
+    tokio::fs::create_dir_all(into).await?;
+    let mut command = if cfg!(target_os = "windows") {
+        let mut tar = Command::new("tar");
+        tar.arg("-xf").arg(archive).arg("-C").arg(into);
+        tar
+    } else {
+        let mut unzip = Command::new("unzip");
+        unzip.arg("-q").arg(archive).arg("-d").arg(into);
+        unzip
+    };
+    let output = command.output().await.map_err(|e| anyhow::anyhow!("Failed to run the archive tool: {}", e))?;
+    if !output.status.success() {
+        anyhow::bail!("Unpacking {:?} failed: {}", archive, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Runs the unpacked browser to check it starts on this machine and is the pinned version.
+async fn check_version(binary: &Path, version: &str) -> anyhow::Result<()> {
+    let output = tokio::time::timeout(Duration::from_secs(30), Command::new(binary).arg("--version").output())
+        .await
+        .map_err(|_| anyhow::anyhow!("{:?} --version timed out", binary))?
+        .map_err(|e| anyhow::anyhow!("Failed to run {:?}: {}", binary, e))?;
+    let reported = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !reported.contains(version) {
+        anyhow::bail!(
+            "Downloaded browser reports {:?} instead of version {}: {}",
+            reported.trim(),
+            version,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
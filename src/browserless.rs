@@ -0,0 +1,129 @@
+use url::Url;
+
+/// Largest part of a page searched for metadata; `<head>` comes well before this.
+const MAX_SCAN_BYTES: usize = 512 * 1024;
+
+/// What a page's HTML says about itself, read without a browser.
+#[derive(Debug, Default)]
+pub struct PageMeta {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Resolved against the page's URL.
+    pub og_image: Option<String>,
+}
+
+/// Reads the title, description, and `og:image` from server-rendered HTML. This is a scan for
+/// `<title>` and `<meta>` tags rather than a parser, which is enough for the head of a page.
+pub fn extract_meta(html: &[u8], base: &Url) -> PageMeta {
+    let html = String::from_utf8_lossy(&html[..html.len().min(MAX_SCAN_BYTES)]);
+    let lower = html.to_ascii_lowercase();
+    let mut meta = PageMeta::default();
+
+    if let Some(start) = lower.find("<title") {
+        let open_end = lower[start..].find('>').map(|i| start + i + 1);
+        let close = open_end.and_then(|from| lower[from..].find("</title").map(|i| (from, from + i)));
+        if let Some((from, to)) = close {
+            meta.title = non_empty(decode_entities(html[from..to].trim()));
+        }
+    }
+
+    let mut description = None;
+    let mut og_description = None;
+    let mut from = 0;
+    while let Some(offset) = lower[from..].find("<meta") {
+        let start = from + offset;
+        let Some(end) = lower[start..].find('>').map(|i| start + i) else {
+            break;
+        };
+        let tag = &html[start + 5..end];
+        let key = attribute(tag, "property").or_else(|| attribute(tag, "name")).map(|k| k.to_ascii_lowercase());
+        let content = attribute(tag, "content").map(|c| decode_entities(c.trim()));
+        match (key.as_deref(), content) {
+            (Some("description"), Some(content)) => description = description.or(non_empty(content)),
+            (Some("og:description"), Some(content)) => og_description = og_description.or(non_empty(content)),
+            (Some("og:image" | "og:image:url"), Some(content)) if meta.og_image.is_none() => {
+                meta.og_image = base.join(&content).ok().map(String::from);
+            }
+            _ => {}
+        }
+        from = end;
+    }
+    meta.description = description.or(og_description);
+    meta
+}
+
+/// The value of a quoted or bare attribute in the inside of a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(offset) = lower[from..].find(name) {
+        let start = from + offset;
+        from = start + name.len();
+        let preceded = start == 0 || lower.as_bytes()[start - 1].is_ascii_whitespace();
+        let rest = lower[from..].trim_start();
+        if !preceded || !rest.starts_with('=') {
+            continue;
+        }
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        return match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+            Some(_) => value.split(|c: char| c.is_ascii_whitespace() || c == '/').next(),
+            None => None,
+        };
+    }
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn non_empty(text: String) -> Option<String> {
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/blog/post").unwrap()
+    }
+
+    #[test]
+    fn reads_the_title_description_and_image() {
+        let html = br#"<html><head>
+            <TITLE> Fish &amp; Chips </TITLE>
+            <meta name="description" content="Best &quot;chips&quot; in town">
+            <meta property='og:image' content='/img/cover.png'>
+            <meta property="og:image" content="https://cdn.example.com/second.png">
+            </head></html>"#;
+        let meta = extract_meta(html, &base());
+        assert_eq!(meta.title.as_deref(), Some("Fish & Chips"));
+        assert_eq!(meta.description.as_deref(), Some("Best \"chips\" in town"));
+        assert_eq!(meta.og_image.as_deref(), Some("https://example.com/img/cover.png"));
+    }
+
+    #[test]
+    fn falls_back_to_the_og_description() {
+        let html = br#"<meta property="og:description" content="From Open Graph"><meta name=description content="">"#;
+        let meta = extract_meta(html, &base());
+        assert_eq!(meta.title, None);
+        assert_eq!(meta.description.as_deref(), Some("From Open Graph"));
+        assert_eq!(meta.og_image, None);
+    }
+
+    #[test]
+    fn ignores_attribute_names_inside_other_attributes() {
+        let html = br#"<meta data-name="og:image" property="og:title" content="x"><meta name=og:image content=cover.jpg />"#;
+        let meta = extract_meta(html, &base());
+        assert_eq!(meta.og_image.as_deref(), Some("https://example.com/blog/cover.jpg"));
+    }
+}
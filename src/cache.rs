@@ -1,28 +1,817 @@
-use sled::Db;
-use std::path::Path;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::transaction::{ConflictableTransactionError, TransactionError, TransactionalTree};
+use sled::{Db, Transactional, Tree};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::config::{CacheConfig, FlushStrategy};
+use crate::thumbnail::Provenance;
+
+/// Bump whenever the encoder or page preparation changes so stale renders are never served.
+pub const CACHE_VERSION: u32 = 13;
+
+/// Identifies the first line of an export file; bump `EXPORT_FORMAT_VERSION` on incompatible changes.
+const EXPORT_FORMAT: &str = "tin-cache-export";
+const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedData {
+    pub image_data: Vec<u8>,
+    /// The page the entry was rendered from.
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Where the page ended up after redirects.
+    pub final_url: Option<String>,
+    /// Lossy quality chosen to fit a `max_bytes` budget.
+    pub quality: Option<u8>,
+    /// Hex SHA-256 of the page's HTML when rendered, for conditional refreshes.
+    pub content_hash: Option<String>,
+    /// The page showed a paywall or login wall.
+    pub paywalled: bool,
+    /// The page's primary language as an ISO 639-1 code.
+    pub language: Option<String>,
+    /// The start of the page's main content as plain text.
+    pub excerpt: Option<String>,
+    /// How the page was rendered; absent for entries imported from older exports.
+    pub provenance: Option<Provenance>,
+    /// Unix timestamp (seconds) of when the entry was rendered.
+    pub created_at: u64,
+}
+
+/// Everything about an entry except the image, stored apart so it can be read without the blob.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedMetadata {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub final_url: Option<String>,
+    pub quality: Option<u8>,
+    pub content_hash: Option<String>,
+    pub paywalled: bool,
+    pub language: Option<String>,
+    pub excerpt: Option<String>,
+    pub provenance: Option<Provenance>,
+    pub created_at: u64,
+    pub image_size: u64,
+    /// SHA-256 of the image, its key in the blob store.
+    pub image_hash: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportHeader {
+    format: String,
+    version: u32,
+    cache_version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportRecord {
+    namespace: String,
+    key: String,
+    #[serde(default)]
+    url: String,
+    created_at: u64,
+    title: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    final_url: Option<String>,
+    #[serde(default)]
+    quality: Option<u8>,
+    #[serde(default)]
+    content_hash: Option<String>,
+    #[serde(default)]
+    paywalled: bool,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    excerpt: Option<String>,
+    #[serde(default)]
+    provenance: Option<Provenance>,
+    /// Base64-encoded image bytes.
+    image_data: String,
+}
+
+/// One render of an entry, as listed by `Cache::history`.
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    /// Unix seconds; identifies the version.
+    pub created_at: u64,
+    /// The entry as it is now, rather than a retained earlier render.
+    pub current: bool,
+    pub size: u64,
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// An entry as listed by `Cache::list`.
+#[derive(Debug, Serialize)]
+pub struct EntryInfo {
+    pub namespace: String,
+    pub key: String,
+    pub url: String,
+    pub size: u64,
+    pub created_at: u64,
+    /// Unix seconds of the last cache hit; `None` if never served from cache.
+    pub last_accessed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
 
 pub struct Cache {
     db: Db,
+    metadata: Tree,
+    /// Encoded images keyed by SHA-256, shared by every entry with identical bytes.
+    blobs: Tree,
+    /// Number of entries pointing at each blob, as big-endian u64.
+    blob_refs: Tree,
+    /// Last cache hit per entry, as big-endian Unix seconds.
+    access: Tree,
+    /// Renders an entry replaced, keyed by the entry's key, a zero byte, and the render's
+    /// big-endian creation time. Each holds a reference to its blob like an entry does.
+    history: Tree,
+    history_versions: usize,
+    flush: FlushStrategy,
+    drop_stale_trees: bool,
+    /// Names of the trees opened by this process; any others are stale.
+    opened: Mutex<HashSet<Vec<u8>>>,
+    /// Bytes on disk as of the last maintenance run.
+    size_on_disk: AtomicU64,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn version_prefix() -> String {
+    format!("v{}:", CACHE_VERSION)
 }
 
 impl Cache {
-    pub fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
-        let db = sled::open(path)?;
-        Ok(Self { db })
+    pub fn new(config: &CacheConfig) -> anyhow::Result<Self> {
+        // Only the `interval` strategy leaves flushing to sled's own background thread.
+        let flush_every_ms = match config.flush {
+            FlushStrategy::Interval => Some(config.flush_interval_ms.max(1)),
+            FlushStrategy::PerWrite | FlushStrategy::OnShutdown => None,
+        };
+        let db = sled::Config::new().path(&config.path).flush_every_ms(flush_every_ms).open()?;
+        let metadata = db.open_tree("metadata")?;
+        let blobs = db.open_tree("blobs")?;
+        let blob_refs = db.open_tree("blob_refs")?;
+        let access = db.open_tree("access")?;
+        let history = db.open_tree("history")?;
+        let size_on_disk = AtomicU64::new(db.size_on_disk()?);
+        let opened = [&metadata, &blobs, &blob_refs, &access, &history].iter().map(|tree| tree.name().to_vec()).collect();
+        Ok(Self {
+            db,
+            metadata,
+            blobs,
+            blob_refs,
+            access,
+            history,
+            history_versions: config.history_versions,
+            flush: config.flush,
+            drop_stale_trees: config.drop_stale_trees,
+            opened: Mutex::new(opened),
+            size_on_disk,
+        })
     }
 
-    pub async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
-        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    /// Opens another tree in the same store, for data that should live alongside the cache.
+    pub fn open_tree(&self, name: &str) -> anyhow::Result<Tree> {
+        self.opened.lock().unwrap_or_else(|e| e.into_inner()).insert(name.as_bytes().to_vec());
+        Ok(self.db.open_tree(name)?)
     }
 
-    pub async fn put(&self, key: &str, value: &[u8]) -> anyhow::Result<()> {
-        self.db.insert(key, value)?;
-        self.db.flush_async().await?;
+    /// A random secret kept in the store, generated on first use, so it stays the same across
+    /// restarts without being configured.
+    pub fn secret(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        let secrets = self.open_tree("secrets")?;
+        if let Some(secret) = secrets.get(name)? {
+            return Ok(secret.to_vec());
+        }
+        let mut secret = [0u8; 32];
+        ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut secret)
+            .map_err(|_| anyhow::anyhow!("Failed to generate the {} secret", name))?;
+        // Another caller may have stored one first; theirs wins.
+        match secrets.compare_and_swap(name, None as Option<&[u8]>, Some(&secret[..]))? {
+            Ok(()) => Ok(secret.to_vec()),
+            Err(existing) => Ok(existing.current.map(|s| s.to_vec()).unwrap_or_else(|| secret.to_vec())),
+        }
+    }
+
+    /// Bytes the store occupied on disk at the last measurement.
+    pub fn size_on_disk(&self) -> u64 {
+        self.size_on_disk.load(Ordering::Relaxed)
+    }
+
+    /// Makes all writes so far durable.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let flushed = self.db.flush_async().await?;
+        info!("Flushed {} bytes of cache writes", flushed);
         Ok(())
     }
 
-    pub async fn clear(&self) -> anyhow::Result<()> {
-        self.db.clear()?;
+    /// Measures the store and, if enabled, drops stale trees every `every`.
+    pub async fn run_maintenance(self: Arc<Self>, every: Duration) {
+        let mut interval = tokio::time::interval(every);
+        // The first tick is immediate and the size was just measured on open.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.maintain() {
+                error!("Cache maintenance failed: {}", e);
+            }
+        }
+    }
+
+    fn maintain(&self) -> anyhow::Result<()> {
+        if self.drop_stale_trees {
+            let opened = self.opened.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            let default = self.db.name();
+            for name in self.db.tree_names() {
+                if name == default || opened.contains(name.as_ref()) {
+                    continue;
+                }
+                self.db.drop_tree(&name)?;
+                info!("Dropped stale cache tree {}", String::from_utf8_lossy(&name));
+            }
+        }
+        let size = self.db.size_on_disk()?;
+        self.size_on_disk.store(size, Ordering::Relaxed);
+        info!("Cache occupies {} bytes on disk", size);
+        Ok(())
+    }
+
+    /// Entries are keyed by cache version and namespace, so tenants sharing the store never collide.
+    fn key(namespace: &str, key: &str) -> String {
+        format!("{}{}:{}", version_prefix(), namespace, key)
+    }
+
+    pub async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<CachedData>> {
+        let key = Self::key(namespace, key);
+        let Some(metadata) = self.read_metadata(&key)? else {
+            return Ok(None);
+        };
+        let Some(image) = self.blobs.get(metadata.image_hash)? else {
+            return Ok(None);
+        };
+        // Only listings report it, so the entry is still served.
+        if let Err(e) = self.access.insert(&key, &now_secs().to_be_bytes()) {
+            warn!("Failed to record access time for {}: {}", key, e);
+        }
+        Ok(Some(with_image(metadata, image.to_vec())))
+    }
+
+    /// The current render of an entry and the earlier ones kept for it, newest first.
+    pub fn history(&self, namespace: &str, key: &str) -> anyhow::Result<Vec<VersionInfo>> {
+        let key = Self::key(namespace, key);
+        let version = |metadata: CachedMetadata, current| VersionInfo {
+            created_at: metadata.created_at,
+            current,
+            size: metadata.image_size,
+            title: metadata.title,
+            provenance: metadata.provenance,
+        };
+        let mut versions: Vec<VersionInfo> = self.read_metadata(&key)?.map(|m| version(m, true)).into_iter().collect();
+        for value in self.history.scan_prefix(history_prefix(&key)).values().rev() {
+            let metadata: CachedMetadata = bincode::deserialize(&value?)
+                .map_err(|e| anyhow::anyhow!("Cache history deserialization failed: {}", e))?;
+            versions.push(version(metadata, false));
+        }
+        Ok(versions)
+    }
+
+    /// The render of an entry made at `created_at`, whether current or kept in its history.
+    pub async fn get_version(&self, namespace: &str, key: &str, created_at: u64) -> anyhow::Result<Option<CachedData>> {
+        let key = Self::key(namespace, key);
+        let metadata = match self.read_metadata(&key)?.filter(|m| m.created_at == created_at) {
+            Some(current) => current,
+            None => match self.history.get(history_key(&key, created_at))? {
+                Some(bytes) => bincode::deserialize(&bytes)
+                    .map_err(|e| anyhow::anyhow!("Cache history deserialization failed: {}", e))?,
+                None => return Ok(None),
+            },
+        };
+        let Some(image) = self.blobs.get(metadata.image_hash)? else {
+            return Ok(None);
+        };
+        Ok(Some(with_image(metadata, image.to_vec())))
+    }
+
+    pub async fn put(&self, namespace: &str, key: &str, value: &CachedData) -> anyhow::Result<()> {
+        self.write_entry(&Self::key(namespace, key), value)?;
+        if self.flush == FlushStrategy::PerWrite {
+            self.db.flush_async().await?;
+        }
+        Ok(())
+    }
+
+    fn read_metadata(&self, full_key: &str) -> anyhow::Result<Option<CachedMetadata>> {
+        match self.metadata.get(full_key)? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)
+                .map_err(|e| anyhow::anyhow!("Cache metadata deserialization failed: {}", e))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Points the entry at the blob for its image, storing the blob if it's new and releasing
+    /// the one the entry pointed at before, or moving the replaced render into the entry's
+    /// history when history is kept.
+    fn write_entry(&self, full_key: &str, value: &CachedData) -> anyhow::Result<()> {
+        let image_hash: [u8; 32] = Sha256::digest(&value.image_data).into();
+        let metadata = CachedMetadata {
+            url: value.url.clone(),
+            title: value.title.clone(),
+            description: value.description.clone(),
+            final_url: value.final_url.clone(),
+            quality: value.quality,
+            content_hash: value.content_hash.clone(),
+            paywalled: value.paywalled,
+            language: value.language.clone(),
+            excerpt: value.excerpt.clone(),
+            provenance: value.provenance.clone(),
+            created_at: value.created_at,
+            image_size: value.image_data.len() as u64,
+            image_hash,
+        };
+        let bytes = bincode::serialize(&metadata)
+            .map_err(|e| anyhow::anyhow!("Cache serialization failed: {}", e))?;
+
+        let keep_history = self.history_versions > 0;
+        let trees = (&self.metadata, &self.blobs, &self.blob_refs, &self.history);
+        let written = trees.transaction(|(metadata, blobs, refs, history)| {
+            let previous = metadata.insert(full_key, bytes.as_slice())?.and_then(|old| {
+                bincode::deserialize::<CachedMetadata>(&old).ok().map(|metadata| (metadata, old))
+            });
+            let release = match previous {
+                // The replaced render keeps its reference from the history.
+                Some((old, old_bytes)) if keep_history && old.created_at != value.created_at => {
+                    history
+                        .insert(history_key(full_key, old.created_at), old_bytes)?
+                        .and_then(|replaced| bincode::deserialize::<CachedMetadata>(&replaced).ok())
+                        .map(|replaced| replaced.image_hash)
+                }
+                Some((old, _)) if old.image_hash == image_hash => return Ok(()),
+                Some((old, _)) => Some(old.image_hash),
+                None => None,
+            };
+            if add_ref(refs, &image_hash, 1)? == 1 {
+                blobs.insert(&image_hash, value.image_data.as_slice())?;
+            }
+            if let Some(previous) = release {
+                release_ref(blobs, refs, &previous)?;
+            }
+            Ok::<_, ConflictableTransactionError<()>>(())
+        });
+        match written {
+            Ok(()) => {}
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+            Err(TransactionError::Abort(())) => anyhow::bail!("Cache write aborted"),
+        }
+        self.access.remove(full_key)?;
+        self.trim_history(full_key, self.history_versions)?;
+        Ok(())
+    }
+
+    /// Deletes an entry along with its history, returning whether it existed.
+    pub async fn remove(&self, namespace: &str, key: &str) -> anyhow::Result<bool> {
+        let full_key = Self::key(namespace, key);
+        let removed = (&self.metadata, &self.blobs, &self.blob_refs).transaction(|(metadata, blobs, refs)| {
+            let removed = metadata
+                .remove(full_key.as_bytes())?
+                .and_then(|old| bincode::deserialize::<CachedMetadata>(&old).ok());
+            if let Some(removed) = &removed {
+                release_ref(blobs, refs, &removed.image_hash)?;
+            }
+            Ok::<_, ConflictableTransactionError<()>>(removed.is_some())
+        });
+        let removed = match removed {
+            Ok(removed) => removed,
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+            Err(TransactionError::Abort(())) => anyhow::bail!("Cache removal aborted"),
+        };
+        self.access.remove(&full_key)?;
+        self.trim_history(&full_key, 0)?;
+        Ok(removed)
+    }
+
+    /// Drops the oldest renders in an entry's history beyond `keep`.
+    fn trim_history(&self, full_key: &str, keep: usize) -> anyhow::Result<()> {
+        let keys = self.history.scan_prefix(history_prefix(full_key)).keys().collect::<Result<Vec<_>, _>>()?;
+        for key in keys.iter().take(keys.len().saturating_sub(keep)) {
+            let trimmed = (&self.history, &self.blobs, &self.blob_refs).transaction(|(history, blobs, refs)| {
+                let removed = history.remove(key)?.and_then(|old| bincode::deserialize::<CachedMetadata>(&old).ok());
+                if let Some(removed) = removed {
+                    release_ref(blobs, refs, &removed.image_hash)?;
+                }
+                Ok::<_, ConflictableTransactionError<()>>(())
+            });
+            match trimmed {
+                Ok(()) => {}
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+                Err(TransactionError::Abort(())) => anyhow::bail!("Cache history trim aborted"),
+            }
+        }
         Ok(())
     }
+
+    /// Deletes blobs no current entry points at, e.g. after older versions were purged, and
+    /// rebuilds the reference counts of the rest.
+    fn collect_blobs(&self) -> anyhow::Result<usize> {
+        let mut counts: HashMap<[u8; 32], u64> = HashMap::new();
+        let entries = self.metadata.scan_prefix(version_prefix()).values();
+        let history = self.history.scan_prefix(version_prefix()).values();
+        for value in entries.chain(history) {
+            if let Ok(metadata) = bincode::deserialize::<CachedMetadata>(&value?) {
+                *counts.entry(metadata.image_hash).or_default() += 1;
+            }
+        }
+        let mut removed = 0;
+        for hash in self.blobs.iter().keys() {
+            let hash = hash?;
+            match <[u8; 32]>::try_from(hash.as_ref()).ok().and_then(|h| counts.get(&h)) {
+                Some(count) => {
+                    self.blob_refs.insert(&hash, &count.to_be_bytes())?;
+                }
+                None => {
+                    self.blobs.remove(&hash)?;
+                    self.blob_refs.remove(&hash)?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Lists current-version entries whose `namespace:key` starts with `prefix`, in key order,
+    /// resuming after the `namespace:key` given as `after`. Also returns the key to resume from
+    /// when more entries remain.
+    pub fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> anyhow::Result<(Vec<EntryInfo>, Option<String>)> {
+        let version = version_prefix();
+        let start = format!("{}{}", version, prefix);
+        let after = after.map(|after| format!("{}{}", version, after)).filter(|after| *after >= start);
+        let lower = match &after {
+            Some(after) => Bound::Excluded(after.as_bytes()),
+            None => Bound::Included(start.as_bytes()),
+        };
+        let range = self.metadata.range::<&[u8], _>((lower, Bound::Unbounded));
+
+        let mut entries = Vec::new();
+        for item in range {
+            let (full_key, value) = item?;
+            if !full_key.starts_with(start.as_bytes()) {
+                break;
+            }
+            if entries.len() == limit {
+                // Another entry matches, so there's a further page.
+                let cursor = entries.last().map(|last: &EntryInfo| format!("{}:{}", last.namespace, last.key));
+                return Ok((entries, cursor));
+            }
+            let name = String::from_utf8_lossy(&full_key[version.len()..]).into_owned();
+            let Some((namespace, key)) = name.split_once(':') else {
+                continue;
+            };
+            let metadata: CachedMetadata = bincode::deserialize(&value)
+                .map_err(|e| anyhow::anyhow!("Cache metadata deserialization failed for {}: {}", name, e))?;
+            let last_accessed = self
+                .access
+                .get(&full_key)?
+                .and_then(|bytes| <[u8; 8]>::try_from(bytes.as_ref()).ok())
+                .map(u64::from_be_bytes);
+            entries.push(EntryInfo {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                url: metadata.url,
+                size: metadata.image_size,
+                created_at: metadata.created_at,
+                last_accessed,
+                provenance: metadata.provenance,
+            });
+        }
+        Ok((entries, None))
+    }
+
+    /// Removes every entry not written under the current `CACHE_VERSION`, across all namespaces.
+    pub async fn purge_old_versions(&self) -> anyhow::Result<usize> {
+        // Entries from before the metadata/image split live in the default tree.
+        let mut removed = self.db.len();
+        self.db.clear()?;
+        removed += purge_tree(&self.metadata)?;
+        purge_tree(&self.access)?;
+        purge_tree(&self.history)?;
+        let blobs = self.collect_blobs()?;
+        self.db.flush_async().await?;
+        info!("Purged {} cache entries and {} images from older versions", removed, blobs);
+        Ok(removed)
+    }
+
+    /// Writes all current-version entries, in every namespace, as JSON lines preceded by a header.
+    pub fn export<W: Write>(&self, mut out: W) -> anyhow::Result<usize> {
+        let header = ExportHeader {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_FORMAT_VERSION,
+            cache_version: CACHE_VERSION,
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+
+        let mut count = 0;
+        for item in self.metadata.scan_prefix(version_prefix()) {
+            let (full_key, value) = item?;
+            let key = String::from_utf8_lossy(&full_key[version_prefix().len()..]).into_owned();
+            let Some((namespace, key)) = key.split_once(':') else {
+                continue;
+            };
+            let metadata: CachedMetadata = bincode::deserialize(&value)
+                .map_err(|e| anyhow::anyhow!("Cache deserialization failed for {}: {}", key, e))?;
+            let Some(image) = self.blobs.get(metadata.image_hash)? else {
+                continue;
+            };
+
+            let record = ExportRecord {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                url: metadata.url,
+                created_at: metadata.created_at,
+                title: metadata.title,
+                description: metadata.description,
+                final_url: metadata.final_url,
+                quality: metadata.quality,
+                content_hash: metadata.content_hash,
+                paywalled: metadata.paywalled,
+                language: metadata.language,
+                excerpt: metadata.excerpt,
+                provenance: metadata.provenance,
+                image_data: general_purpose::STANDARD.encode(&image),
+            };
+            serde_json::to_writer(&mut out, &record)?;
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+        out.flush()?;
+
+        info!("Exported {} cache entries", count);
+        Ok(count)
+    }
+
+    /// Reads an export produced by `export`, inserting entries under their original namespaces.
+    /// Entries rendered under another cache version are skipped, since they'd be served as
+    /// current renders.
+    pub fn import<R: BufRead>(&self, input: R) -> anyhow::Result<ImportReport> {
+        let mut lines = input.lines();
+        let header: ExportHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => anyhow::bail!("Import file is empty"),
+        };
+        if header.format != EXPORT_FORMAT || header.version != EXPORT_FORMAT_VERSION {
+            anyhow::bail!("Unsupported export format: {} v{}", header.format, header.version);
+        }
+        let stale = header.cache_version != CACHE_VERSION;
+        if stale {
+            warn!("Skipping entries rendered under cache version {}; this is version {}", header.cache_version, CACHE_VERSION);
+        }
+
+        let mut report = ImportReport::default();
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if stale {
+                report.skipped += 1;
+                continue;
+            }
+            let record: ExportRecord = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("Invalid record on line {}: {}", index + 2, e))?;
+            let data = CachedData {
+                image_data: general_purpose::STANDARD.decode(&record.image_data)
+                    .map_err(|e| anyhow::anyhow!("Invalid image data on line {}: {}", index + 2, e))?,
+                url: record.url,
+                title: record.title,
+                description: record.description,
+                final_url: record.final_url,
+                quality: record.quality,
+                content_hash: record.content_hash,
+                paywalled: record.paywalled,
+                language: record.language,
+                excerpt: record.excerpt,
+                provenance: record.provenance,
+                created_at: record.created_at,
+            };
+            self.write_entry(&Self::key(&record.namespace, &record.key), &data)?;
+            report.imported += 1;
+        }
+        self.db.flush()?;
+
+        info!("Imported {} cache entries and skipped {}", report.imported, report.skipped);
+        Ok(report)
+    }
+}
+
+/// What `import` did with an export's entries.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Entries from another cache version.
+    pub skipped: usize,
+}
+
+fn with_image(metadata: CachedMetadata, image_data: Vec<u8>) -> CachedData {
+    CachedData {
+        image_data,
+        url: metadata.url,
+        title: metadata.title,
+        description: metadata.description,
+        final_url: metadata.final_url,
+        quality: metadata.quality,
+        content_hash: metadata.content_hash,
+        paywalled: metadata.paywalled,
+        language: metadata.language,
+        excerpt: metadata.excerpt,
+        provenance: metadata.provenance,
+        created_at: metadata.created_at,
+    }
+}
+
+fn history_prefix(full_key: &str) -> Vec<u8> {
+    let mut prefix = full_key.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+fn history_key(full_key: &str, created_at: u64) -> Vec<u8> {
+    let mut key = history_prefix(full_key);
+    key.extend_from_slice(&created_at.to_be_bytes());
+    key
+}
+
+/// Drops a reference to a blob, deleting the blob with its last reference.
+fn release_ref(blobs: &TransactionalTree, refs: &TransactionalTree, hash: &[u8; 32]) -> Result<(), ConflictableTransactionError<()>> {
+    if add_ref(refs, hash, -1)? == 0 {
+        blobs.remove(hash)?;
+    }
+    Ok(())
+}
+
+/// Adjusts a blob's reference count by `delta`, returning the new count; a count of zero is removed.
+fn add_ref(refs: &TransactionalTree, hash: &[u8; 32], delta: i64) -> Result<u64, ConflictableTransactionError<()>> {
+    let current = refs
+        .get(hash)?
+        .and_then(|bytes| <[u8; 8]>::try_from(bytes.as_ref()).ok())
+        .map_or(0, u64::from_be_bytes);
+    let count = current.saturating_add_signed(delta);
+    if count == 0 {
+        refs.remove(hash)?;
+    } else {
+        refs.insert(hash, &count.to_be_bytes())?;
+    }
+    Ok(count)
+}
+
+fn purge_tree(tree: &Tree) -> anyhow::Result<usize> {
+    let current = version_prefix();
+    let mut removed = 0;
+    for key in tree.iter().keys() {
+        let key = key?;
+        if !key.starts_with(current.as_bytes()) {
+            tree.remove(&key)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A cache in its own temporary directory, deleted when dropped.
+    struct TestCache {
+        cache: Cache,
+        path: PathBuf,
+    }
+
+    impl TestCache {
+        fn new(history_versions: usize) -> Self {
+            let path = std::env::temp_dir().join(format!("tin-cache-test-{}", uuid::Uuid::new_v4()));
+            let cache = Cache::new(&CacheConfig { path: path.clone(), history_versions, ..CacheConfig::default() }).unwrap();
+            Self { cache, path }
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn entry(created_at: u64) -> CachedData {
+        CachedData {
+            image_data: format!("image rendered at {}", created_at).into_bytes(),
+            url: "https://example.com/".to_string(),
+            title: Some(format!("Render {}", created_at)),
+            description: None,
+            final_url: None,
+            quality: None,
+            content_hash: None,
+            paywalled: false,
+            language: None,
+            excerpt: None,
+            provenance: None,
+            created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn history_keeps_only_the_newest_replaced_renders() {
+        let test = TestCache::new(2);
+        for created_at in 1..=4 {
+            test.cache.put("ns", "page", &entry(created_at)).await.unwrap();
+        }
+
+        let history = test.cache.history("ns", "page").unwrap();
+        let versions: Vec<_> = history.iter().map(|v| (v.created_at, v.current)).collect();
+        assert_eq!(versions, [(4, true), (3, false), (2, false)]);
+        assert!(test.cache.get_version("ns", "page", 1).await.unwrap().is_none());
+        let kept = test.cache.get_version("ns", "page", 3).await.unwrap().unwrap();
+        assert_eq!(kept.image_data, entry(3).image_data);
+        // The trimmed render's image is released with it.
+        assert_eq!(test.cache.blobs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn without_history_replaced_renders_are_dropped() {
+        let test = TestCache::new(0);
+        test.cache.put("ns", "page", &entry(1)).await.unwrap();
+        test.cache.put("ns", "page", &entry(2)).await.unwrap();
+        assert_eq!(test.cache.history("ns", "page").unwrap().len(), 1);
+        assert_eq!(test.cache.blobs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_the_entry_and_its_history() {
+        let test = TestCache::new(2);
+        test.cache.put("ns", "page", &entry(1)).await.unwrap();
+        test.cache.put("ns", "page", &entry(2)).await.unwrap();
+
+        assert!(test.cache.remove("ns", "page").await.unwrap());
+        assert!(test.cache.get("ns", "page").await.unwrap().is_none());
+        assert!(test.cache.history("ns", "page").unwrap().is_empty());
+        assert!(test.cache.blobs.is_empty());
+        assert!(!test.cache.remove("ns", "page").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn export_round_trips_through_import() {
+        let source = TestCache::new(0);
+        source.cache.put("a", "one", &entry(1)).await.unwrap();
+        source.cache.put("b", "two", &entry(2)).await.unwrap();
+        let mut export = Vec::new();
+        assert_eq!(source.cache.export(&mut export).unwrap(), 2);
+
+        let target = TestCache::new(0);
+        let report = target.cache.import(export.as_slice()).unwrap();
+        assert_eq!((report.imported, report.skipped), (2, 0));
+        let imported = target.cache.get("b", "two").await.unwrap().unwrap();
+        assert_eq!(imported.image_data, entry(2).image_data);
+        assert_eq!(imported.title.as_deref(), Some("Render 2"));
+        assert_eq!(imported.created_at, 2);
+    }
+
+    #[tokio::test]
+    async fn import_skips_entries_from_another_cache_version() {
+        let source = TestCache::new(0);
+        source.cache.put("a", "one", &entry(1)).await.unwrap();
+        let mut export = Vec::new();
+        source.cache.export(&mut export).unwrap();
+        let export = String::from_utf8(export).unwrap();
+        let current = format!("\"cache_version\":{}", CACHE_VERSION);
+        let stale = export.replacen(&current, &format!("\"cache_version\":{}", CACHE_VERSION - 1), 1);
+        assert_ne!(stale, export);
+
+        let target = TestCache::new(0);
+        let report = target.cache.import(stale.as_bytes()).unwrap();
+        assert_eq!((report.imported, report.skipped), (0, 1));
+        assert!(target.cache.get("a", "one").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn import_rejects_other_formats() {
+        let test = TestCache::new(0);
+        assert!(test.cache.import("".as_bytes()).is_err());
+        assert!(test.cache.import("{\"format\":\"other\",\"version\":1,\"cache_version\":1}\n".as_bytes()).is_err());
+    }
 }
@@ -0,0 +1,811 @@
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::consent::ConsentMode;
+use crate::processing::Color;
+use crate::page_pool::PagePoolSettings;
+use crate::session::SessionCookie;
+use crate::thumbnail::{PageBudget, WaitStrategy};
+
+const DEFAULT_CONFIG_PATH: &str = "tin.toml";
+/// Largest `oversample` accepted; beyond this captures get huge for little gain.
+pub const MAX_OVERSAMPLE: f32 = 4.0;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Serve HTTPS when present.
+    pub tls: Option<TlsConfig>,
+    /// Sign thumbnail responses when present.
+    pub signing: Option<SigningConfig>,
+    pub browser: BrowserConfig,
+    pub cache: CacheConfig,
+    pub links: LinksConfig,
+    pub watermark: WatermarkConfig,
+    pub badge: BadgeConfig,
+    pub placeholder: PlaceholderConfig,
+    pub limits: LimitsConfig,
+    pub processing: ProcessingConfig,
+    pub metadata: MetadataConfig,
+    pub render: RenderConfig,
+    pub robots: RobotsConfig,
+    pub politeness: PolitenessConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub queue: QueueConfig,
+    pub access: AccessConfig,
+    pub cors: CorsConfig,
+    pub compression: CompressionConfig,
+    pub urls: UrlConfig,
+    pub audit: AuditConfig,
+    pub reload: ReloadConfig,
+    pub alerts: AlertsConfig,
+    pub auth: AuthConfig,
+    /// Render overrides for problem sites; the first profile matching a host applies.
+    pub sites: Vec<SiteProfile>,
+    /// Where new thumbnails are sent once rendered.
+    pub hooks: Vec<HookConfig>,
+    /// Logged-in browser states that requests can render with by name.
+    pub sessions: Vec<SessionConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// How often to check the files for changes and reload them.
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_tls_reload_interval() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningConfig {
+    /// Ed25519 private key in PKCS#8 PEM form.
+    pub key_path: PathBuf,
+    /// Reported alongside signatures; defaults to a fingerprint of the public key.
+    pub key_id: Option<String>,
+}
+
+/// How Chrome is launched.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BrowserConfig {
+    /// DevTools endpoint of an already running browser to attach to instead of launching one,
+    /// e.g. `ws://chrome:3000` or `http://chrome:9222`. The launch settings below are then unused.
+    pub ws_url: Option<String>,
+    /// Browser binary to run; probed from common install locations when unset.
+    pub chrome_executable: Option<PathBuf>,
+    /// Passed to Chrome after the built-in flags.
+    pub extra_chrome_args: Vec<String>,
+    pub headless: HeadlessMode,
+    /// Keep Chrome's sandbox on; needs a kernel and container that permit it.
+    pub sandbox: bool,
+    /// Directories of fonts a launched Chrome may use besides the system's, through fontconfig.
+    pub font_dirs: Vec<PathBuf>,
+    /// Start even if the browser can't be, serving cached thumbnails and ones made without a
+    /// browser until a restart, rather than exiting.
+    pub degraded_mode: bool,
+    pub download: DownloadConfig,
+}
+
+/// Fetching a pinned Chromium build when no browser is installed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    pub enabled: bool,
+    /// Chrome for Testing version to install.
+    pub version: String,
+    /// Serves `{version}/{platform}/chrome-{platform}.zip`, as the Chrome for Testing bucket does.
+    pub base_url: String,
+    /// Where builds are unpacked, one directory per version.
+    pub dir: PathBuf,
+    /// Expected SHA-256 of the archive for each platform (`linux64`, `linux-arm64`, `mac-x64`,
+    /// `mac-arm64`, `win64`); archives without a matching digest aren't installed.
+    pub sha256: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeadlessMode {
+    /// The original `--headless` implementation.
+    #[default]
+    Old,
+    /// `--headless=new`, which shares the regular browser's code paths.
+    New,
+    /// A visible window, for debugging.
+    Off,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub path: PathBuf,
+    /// Logical namespace prepended to every key, so several deployments can share one store.
+    pub namespace: String,
+    /// Delete entries written under an older cache version on startup.
+    pub purge_old_versions: bool,
+    /// When writes are made durable on disk.
+    pub flush: FlushStrategy,
+    /// Time between background flushes with the `interval` strategy.
+    pub flush_interval_ms: u64,
+    /// How often the store's size is measured and stale trees are cleaned up.
+    pub maintenance_interval_secs: u64,
+    /// Drop trees left behind by features this version no longer has.
+    pub drop_stale_trees: bool,
+    /// Earlier renders kept per entry when it's replaced (0 = none).
+    pub history_versions: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlushStrategy {
+    /// Flush after every cache write; safest, but slows down writes.
+    PerWrite,
+    /// Flush in the background every `flush_interval_ms`.
+    #[default]
+    Interval,
+    /// Flush only on a graceful shutdown; a crash loses everything written since startup.
+    OnShutdown,
+}
+
+/// Images served from `/images/{token}` for `response_mode=url`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// How long a returned link keeps working.
+    pub ttl_secs: u64,
+    /// Public origin prepended to `/images/{token}`, e.g. when behind a gateway; links are
+    /// relative when unset.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatermarkConfig {
+    /// PNG overlay applied when a request asks for a watermark.
+    pub path: Option<PathBuf>,
+    pub opacity: f32,
+    pub margin: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BadgeConfig {
+    /// Font size in pixels when the request doesn't set one.
+    pub size: f32,
+    pub margin: u32,
+    pub text_color: Color,
+    pub background: Color,
+}
+
+/// The image served for `fallback=placeholder` requests whose render failed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlaceholderConfig {
+    /// PNG drawn over the brand color, scaled to cover the placeholder.
+    pub template: Option<PathBuf>,
+    /// Background when the site's favicon gives no brand color.
+    pub color: Color,
+}
+
+/// Upper bounds on request parameters, so a single request can't exhaust Chrome or the encoder.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub max_url_length: usize,
+    /// Tallest page `/thumbnail/full` captures; longer pages are cut off.
+    pub max_full_page_height: u32,
+    /// Longest a `/thumbnail/full` capture may take, including time spent waiting on a slow
+    /// client to read the response.
+    pub max_full_page_secs: u64,
+}
+
+/// Metadata written into thumbnails. Anything a source image carried is always removed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetadataConfig {
+    /// Add an XMP block with the source URL, capture time, and generator version.
+    pub embed_provenance: bool,
+    /// Add an sRGB ICC profile, for tools that don't assume untagged images are sRGB.
+    pub embed_srgb_profile: bool,
+}
+
+/// Bounds on the resizing and encoding done off the async runtime.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProcessingConfig {
+    /// Images processed at once; 0 uses one per CPU.
+    pub max_parallel: usize,
+    /// Estimated memory all images in progress may use together; larger images wait for room.
+    pub max_memory_mb: u32,
+    /// Source images declaring more pixels are rejected before decoding.
+    pub max_source_pixels: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// Pages rendered at once.
+    pub max_concurrent_renders: usize,
+    /// Overall limit on a render, including queueing for a slot.
+    pub request_timeout_secs: u64,
+    /// Blank pages kept open for reuse (0 = open a fresh page per render).
+    pub page_pool_size: usize,
+    /// Renders after which a pooled page is closed and replaced.
+    pub page_max_uses: u32,
+    pub page_max_age_secs: u64,
+    /// Abort when the target page downloads more than this many bytes (0 = unlimited).
+    pub max_page_bytes: u64,
+    /// Abort when the target page issues more than this many requests (0 = unlimited).
+    pub max_page_requests: u64,
+    /// Hard limit on navigation plus settling before the screenshot.
+    pub navigation_budget_secs: u64,
+    /// Abort pages whose main thread spends more than this much CPU time loading (0 = unlimited).
+    pub max_page_cpu_ms: u64,
+    /// Main-frame redirects followed before a render fails; requests may lower it.
+    pub max_redirects: u32,
+    /// Follow redirects to other origins; requests may turn it off but not on.
+    pub allow_cross_origin_redirects: bool,
+    /// Render pages with invalid TLS certificates for every caller, e.g. self-signed staging hosts.
+    pub ignore_certificate_errors: bool,
+    /// What to do about cookie consent dialogs; requests and site profiles may override it.
+    pub consent: ConsentMode,
+    /// Render a built-in test page at startup and report not ready until it succeeds.
+    pub self_test: bool,
+    /// Device pixels captured per CSS pixel before downscaling to the requested size, for
+    /// sharper text in small thumbnails; requests may override it (1 = off).
+    pub oversample: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RobotsConfig {
+    /// Fetch and honor robots.txt for target hosts before rendering.
+    pub enabled: bool,
+    /// Product token matched against robots.txt `User-agent` lines and sent when fetching it.
+    pub user_agent: String,
+    pub cache_ttl_secs: u64,
+}
+
+/// Outbound limits per target host (0 = unlimited).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PolitenessConfig {
+    pub max_concurrent_per_host: usize,
+    pub max_requests_per_minute_per_host: usize,
+}
+
+/// Fast-fails hosts that keep failing to render.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open a host's circuit (0 = never).
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+}
+
+/// Distributes renders to worker processes over a Redis stream.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// When set, the server enqueues renders for workers instead of launching Chrome itself.
+    pub redis_url: Option<String>,
+    pub stream: String,
+    /// Consumer group shared by all workers.
+    pub group: String,
+}
+
+/// Which target hosts may be rendered at all, regardless of tenant.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Domains that may be rendered, including subdomains; empty allows any.
+    pub allowed_domains: Vec<String>,
+    /// Domains that may never be rendered, including subdomains, even if allowed above.
+    pub blocked_domains: Vec<String>,
+}
+
+/// Which browser origins may call the API.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// `https://app.example.com`, `https://*.example.com` for its subdomains, or `*` for any;
+    /// empty sends no CORS headers.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response.
+    pub max_age_secs: u64,
+}
+
+/// Compressing responses for clients that accept it. Images are never compressed again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Negotiate gzip, Brotli, or zstd through `Accept-Encoding`.
+    pub enabled: bool,
+    /// Smaller responses are sent as they are.
+    pub min_bytes: u16,
+}
+
+/// Picking up changes to `[access]`, `[[sites]]`, and `[auth]` without a restart.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// How often the config file's modification time is checked; 0 turns watching off, leaving
+    /// `SIGHUP` and `POST /admin/reload`.
+    pub interval_secs: u64,
+}
+
+/// Notifications for problems that otherwise only show up as missing thumbnails. Nothing is
+/// sent without sinks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertsConfig {
+    pub sinks: Vec<AlertSinkConfig>,
+    /// Identifies this node in alerts; defaults to the host name.
+    pub instance: Option<String>,
+    /// Alert when more than this share of thumbnail requests fail to render (0 = never).
+    pub error_rate_threshold: f64,
+    /// The period the error rate is measured over.
+    pub error_rate_window_secs: u64,
+    /// Requests needed in the window before the error rate is judged.
+    pub error_rate_min_requests: u64,
+    /// Alert when the cache's filesystem is fuller than this share (0 = never).
+    pub disk_usage_threshold: f64,
+    /// How often the error rate and disk usage are checked.
+    pub check_interval_secs: u64,
+    /// Least time between repeats of an alert that is still firing.
+    pub repeat_after_secs: u64,
+}
+
+/// Where alerts are sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AlertSinkConfig {
+    /// POST each alert as JSON.
+    Webhook { url: String },
+    /// POST a `text` message to a Slack-compatible incoming webhook.
+    Slack { url: String },
+}
+
+/// How request URLs are rewritten before cache keying and navigation, so equivalent URLs share
+/// an entry. Hosts are always converted to punycode and lowercased and default ports dropped.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UrlConfig {
+    /// Drop `#fragment`s; turn off for sites that route on the fragment.
+    pub strip_fragment: bool,
+    /// Drop the query parameters in `tracking_params`.
+    pub strip_tracking_params: bool,
+    /// Parameter names, or prefixes ending in `*`, removed by `strip_tracking_params`.
+    pub tracking_params: Vec<String>,
+}
+
+/// The log of who requested which pages, kept in the cache store.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Record every page request; entries already recorded stay queryable when turned off.
+    pub enabled: bool,
+    /// Entries older than this are removed (0 = kept forever).
+    pub retention_days: u64,
+}
+
+/// How pages on matching hosts are rendered, for sites that always need special handling.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteProfile {
+    /// `example.com` matches the domain and its subdomains; `*.example.com` only subdomains.
+    pub hosts: Vec<String>,
+    pub wait: WaitStrategy,
+    /// Wait up to 10 seconds for an element matching this selector before capture.
+    pub wait_for_selector: Option<String>,
+    /// Extra time to wait once the page has settled.
+    pub delay_ms: u64,
+    /// CSS selectors of elements to hide, such as newsletter modals.
+    pub hide_selectors: Vec<String>,
+    /// Viewport to render at; the capture is then scaled to the requested size.
+    pub viewport_width: Option<u32>,
+    pub viewport_height: Option<u32>,
+    /// Emulate `prefers-color-scheme: dark`.
+    pub dark_mode: bool,
+    /// Overrides `render.consent`.
+    pub consent: Option<ConsentMode>,
+}
+
+/// A named login state applied to the page before navigation, for pages behind a login.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionConfig {
+    pub name: String,
+    /// A Playwright `storageState` JSON file with cookies and `localStorage`, read on each use.
+    pub storage_state: Option<PathBuf>,
+    /// Cookies added to those in `storage_state`.
+    #[serde(default)]
+    pub cookies: Vec<SessionCookie>,
+    /// Tenants allowed to use the profile; `*` allows any caller, and none are allowed when empty.
+    #[serde(default)]
+    pub tenants: Vec<String>,
+}
+
+/// An output hook, run for each freshly rendered thumbnail.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HookConfig {
+    /// POST the thumbnail's details as JSON.
+    Webhook {
+        url: String,
+        /// Add the image, base64-encoded, as `image_data`.
+        #[serde(default)]
+        include_image: bool,
+        /// Sign bodies with HMAC-SHA256 in `X-Tin-Signature`.
+        #[serde(default)]
+        secret: Option<String>,
+    },
+    /// Write the image and its details under a local directory.
+    Directory { path: PathBuf },
+    /// Add the thumbnail's details to a Redis stream.
+    Redis {
+        url: String,
+        #[serde(default = "default_hook_stream")]
+        stream: String,
+    },
+}
+
+fn default_hook_stream() -> String {
+    "tin:thumbnails".to_string()
+}
+
+/// API keys and tenants. With no tenants configured the API is open to anyone.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Key accepted by the `/admin` endpoints; they are disabled when unset.
+    pub admin_key: Option<String>,
+    pub tenants: Vec<TenantConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    pub name: String,
+    pub api_keys: Vec<String>,
+    /// Cache namespace for this tenant's renders; defaults to the tenant name.
+    #[serde(default)]
+    pub cache_namespace: Option<String>,
+    /// Requests accepted per minute across all of the tenant's keys (0 = unlimited).
+    #[serde(default)]
+    pub requests_per_minute: usize,
+    /// Domains the tenant may render, including subdomains; empty allows any.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Overrides `robots.enabled` for this tenant.
+    #[serde(default)]
+    pub respect_robots: Option<bool>,
+    /// Lets this tenant's requests set `ignore_tls_errors`.
+    #[serde(default)]
+    pub allow_insecure_tls: bool,
+}
+
+impl Default for UrlConfig {
+    fn default() -> Self {
+        Self {
+            strip_fragment: true,
+            strip_tracking_params: false,
+            tracking_params: ["utm_*", "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "_hsenc", "_hsmi"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::from([127, 0, 0, 1]),
+            port: 9142,
+            tls: None,
+            signing: None,
+            browser: BrowserConfig::default(),
+            cache: CacheConfig::default(),
+            links: LinksConfig::default(),
+            watermark: WatermarkConfig::default(),
+            badge: BadgeConfig::default(),
+            placeholder: PlaceholderConfig::default(),
+            processing: ProcessingConfig::default(),
+            metadata: MetadataConfig::default(),
+            limits: LimitsConfig::default(),
+            render: RenderConfig::default(),
+            robots: RobotsConfig::default(),
+            politeness: PolitenessConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            queue: QueueConfig::default(),
+            access: AccessConfig::default(),
+            cors: CorsConfig::default(),
+            compression: CompressionConfig::default(),
+            urls: UrlConfig::default(),
+            audit: AuditConfig::default(),
+            reload: ReloadConfig::default(),
+            alerts: AlertsConfig::default(),
+            auth: AuthConfig::default(),
+            sites: Vec::new(),
+            hooks: Vec::new(),
+            sessions: Vec::new(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["content-type".to_string(), "authorization".to_string(), "x-api-key".to_string()],
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { enabled: true, min_bytes: 1024 }
+    }
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { interval_secs: 5 }
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            sinks: Vec::new(),
+            instance: None,
+            error_rate_threshold: 0.5,
+            error_rate_window_secs: 300,
+            error_rate_min_requests: 20,
+            disk_usage_threshold: 0.9,
+            check_interval_secs: 30,
+            repeat_after_secs: 3600,
+        }
+    }
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            ws_url: None,
+            chrome_executable: None,
+            extra_chrome_args: Vec::new(),
+            headless: HeadlessMode::Old,
+            sandbox: false,
+            font_dirs: Vec::new(),
+            degraded_mode: true,
+            download: DownloadConfig::default(),
+        }
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            version: "131.0.6778.85".to_string(),
+            base_url: "https://storage.googleapis.com/chrome-for-testing-public".to_string(),
+            dir: PathBuf::from(".chromium"),
+            sha256: HashMap::new(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(".thumbnail_cache"),
+            namespace: "default".to_string(),
+            purge_old_versions: false,
+            flush: FlushStrategy::Interval,
+            flush_interval_ms: 1000,
+            maintenance_interval_secs: 300,
+            drop_stale_trees: true,
+            history_versions: 0,
+        }
+    }
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 300,
+            base_url: None,
+        }
+    }
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            opacity: 0.8,
+            margin: 16,
+        }
+    }
+}
+
+impl Default for BadgeConfig {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            margin: 12,
+            text_color: Color(Rgba([255, 255, 255, 255])),
+            background: Color(Rgba([0, 0, 0, 180])),
+        }
+    }
+}
+
+impl Default for PlaceholderConfig {
+    fn default() -> Self {
+        Self {
+            template: None,
+            color: Color(Rgba([71, 85, 105, 255])),
+        }
+    }
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            max_parallel: 0,
+            max_memory_mb: 1024,
+            max_source_pixels: 50_000_000,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 4096,
+            max_height: 4096,
+            max_pixels: 4096 * 2304,
+            max_url_length: 2048,
+            max_full_page_height: 30_000,
+            max_full_page_secs: 120,
+        }
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_renders: 3,
+            request_timeout_secs: 45,
+            page_pool_size: 3,
+            page_max_uses: 50,
+            page_max_age_secs: 600,
+            max_page_bytes: 50 * 1024 * 1024,
+            max_page_requests: 500,
+            navigation_budget_secs: 30,
+            max_page_cpu_ms: 10_000,
+            max_redirects: 10,
+            allow_cross_origin_redirects: true,
+            ignore_certificate_errors: false,
+            consent: ConsentMode::Reject,
+            self_test: true,
+            oversample: 1.0,
+        }
+    }
+}
+
+impl Default for RobotsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_agent: "thumbnail-service".to_string(),
+            cache_ttl_secs: 3600,
+        }
+    }
+}
+
+impl Default for PolitenessConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_per_host: 2,
+            max_requests_per_minute_per_host: 30,
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_secs: 60,
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            redis_url: None,
+            stream: "tin:jobs".to_string(),
+            group: "tin-workers".to_string(),
+        }
+    }
+}
+
+impl RenderConfig {
+    pub fn page_budget(&self) -> PageBudget {
+        PageBudget {
+            max_bytes: self.max_page_bytes,
+            max_requests: self.max_page_requests,
+            navigation: Duration::from_secs(self.navigation_budget_secs),
+            max_cpu: Duration::from_millis(self.max_page_cpu_ms),
+        }
+    }
+
+    pub fn page_pool(&self) -> PagePoolSettings {
+        PagePoolSettings {
+            size: self.page_pool_size,
+            max_uses: self.page_max_uses.max(1),
+            max_age: Duration::from_secs(self.page_max_age_secs),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from `TIN_CONFIG` (or `tin.toml` if present), then applies env overrides.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match Self::path() {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+
+        if let Some(port) = std::env::var("PORT").ok().and_then(|p| p.parse().ok()) {
+            config.port = port;
+        }
+
+        Ok(config)
+    }
+
+    /// The config file in use: `TIN_CONFIG`, else `tin.toml` if it exists.
+    pub fn path() -> Option<PathBuf> {
+        match std::env::var("TIN_CONFIG").ok().map(PathBuf::from) {
+            Some(path) => Some(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(PathBuf::from(DEFAULT_CONFIG_PATH)),
+            None => None,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        info!("Loading config from {:?}", path);
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config {:?}: {}", path, e))?;
+        let config: Self = toml::from_str(&contents).map_err(|e| anyhow::anyhow!("Invalid config {:?}: {}", path, e))?;
+        config.validate().map_err(|e| anyhow::anyhow!("Invalid config {:?}: {}", path, e))?;
+        Ok(config)
+    }
+
+    /// Rejects values every request would otherwise fail on.
+    fn validate(&self) -> anyhow::Result<()> {
+        if !(1.0..=MAX_OVERSAMPLE).contains(&self.render.oversample) {
+            anyhow::bail!("render.oversample must be between 1 and {}", MAX_OVERSAMPLE);
+        }
+        Ok(())
+    }
+}
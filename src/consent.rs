@@ -0,0 +1,149 @@
+use chromiumoxide::Page;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+use tracing::{debug, warn};
+
+/// What to do about cookie consent dialogs before capture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentMode {
+    /// Click the dialog's reject button, hiding the dialog when there is none.
+    #[default]
+    Reject,
+    /// Click the dialog's accept button, hiding the dialog when there is none.
+    Accept,
+    /// Only hide dialogs.
+    Hide,
+    /// Leave dialogs alone.
+    Off,
+}
+
+impl ConsentMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConsentMode::Reject => "reject",
+            ConsentMode::Accept => "accept",
+            ConsentMode::Hide => "hide",
+            ConsentMode::Off => "off",
+        }
+    }
+}
+
+/// A consent management platform recognized by the element its dialog lives in.
+#[derive(Serialize)]
+struct Cmp {
+    name: &'static str,
+    container: &'static str,
+    accept: &'static str,
+    reject: &'static str,
+}
+
+const CMPS: &[Cmp] = &[
+    Cmp {
+        name: "OneTrust",
+        container: "#onetrust-consent-sdk",
+        accept: "#onetrust-accept-btn-handler",
+        reject: "#onetrust-reject-all-handler",
+    },
+    Cmp {
+        name: "Cookiebot",
+        container: "#CybotCookiebotDialog",
+        accept: "#CybotCookiebotDialogBodyLevelButtonLevelOptinAllowAll, #CybotCookiebotDialogBodyButtonAccept",
+        reject: "#CybotCookiebotDialogBodyButtonDecline",
+    },
+    Cmp {
+        name: "Quantcast",
+        container: ".qc-cmp2-container",
+        accept: ".qc-cmp2-summary-buttons button[mode=\"primary\"]",
+        reject: ".qc-cmp2-summary-buttons button[mode=\"secondary\"]",
+    },
+    Cmp {
+        name: "Didomi",
+        container: "#didomi-host",
+        accept: "#didomi-notice-agree-button",
+        reject: "#didomi-notice-disagree-button",
+    },
+    Cmp {
+        name: "TrustArc",
+        container: "#truste-consent-track",
+        accept: "#truste-consent-button",
+        reject: "#truste-consent-required",
+    },
+];
+
+/// Hidden when no known platform is found; broad, so only a fallback.
+const GENERIC_SELECTORS: &[&str] = &[
+    "[class*=\"cookie\"]",
+    "[class*=\"consent\"]",
+    "[id*=\"cookie\"]",
+    "[class*=\"gdpr\"]",
+];
+
+/// Clicks the configured button for a known platform's dialog, or hides it, falling back to
+/// hiding anything that looks like a consent banner. Returns what was done, for logging.
+const CONSENT_SCRIPT: &str = r#"
+    ((cmps, mode, generic) => {
+        const hide = el => el.style.setProperty('display', 'none', 'important');
+        for (const cmp of cmps) {
+            const container = document.querySelector(cmp.container);
+            if (!container) continue;
+            const button = mode === 'hide' ? null : document.querySelector(mode === 'accept' ? cmp.accept : cmp.reject);
+            if (button) {
+                button.click();
+                return 'clicked ' + mode + ' on ' + cmp.name;
+            }
+            hide(container);
+            return 'hid ' + cmp.name;
+        }
+        let hidden = 0;
+        for (const selector of generic) {
+            document.querySelectorAll(selector).forEach(el => { hide(el); hidden++; });
+        }
+        return hidden > 0 ? 'hid ' + hidden + ' generic elements' : null;
+    })
+"#;
+
+/// Hides a platform's dialog if it is still showing after its button was clicked.
+const CLEANUP_SCRIPT: &str = r#"
+    ((cmps) => {
+        for (const cmp of cmps) {
+            const container = document.querySelector(cmp.container);
+            if (container && container.offsetParent !== null) {
+                container.style.setProperty('display', 'none', 'important');
+            }
+        }
+    })
+"#;
+
+/// Deals with the page's consent dialog as `mode` says.
+pub async fn handle(page: &Page, url: &str, mode: ConsentMode) {
+    if mode == ConsentMode::Off {
+        return;
+    }
+    let (Ok(cmps), Ok(generic)) = (serde_json::to_string(CMPS), serde_json::to_string(GENERIC_SELECTORS)) else {
+        return;
+    };
+
+    let script = format!("{}({}, '{}', {})", CONSENT_SCRIPT, cmps, mode.as_str(), generic);
+    let outcome = match timeout(Duration::from_secs(5), page.evaluate(script)).await {
+        Ok(Ok(result)) => result.value().and_then(|v| v.as_str().map(str::to_string)),
+        Ok(Err(e)) => {
+            warn!("Consent handling failed on {}: {}", url, e);
+            return;
+        }
+        Err(_) => {
+            warn!("Timeout handling consent on {}", url);
+            return;
+        }
+    };
+    let Some(outcome) = outcome else {
+        return;
+    };
+    debug!("Consent on {}: {}", url, outcome);
+
+    if outcome.starts_with("clicked") {
+        // Dialogs animate away, and some only close once their API call returns.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let _ = timeout(Duration::from_secs(5), page.evaluate(format!("{}({})", CLEANUP_SCRIPT, cmps))).await;
+    }
+}
@@ -0,0 +1,103 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// Response headers scripts on allowed origins may read; browsers hide all but a safelisted
+/// few otherwise.
+const EXPOSED_HEADERS: [&str; 8] = [
+    "etag",
+    "retry-after",
+    "x-cache",
+    "x-render-time-ms",
+    "x-queue-time-ms",
+    "x-image-quality",
+    "x-placeholder",
+    "x-degraded",
+];
+
+/// Builds the CORS layer from the config. With no allowed origins, no CORS headers are sent and
+/// browsers keep the API to same-origin pages.
+pub fn layer(config: &CorsConfig) -> anyhow::Result<CorsLayer> {
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|m| Method::from_bytes(m.trim().to_ascii_uppercase().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid cors.allowed_methods: {}", e))?;
+    let headers = config
+        .allowed_headers
+        .iter()
+        .map(|h| HeaderName::from_bytes(h.trim().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid cors.allowed_headers: {}", e))?;
+    for origin in &config.allowed_origins {
+        if origin != "*" && !origin.contains("://") {
+            anyhow::bail!("Invalid cors.allowed_origins entry {:?}: expected e.g. https://app.example.com", origin);
+        }
+    }
+
+    let patterns: Vec<String> = config.allowed_origins.iter().map(|o| o.trim_end_matches('/').to_ascii_lowercase()).collect();
+    let origin = AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.to_ascii_lowercase();
+        patterns.iter().any(|pattern| origin_matches(pattern, &origin))
+    });
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(Duration::from_secs(config.max_age_secs)))
+}
+
+/// `*` matches any origin, `https://*.example.com` any subdomain of `example.com` over HTTPS on
+/// the default port, and anything else exactly.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.split_once("*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|rest| rest.strip_suffix(domain))
+            .and_then(|subdomain| subdomain.strip_suffix('.'))
+            .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains(['/', ':', '@'])),
+        None => pattern == origin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_matches_any_origin() {
+        assert!(origin_matches("*", "https://anything.example"));
+        assert!(origin_matches("*", "http://localhost:3000"));
+    }
+
+    #[test]
+    fn exact_patterns_match_only_themselves() {
+        assert!(origin_matches("https://app.example.com", "https://app.example.com"));
+        assert!(!origin_matches("https://app.example.com", "http://app.example.com"));
+        assert!(!origin_matches("https://app.example.com", "https://app.example.com:8443"));
+        assert!(!origin_matches("https://app.example.com", "https://other.example.com"));
+    }
+
+    #[test]
+    fn subdomain_patterns_need_a_subdomain_on_the_same_scheme_and_port() {
+        let pattern = "https://*.example.com";
+        assert!(origin_matches(pattern, "https://a.example.com"));
+        assert!(origin_matches(pattern, "https://a.b.example.com"));
+        assert!(!origin_matches(pattern, "https://example.com"));
+        assert!(!origin_matches(pattern, "https://aexample.com"));
+        assert!(!origin_matches(pattern, "http://a.example.com"));
+        assert!(!origin_matches(pattern, "https://a.example.com:8443"));
+        assert!(!origin_matches(pattern, "https://evil.test/.example.com"));
+        assert!(!origin_matches(pattern, "https://user@a.example.com"));
+    }
+}
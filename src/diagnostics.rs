@@ -0,0 +1,163 @@
+use chromiumoxide::cdp::browser_protocol::network::{
+    EventLoadingFailed, EventRequestWillBeSent, EventResponseReceived, RequestId,
+};
+use chromiumoxide::cdp::js_protocol::runtime::{
+    ConsoleApiCalledType, EventConsoleApiCalled, EventExceptionThrown, RemoteObject,
+};
+use chromiumoxide::Page;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// Entries kept per list; further ones are counted but dropped.
+const MAX_ENTRIES: usize = 50;
+const MAX_MESSAGE_CHARS: usize = 500;
+
+/// What went wrong on a page while it rendered, returned for `debug` requests.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub console_errors: Vec<ConsoleError>,
+    pub failed_requests: Vec<FailedRequest>,
+    /// Entries beyond the per-list limit.
+    pub dropped: usize,
+}
+
+/// A `console.error` call or an uncaught exception.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsoleError {
+    pub message: String,
+    pub url: Option<String>,
+    pub line: Option<i64>,
+}
+
+/// A request that failed at the network level or got an HTTP error status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedRequest {
+    pub url: String,
+    pub status: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl Diagnostics {
+    fn console_error(&mut self, error: ConsoleError) {
+        if self.console_errors.len() < MAX_ENTRIES {
+            self.console_errors.push(error);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    fn failed_request(&mut self, request: FailedRequest) {
+        if self.failed_requests.len() < MAX_ENTRIES {
+            self.failed_requests.push(request);
+        } else {
+            self.dropped += 1;
+        }
+    }
+}
+
+/// Records console errors and failed requests on a page until finished.
+pub struct DiagnosticsCollector {
+    collected: Arc<Mutex<Diagnostics>>,
+    task: JoinHandle<()>,
+}
+
+impl DiagnosticsCollector {
+    pub async fn start(page: &Page) -> anyhow::Result<Self> {
+        let mut console = page.event_listener::<EventConsoleApiCalled>().await?;
+        let mut exceptions = page.event_listener::<EventExceptionThrown>().await?;
+        let mut requests = page.event_listener::<EventRequestWillBeSent>().await?;
+        let mut responses = page.event_listener::<EventResponseReceived>().await?;
+        let mut failures = page.event_listener::<EventLoadingFailed>().await?;
+        let collected = Arc::new(Mutex::new(Diagnostics::default()));
+
+        let diagnostics = collected.clone();
+        let task = tokio::spawn(async move {
+            // Failure events only carry the request ID.
+            let mut urls: HashMap<RequestId, String> = HashMap::new();
+            loop {
+                tokio::select! {
+                    Some(event) = console.next() => {
+                        if event.r#type != ConsoleApiCalledType::Error {
+                            continue;
+                        }
+                        let frame = event.stack_trace.as_ref().and_then(|s| s.call_frames.first());
+                        let message = event.args.iter().map(describe).collect::<Vec<_>>().join(" ");
+                        lock(&diagnostics).console_error(ConsoleError {
+                            message: truncate(message),
+                            url: frame.map(|f| f.url.clone()).filter(|u| !u.is_empty()),
+                            line: frame.map(|f| f.line_number + 1),
+                        });
+                    }
+                    Some(event) = exceptions.next() => {
+                        let details = &event.exception_details;
+                        let message = details
+                            .exception
+                            .as_ref()
+                            .and_then(|e| e.description.clone())
+                            .unwrap_or_else(|| details.text.clone());
+                        lock(&diagnostics).console_error(ConsoleError {
+                            message: truncate(message),
+                            url: details.url.clone(),
+                            line: Some(details.line_number + 1),
+                        });
+                    }
+                    Some(event) = requests.next() => {
+                        urls.insert(event.request_id.clone(), event.request.url.clone());
+                    }
+                    Some(event) = responses.next() => {
+                        if event.response.status >= 400 {
+                            lock(&diagnostics).failed_request(FailedRequest {
+                                url: event.response.url.clone(),
+                                status: Some(event.response.status),
+                                error: None,
+                            });
+                        }
+                    }
+                    Some(event) = failures.next() => {
+                        // Cancellations are the page's own doing, e.g. navigating away.
+                        if event.canceled == Some(true) {
+                            continue;
+                        }
+                        lock(&diagnostics).failed_request(FailedRequest {
+                            url: urls.get(&event.request_id).cloned().unwrap_or_default(),
+                            status: None,
+                            error: Some(event.error_text.clone()),
+                        });
+                    }
+                    else => return,
+                }
+            }
+        });
+
+        Ok(Self { collected, task })
+    }
+
+    /// Stops collecting and returns what was seen.
+    pub fn finish(self) -> Diagnostics {
+        self.task.abort();
+        std::mem::take(&mut *lock(&self.collected))
+    }
+}
+
+fn lock(diagnostics: &Mutex<Diagnostics>) -> std::sync::MutexGuard<'_, Diagnostics> {
+    diagnostics.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn describe(arg: &RemoteObject) -> String {
+    match &arg.value {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+        None => arg.description.clone().unwrap_or_default(),
+    }
+}
+
+fn truncate(mut message: String) -> String {
+    if let Some((index, _)) = message.char_indices().nth(MAX_MESSAGE_CHARS) {
+        message.truncate(index);
+        message.push('…');
+    }
+    message
+}
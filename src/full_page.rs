@@ -0,0 +1,124 @@
+use bytes::Bytes;
+use png::{BitDepth, ColorType, Encoder, StreamWriter};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::server::AppError;
+
+/// Collects what the encoder writes between strips, to be sent on as one body chunk.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Output {
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner())))
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes a full-page PNG one screenshot strip at a time, so however long the page is, only a
+/// strip and the compressor's window are held in memory.
+pub struct StripEncoder {
+    writer: StreamWriter<'static, Output>,
+    output: Output,
+    width: u32,
+    rows_left: u32,
+}
+
+impl StripEncoder {
+    /// Starts a `width` x `height` RGB image; the header is in the first chunk `push` returns.
+    pub fn new(width: u32, height: u32) -> Result<Self, AppError> {
+        let output = Output::default();
+        let mut encoder = Encoder::new(output.clone(), width, height);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let writer = encoder
+            .write_header()
+            .and_then(|header| header.into_stream_writer())
+            .map_err(encode_error)?;
+        Ok(Self { writer, output, width, rows_left: height })
+    }
+
+    /// Appends the rows of an encoded strip, cropped or padded with white to the image width, and
+    /// returns the PNG bytes produced since the last call. Rows past the image height are dropped.
+    pub fn push(&mut self, strip: &[u8]) -> Result<Bytes, AppError> {
+        let strip = image::load_from_memory(strip)
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to decode capture strip: {}", e)))?
+            .into_rgb8();
+        let row_bytes = self.width as usize * 3;
+        let mut row = vec![255u8; row_bytes];
+        for source in strip.as_raw().chunks_exact(strip.width() as usize * 3).take(self.rows_left as usize) {
+            let copied = source.len().min(row_bytes);
+            row[..copied].copy_from_slice(&source[..copied]);
+            row[copied..].fill(255);
+            self.writer.write_all(&row).map_err(|e| encode_error(e.into()))?;
+            self.rows_left -= 1;
+        }
+        Ok(self.output.take())
+    }
+
+    /// Finishes the image, failing if the strips fell short of its height.
+    pub fn finish(self) -> Result<Bytes, AppError> {
+        if self.rows_left > 0 {
+            return Err(AppError::ImageProcessing(format!("Capture ended {} rows short", self.rows_left)));
+        }
+        self.writer.finish().map_err(encode_error)?;
+        Ok(self.output.take())
+    }
+}
+
+fn encode_error(e: png::EncodingError) -> AppError {
+    AppError::ImageProcessing(format!("Failed to encode full-page PNG: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn strip(width: u32, height: u32, color: [u8; 3]) -> Vec<u8> {
+        let mut data = Vec::new();
+        RgbImage::from_pixel(width, height, Rgb(color))
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn crops_pads_and_trims_strips_to_the_image() {
+        let mut encoder = StripEncoder::new(4, 5).unwrap();
+        let mut png = Vec::new();
+        png.extend_from_slice(&encoder.push(&strip(6, 3, [255, 0, 0])).unwrap());
+        png.extend_from_slice(&encoder.push(&strip(2, 3, [0, 0, 255])).unwrap());
+        png.extend_from_slice(&encoder.finish().unwrap());
+
+        let image = image::load_from_memory(&png).unwrap().into_rgb8();
+        assert_eq!(image.dimensions(), (4, 5));
+        assert_eq!(image.get_pixel(3, 2), &Rgb([255, 0, 0]));
+        assert_eq!(image.get_pixel(1, 4), &Rgb([0, 0, 255]));
+        assert_eq!(image.get_pixel(2, 3), &Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn finishing_short_of_the_height_fails() {
+        let mut encoder = StripEncoder::new(4, 5).unwrap();
+        encoder.push(&strip(4, 2, [0, 0, 0])).unwrap();
+        assert!(encoder.finish().is_err());
+    }
+
+    #[test]
+    fn rejects_a_strip_that_is_not_an_image() {
+        let mut encoder = StripEncoder::new(4, 5).unwrap();
+        assert!(encoder.push(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use axum::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use bytes::Bytes;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use ring::hmac;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::audit::{AuditEntry, AuditLog, Requester};
+use crate::config::HookConfig;
+use crate::usage::ANONYMOUS;
+
+/// Events waiting for delivery beyond this are dropped, so slow hooks can't hold up renders.
+const QUEUE_CAPACITY: usize = 256;
+
+/// Approximate number of events kept in a Redis stream before old ones are trimmed.
+const MAX_STREAM_LENGTH: usize = 10_000;
+
+/// A new thumbnail, handed to output hooks once it's encoded and cached.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThumbnailEvent {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    /// Absent when no tenants are configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub namespace: String,
+    pub cache_key: String,
+    /// `webp`, `jpeg`, or `png`.
+    pub format: String,
+    pub content_type: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub created_at: u64,
+    pub size: u64,
+    /// Hex SHA-256 of the image.
+    pub image_sha256: String,
+    #[serde(skip)]
+    pub image: Bytes,
+    /// Fingerprint of the API key the render was for, or `-`, for the audit log.
+    #[serde(skip)]
+    pub key: String,
+}
+
+/// Somewhere new thumbnails are sent. Hooks run in the background after the response, so a
+/// failure is logged and doesn't affect the request.
+#[async_trait]
+pub trait OutputHook: Send + Sync {
+    /// Identifies the hook in logs.
+    fn name(&self) -> String;
+
+    /// Where the hook sends events over HTTP, so deliveries are audited like other fetches.
+    fn remote_url(&self) -> Option<&str> {
+        None
+    }
+
+    async fn deliver(&self, event: &ThumbnailEvent) -> anyhow::Result<()>;
+}
+
+/// Fans new thumbnails out to the configured hooks from a background task.
+pub struct Hooks {
+    sender: Option<mpsc::Sender<ThumbnailEvent>>,
+}
+
+impl Hooks {
+    pub async fn new(configs: &[HookConfig], audit: Arc<AuditLog>) -> anyhow::Result<Self> {
+        let mut hooks: Vec<Box<dyn OutputHook>> = Vec::new();
+        for config in configs {
+            hooks.push(match config {
+                HookConfig::Webhook { url, include_image, secret } => {
+                    Box::new(WebhookHook::new(url, *include_image, secret.as_deref())?)
+                }
+                HookConfig::Directory { path } => Box::new(DirectoryHook { dir: path.clone() }),
+                HookConfig::Redis { url, stream } => Box::new(RedisHook::connect(url, stream).await?),
+            });
+        }
+        Ok(Self::with_hooks(hooks, audit))
+    }
+
+    fn with_hooks(hooks: Vec<Box<dyn OutputHook>>, audit: Arc<AuditLog>) -> Self {
+        if hooks.is_empty() {
+            return Self { sender: None };
+        }
+        info!("Sending new thumbnails to {}", hooks.iter().map(|h| h.name()).collect::<Vec<_>>().join(", "));
+        let (sender, mut events) = mpsc::channel::<ThumbnailEvent>(QUEUE_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let deliveries = hooks.iter().map(|hook| async {
+                    let delivered = hook.deliver(&event).await;
+                    if let Some(url) = hook.remote_url() {
+                        let requester = Requester { tenant: event.tenant.as_deref().unwrap_or(ANONYMOUS), key: &event.key };
+                        let entry = AuditEntry::fetch(requester, "hook", url, delivered.as_ref().ok().map(|_| 0));
+                        if let Err(e) = audit.record(&entry) {
+                            warn!("Failed to record audit entry for {}: {}", url, e);
+                        }
+                    }
+                    match delivered {
+                        Ok(()) => debug!("Sent {} to {}", event.url, hook.name()),
+                        Err(e) => warn!("Failed to send {} to {}: {}", event.url, hook.name(), e),
+                    }
+                });
+                futures::future::join_all(deliveries).await;
+            }
+        });
+        Self { sender: Some(sender) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sender.is_none()
+    }
+
+    /// Queues an event for the hooks without waiting for them.
+    pub fn publish(&self, event: ThumbnailEvent) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if let Err(e) = sender.try_send(event) {
+            warn!("Dropping output hook event: {}", e);
+        }
+    }
+}
+
+/// POSTs each event as JSON, signed with HMAC-SHA256 in `X-Tin-Signature` when a secret is set.
+struct WebhookHook {
+    client: reqwest::Client,
+    url: String,
+    include_image: bool,
+    key: Option<hmac::Key>,
+}
+
+impl WebhookHook {
+    fn new(url: &str, include_image: bool, secret: Option<&str>) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?,
+            url: url.to_string(),
+            include_image,
+            key: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputHook for WebhookHook {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn remote_url(&self) -> Option<&str> {
+        Some(&self.url)
+    }
+
+    async fn deliver(&self, event: &ThumbnailEvent) -> anyhow::Result<()> {
+        let mut body = serde_json::to_value(event)?;
+        if self.include_image {
+            body["image_data"] = general_purpose::STANDARD.encode(&event.image).into();
+        }
+        let body = serde_json::to_vec(&body)?;
+        let mut request = self.client.post(&self.url).header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(key) = &self.key {
+            let tag = hmac::sign(key, &body);
+            let hex: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+            request = request.header("x-tin-signature", format!("sha256={}", hex));
+        }
+        request.body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Writes each image to `<dir>/<namespace>/<key hash>.<format>` with the event as a JSON file
+/// beside it, replacing the files of the entry's previous render.
+struct DirectoryHook {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl OutputHook for DirectoryHook {
+    fn name(&self) -> String {
+        format!("directory {:?}", self.dir)
+    }
+
+    async fn deliver(&self, event: &ThumbnailEvent) -> anyhow::Result<()> {
+        let dir = self.dir.join(&event.namespace);
+        tokio::fs::create_dir_all(&dir).await?;
+        let stem = format!("{:x}", Sha256::digest(event.cache_key.as_bytes()));
+        let image = dir.join(format!("{}.{}", stem, event.format));
+        let metadata = dir.join(format!("{}.json", stem));
+        // Written aside and renamed, so readers never see a partial file.
+        for (path, contents) in [(image, event.image.to_vec()), (metadata, serde_json::to_vec_pretty(event)?)] {
+            let partial = path.with_extension("partial");
+            tokio::fs::write(&partial, contents).await?;
+            tokio::fs::rename(&partial, &path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds each event, without the image, to a Redis stream as JSON in the `event` field.
+struct RedisHook {
+    connection: ConnectionManager,
+    stream: String,
+}
+
+impl RedisHook {
+    async fn connect(url: &str, stream: &str) -> anyhow::Result<Self> {
+        let connection = redis::Client::open(url)?.get_connection_manager().await?;
+        Ok(Self { connection, stream: stream.to_string() })
+    }
+}
+
+#[async_trait]
+impl OutputHook for RedisHook {
+    fn name(&self) -> String {
+        format!("redis stream {}", self.stream)
+    }
+
+    async fn deliver(&self, event: &ThumbnailEvent) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(event)?;
+        let mut connection = self.connection.clone();
+        let _: String = connection
+            .xadd_maxlen(&self.stream, redis::streams::StreamMaxlen::Approx(MAX_STREAM_LENGTH), "*", &[("event", payload)])
+            .await?;
+        Ok(())
+    }
+}
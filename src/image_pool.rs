@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::config::ProcessingConfig;
+use crate::server::AppError;
+
+/// Rough bytes held per pixel while an image is decoded, resized, and encoded.
+const BYTES_PER_PIXEL: u64 = 16;
+
+/// Runs CPU-bound image work on blocking threads, so Lanczos resizing and encoding never stall
+/// the async runtime. Parallelism is capped, and so is the memory images in progress are
+/// estimated to need; a large image waits until enough smaller ones finish.
+pub struct ImagePool {
+    slots: Arc<Semaphore>,
+    memory: Arc<Semaphore>,
+    memory_mb: u32,
+    max_source_pixels: u64,
+}
+
+impl ImagePool {
+    pub fn new(config: &ProcessingConfig) -> Self {
+        let parallel = match config.max_parallel {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let memory_mb = config.max_memory_mb.max(1);
+        info!("Image processing: {} at once within {} MB", parallel, memory_mb);
+        Self {
+            slots: Arc::new(Semaphore::new(parallel)),
+            memory: Arc::new(Semaphore::new(memory_mb as usize)),
+            memory_mb,
+            max_source_pixels: config.max_source_pixels,
+        }
+    }
+
+    /// Reads the pixel count from the image header, rejecting images over the configured cap
+    /// before anything decodes them.
+    pub fn check_source(&self, data: &[u8]) -> Result<u64, AppError> {
+        let (width, height) = image::io::Reader::new(std::io::Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to read image header: {}", e)))?
+            .into_dimensions()
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to read image dimensions: {}", e)))?;
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_source_pixels {
+            return Err(AppError::Render(
+                "source_too_large",
+                format!("{}x{} source image exceeds {} pixels", width, height, self.max_source_pixels),
+            ));
+        }
+        Ok(pixels)
+    }
+
+    /// Runs `work` on a blocking thread once a slot and memory for `pixels` (sources and output
+    /// together) are free.
+    pub async fn run<T, F>(&self, pixels: u64, work: F) -> Result<T, AppError>
+    where
+        F: FnOnce() -> Result<T, AppError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let megabytes = (pixels * BYTES_PER_PIXEL / (1024 * 1024)).clamp(1, self.memory_mb as u64) as u32;
+        let closed = |_| AppError::Internal("Image pool is closed".to_string());
+        let memory = self.memory.clone().acquire_many_owned(megabytes).await.map_err(closed)?;
+        let slot = self.slots.clone().acquire_owned().await.map_err(closed)?;
+
+        tokio::task::spawn_blocking(move || {
+            let _permits = (slot, memory);
+            work()
+        })
+        .await
+        .map_err(|e| AppError::Internal(format!("Image task failed: {}", e)))?
+    }
+}
@@ -0,0 +1,156 @@
+use chromiumoxide::Page;
+use serde::Deserialize;
+use tokio::time::{timeout, Duration};
+use tracing::warn;
+
+/// Longest excerpt returned, in characters.
+const EXCERPT_CHARS: usize = 300;
+
+/// Reads the declared language and up to 2000 characters of the main content's visible text:
+/// the longest `<article>`, else `<main>`, else the body.
+const EXTRACT_SCRIPT: &str = r#"
+    (() => {
+        const declared = document.documentElement.lang
+            || document.querySelector('meta[http-equiv="content-language" i]')?.content
+            || document.querySelector('meta[property="og:locale"]')?.content
+            || null;
+        const text = el => (el?.innerText || '').replace(/\s+/g, ' ').trim();
+        const articles = [...document.querySelectorAll('article')].map(text).sort((a, b) => b.length - a.length);
+        const content = articles[0] || text(document.querySelector('main, [role="main"]')) || text(document.body);
+        return { declared, text: content.slice(0, 2000) };
+    })()
+"#;
+
+#[derive(Deserialize)]
+struct Extracted {
+    declared: Option<String>,
+    text: String,
+}
+
+/// The page's primary language and the start of its main content.
+#[derive(Debug, Default)]
+pub struct PageText {
+    /// ISO 639-1 code: the declared language, else one guessed from the text.
+    pub language: Option<String>,
+    /// Plain text, whitespace collapsed, cut at a word boundary.
+    pub excerpt: Option<String>,
+}
+
+/// Extracts the language and an excerpt from the loaded page.
+pub async fn extract(page: &Page, url: &str) -> PageText {
+    let extracted = match timeout(Duration::from_secs(5), page.evaluate(EXTRACT_SCRIPT)).await {
+        Ok(Ok(result)) => match result.into_value::<Extracted>() {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                warn!("Unexpected text extraction result on {}: {}", url, e);
+                return PageText::default();
+            }
+        },
+        Ok(Err(e)) => {
+            warn!("Text extraction failed on {}: {}", url, e);
+            return PageText::default();
+        }
+        Err(_) => {
+            warn!("Timeout extracting text from {}", url);
+            return PageText::default();
+        }
+    };
+
+    let language = extracted
+        .declared
+        .as_deref()
+        .and_then(primary_subtag)
+        .or_else(|| detect(&extracted.text).map(str::to_string));
+    PageText {
+        language,
+        excerpt: excerpt(&extracted.text),
+    }
+}
+
+/// The first `EXCERPT_CHARS` characters of `text`, cut back to the last whole word.
+fn excerpt(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let Some((cut, _)) = text.char_indices().nth(EXCERPT_CHARS) else {
+        return Some(text.to_string());
+    };
+    let head = &text[..cut];
+    let head = head.rfind(' ').map_or(head, |space| &head[..space]);
+    Some(format!("{}…", head.trim_end()))
+}
+
+/// Common function words per language; short, frequent, and rarely shared between these languages.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    ("en", &["the", "and", "of", "to", "is", "in", "that", "for", "with", "you", "this", "are"]),
+    ("de", &["der", "die", "und", "das", "ist", "nicht", "mit", "ein", "eine", "auf", "sich", "auch"]),
+    ("fr", &["le", "la", "les", "et", "des", "est", "une", "pour", "dans", "que", "pas", "sur"]),
+    ("es", &["el", "los", "las", "y", "del", "es", "una", "para", "por", "con", "que", "como"]),
+    ("it", &["il", "di", "che", "della", "per", "non", "sono", "una", "gli", "anche", "con", "nel"]),
+    ("pt", &["os", "das", "não", "uma", "para", "com", "que", "mais", "como", "por", "dos", "são"]),
+    ("nl", &["de", "het", "een", "en", "van", "is", "niet", "op", "dat", "met", "zijn", "voor"]),
+    ("sv", &["och", "att", "det", "som", "är", "på", "för", "med", "inte", "av", "den", "till"]),
+    ("pl", &["i", "w", "nie", "się", "na", "jest", "że", "do", "to", "z", "jak", "ale"]),
+];
+
+/// Fewest stopword hits before a Latin-script guess is trusted.
+const MIN_HITS: usize = 3;
+
+/// Guesses the primary language of `text` as an ISO 639-1 code, from its script or, for Latin
+/// text, from function-word frequencies.
+fn detect(text: &str) -> Option<&'static str> {
+    if let Some(language) = detect_script(text) {
+        return Some(language);
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let (language, hits) = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| (*language, words.iter().filter(|w| stopwords.contains(&w.as_str())).count()))
+        .max_by_key(|&(_, hits)| hits)?;
+    (hits >= MIN_HITS).then_some(language)
+}
+
+/// Languages that are identified by their script alone, if it makes up most letters.
+fn detect_script(text: &str) -> Option<&'static str> {
+    let mut counts = [0usize; 8];
+    let mut letters = 0;
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let index = match c as u32 {
+            0x3040..=0x30FF => 0,                    // Hiragana and Katakana
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => 1,  // Hangul
+            0x4E00..=0x9FFF => 2,                    // CJK ideographs
+            0x0400..=0x04FF => 3,                    // Cyrillic
+            0x0600..=0x06FF => 4,                    // Arabic
+            0x0590..=0x05FF => 5,                    // Hebrew
+            0x0370..=0x03FF => 6,                    // Greek
+            0x0E00..=0x0E7F => 7,                    // Thai
+            _ => continue,
+        };
+        counts[index] += 1;
+    }
+    if letters == 0 {
+        return None;
+    }
+    // Japanese mixes kana with ideographs, so any real share of kana decides it.
+    if counts[0] * 10 > letters {
+        return Some("ja");
+    }
+    let (index, &count) = counts.iter().enumerate().max_by_key(|&(_, count)| *count)?;
+    if count * 2 < letters {
+        return None;
+    }
+    Some(["ja", "ko", "zh", "ru", "ar", "he", "el", "th"][index])
+}
+
+/// The primary subtag of a declared language such as `en-US`, lowercased.
+fn primary_subtag(tag: &str) -> Option<String> {
+    let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+    (primary.len() >= 2 && primary.len() <= 3 && primary.chars().all(|c| c.is_ascii_alphabetic())).then_some(primary)
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Tree;
+use std::time::Duration;
+
+use crate::cache::now_secs;
+
+/// An image held for `response_mode=url` responses until it expires.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkedImage {
+    pub content_type: String,
+    /// Hex SHA-256 of the image, used as its ETag.
+    pub hash: String,
+    /// Unix seconds after which the link stops working.
+    pub expires_at: u64,
+    pub data: Vec<u8>,
+}
+
+/// Short-lived images served from `/images/{token}`, so large thumbnails can be fetched
+/// separately instead of inlined in the JSON response.
+pub struct LinkStore {
+    tree: Tree,
+    ttl: Duration,
+}
+
+impl LinkStore {
+    pub fn new(tree: Tree, ttl: Duration) -> Self {
+        Self { tree, ttl }
+    }
+
+    /// Holds the image for the configured TTL under a random 128-bit token, returning the token
+    /// and expiry. The token rather than a content hash is the capability, so anyone who knows
+    /// or guesses the image can't fetch someone else's link.
+    pub fn put(&self, data: &[u8], content_type: &str) -> anyhow::Result<(String, u64)> {
+        let mut token = [0u8; 16];
+        ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut token)
+            .map_err(|_| anyhow::anyhow!("Failed to generate an image link token"))?;
+        let token: String = token.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let image = LinkedImage {
+            content_type: content_type.to_string(),
+            hash: format!("{:x}", Sha256::digest(data)),
+            expires_at: now_secs() + self.ttl.as_secs(),
+            data: data.to_vec(),
+        };
+        let bytes = bincode::serialize(&image)
+            .map_err(|e| anyhow::anyhow!("Linked image serialization failed: {}", e))?;
+        self.tree.insert(&token, bytes)?;
+        Ok((token, image.expires_at))
+    }
+
+    /// Returns the image unless it's missing or expired.
+    pub fn get(&self, token: &str) -> anyhow::Result<Option<LinkedImage>> {
+        let Some(bytes) = self.tree.get(token)? else {
+            return Ok(None);
+        };
+        let image: LinkedImage = bincode::deserialize(&bytes)
+            .map_err(|e| anyhow::anyhow!("Linked image deserialization failed: {}", e))?;
+        Ok(Some(image).filter(|image| image.expires_at > now_secs()))
+    }
+
+    /// Deletes expired images, returning how many were removed.
+    pub fn purge_expired(&self) -> anyhow::Result<usize> {
+        let now = now_secs();
+        let mut removed = 0;
+        for item in self.tree.iter() {
+            let (token, bytes) = item?;
+            let expired = bincode::deserialize::<LinkedImage>(&bytes).map_or(true, |image| image.expires_at <= now);
+            if expired {
+                self.tree.remove(&token)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,134 @@
+use chromiumoxide::browser::Browser;
+use chromiumoxide::cdp::browser_protocol::browser::{SetDownloadBehaviorBehavior, SetDownloadBehaviorParams};
+use chromiumoxide::cdp::browser_protocol::page::AddScriptToEvaluateOnNewDocumentParams;
+use chromiumoxide::cdp::browser_protocol::performance::{EnableParams, EnableTimeDomain, GetMetricsParams};
+use chromiumoxide::cdp::browser_protocol::target::{CloseTargetParams, EventTargetCreated};
+use chromiumoxide::Page;
+use futures::StreamExt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, info, warn};
+
+/// Neutralizes what a page could use to escape its tab or prompt a user nobody is watching:
+/// `window.open`, links and forms targeting other windows, download links, dialogs, and
+/// notification, geolocation, and other permission requests.
+const GUARD_SCRIPT: &str = r#"
+    (() => {
+        const lock = (object, name, value) => {
+            try { Object.defineProperty(object, name, { value, writable: false, configurable: false }); } catch (e) {}
+        };
+        lock(window, 'open', () => null);
+        lock(window, 'alert', () => {});
+        lock(window, 'confirm', () => false);
+        lock(window, 'prompt', () => null);
+        lock(window, 'print', () => {});
+        if (window.Notification) {
+            lock(Notification, 'requestPermission', () => Promise.resolve('denied'));
+            try { Object.defineProperty(Notification, 'permission', { get: () => 'denied' }); } catch (e) {}
+        }
+        const refuse = () => Promise.reject(new DOMException('Permission denied', 'NotAllowedError'));
+        if (navigator.permissions) {
+            lock(navigator.permissions, 'query', () => Promise.resolve({ state: 'denied', onchange: null }));
+        }
+        if (navigator.geolocation) {
+            const denied = (_, error) => error && error({ code: 1, message: 'User denied Geolocation' });
+            lock(navigator.geolocation, 'getCurrentPosition', denied);
+            lock(navigator.geolocation, 'watchPosition', (success, error) => { denied(success, error); return 0; });
+        }
+        if (navigator.mediaDevices) lock(navigator.mediaDevices, 'getUserMedia', refuse);
+        if (window.PushManager) lock(PushManager.prototype, 'subscribe', refuse);
+        const elsewhere = target => target && !['_self', '_parent', '_top'].includes(target.toLowerCase());
+        const intercept = event => {
+            const link = event.target.closest && event.target.closest('a, area');
+            if (link && (link.hasAttribute('download') || elsewhere(link.target))) event.preventDefault();
+        };
+        addEventListener('click', intercept, true);
+        addEventListener('auxclick', intercept, true);
+        addEventListener('submit', event => { if (elsewhere(event.target.target)) event.preventDefault(); }, true);
+    })()
+"#;
+
+/// Installs the popup and prompt guard on a page for all documents it will load.
+pub async fn guard_page(page: &Page) -> anyhow::Result<()> {
+    timeout(Duration::from_secs(5), page.execute(AddScriptToEvaluateOnNewDocumentParams::new(GUARD_SCRIPT)))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timeout installing the page guard"))??;
+    Ok(())
+}
+
+/// Denies downloads and closes any window a page manages to open despite the guard, e.g. with
+/// JavaScript disabled. Follows the browser across reconnects.
+pub async fn close_popups(browser: Arc<Mutex<Browser>>) {
+    loop {
+        let created = {
+            let browser = browser.lock().await;
+            let deny = SetDownloadBehaviorParams::builder().behavior(SetDownloadBehaviorBehavior::Deny).build();
+            match deny {
+                Ok(deny) => {
+                    if let Err(e) = browser.execute(deny).await {
+                        warn!("Failed to deny downloads: {}", e);
+                    }
+                }
+                Err(e) => warn!("Failed to deny downloads: {}", e),
+            }
+            browser.event_listener::<EventTargetCreated>().await
+        };
+        match created {
+            Ok(mut created) => {
+                while let Some(event) = created.next().await {
+                    let target = &event.target_info;
+                    // Pages opened for renders have no opener; anything else was opened by a page.
+                    if target.opener_id.is_none() || target.r#type != "page" {
+                        continue;
+                    }
+                    info!("Closing popup {} opened by a page", target.url);
+                    let close = CloseTargetParams::new(target.target_id.clone());
+                    if let Err(e) = browser.lock().await.execute(close).await {
+                        debug!("Failed to close popup: {}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to watch for popups: {}", e),
+        }
+        // The stream ends when the browser connection does; wait for a new one.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Measures the main-thread CPU time a page has used, from the renderer's own counters.
+pub struct CpuMeter {
+    page: Page,
+    baseline: Duration,
+}
+
+impl CpuMeter {
+    pub async fn start(page: &Page) -> anyhow::Result<Self> {
+        let enable = EnableParams::builder().time_domain(EnableTimeDomain::ThreadTicks).build();
+        timeout(Duration::from_secs(5), page.execute(enable))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout enabling performance metrics"))??;
+        let page = page.clone();
+        // Pooled pages carry the counters of earlier renders.
+        let baseline = task_duration(&page).await?.unwrap_or_default();
+        Ok(Self { page, baseline })
+    }
+
+    /// CPU time used since `start`, or `None` if the page didn't answer within `wait`, as
+    /// happens when a script is keeping its thread busy.
+    pub async fn used(&self, wait: Duration) -> Option<Duration> {
+        match timeout(wait, task_duration(&self.page)).await {
+            Ok(Ok(used)) => used.map(|used| used.saturating_sub(self.baseline)),
+            _ => None,
+        }
+    }
+}
+
+async fn task_duration(page: &Page) -> anyhow::Result<Option<Duration>> {
+    let metrics = page.execute(GetMetricsParams::default()).await?;
+    Ok(metrics
+        .metrics
+        .iter()
+        .find(|metric| metric.name == "TaskDuration")
+        .map(|metric| Duration::from_secs_f64(metric.value.max(0.0))))
+}
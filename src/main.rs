@@ -1,31 +1,191 @@
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
 use std::net::SocketAddr;
-use tracing::info;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
 
+mod alerts;
+mod audit;
+mod auth;
+mod breaker;
+mod browser_download;
+mod browserless;
 mod cache;
+mod config;
+mod consent;
+mod cors;
+mod diagnostics;
+mod full_page;
+mod hooks;
+mod image_pool;
+mod language;
+mod links;
+mod lockdown;
+mod metadata;
+mod normalize;
+mod politeness;
+mod page_pool;
+mod paywall;
+mod probe;
+mod processing;
+mod queue;
+mod robots;
+mod self_test;
 mod server;
+mod session;
+mod signing;
 mod thumbnail;
+mod trace;
+mod usage;
 
+use crate::cache::Cache;
+use crate::config::{Config, TlsConfig};
 use crate::server::create_app;
 
+const DEFAULT_LOG_FILTER: &str = "thumbnail_service=info,tower_http=debug";
+
+/// How long open HTTPS connections get to finish after a shutdown signal.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage: thumbnail-service [worker | cache export <file> | cache import <file>]";
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter("thumbnail_service=info,tower_http=debug")
+    // The filter sits behind a reload layer so the admin API can change it at runtime.
+    let (filter, log_handle) = reload::Layer::new(EnvFilter::new(DEFAULT_LOG_FILTER));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(9142);
+    let config = Config::load()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return run_command(&config, &args).await;
+    }
+
+    let addr = SocketAddr::new(config.host, config.port);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    
     info!("Starting thumbnail service on {}", addr);
-    
-    let app = create_app().await?;
-    
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
 
+    let cache = Arc::new(Cache::new(&config.cache)?);
+    let app = create_app(&config, cache.clone(), log_handle).await?;
+
+    match &config.tls {
+        Some(tls) => serve_tls(addr, app, tls).await?,
+        None => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await?;
+        }
+    }
+
+    info!("Shutting down");
+    cache.flush().await?;
+    Ok(())
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+async fn serve_tls(addr: SocketAddr, app: Router, tls: &TlsConfig) -> anyhow::Result<()> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to load TLS certificate: {}", e))?;
+    info!("Serving HTTPS with certificate {:?}", tls.cert_path);
+
+    tokio::spawn(watch_certificate(rustls_config.clone(), tls.clone()));
+
+    let handle = axum_server::Handle::new();
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        shutdown.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    });
+
+    axum_server::bind_rustls(addr, rustls_config)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+/// Reloads the certificate and key whenever either file's modification time changes.
+async fn watch_certificate(rustls_config: RustlsConfig, tls: TlsConfig) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let stamp = |tls: &TlsConfig| -> Option<(SystemTime, SystemTime)> {
+        Some((modified(&tls.cert_path)?, modified(&tls.key_path)?))
+    };
+
+    let mut last = stamp(&tls);
+    let mut interval = tokio::time::interval(Duration::from_secs(tls.reload_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let current = stamp(&tls);
+        if current.is_none() || current == last {
+            continue;
+        }
+        match rustls_config.reload_from_pem_file(&tls.cert_path, &tls.key_path).await {
+            Ok(()) => {
+                info!("Reloaded TLS certificate {:?}", tls.cert_path);
+                last = current;
+            }
+            Err(e) => error!("Failed to reload TLS certificate: {}", e),
+        }
+    }
+}
+
+async fn run_command(config: &Config, args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["worker"] => queue::run_worker(config).await?,
+        ["cache", "export", file] => {
+            let cache = Cache::new(&config.cache)?;
+            let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+            let count = cache.export(out)?;
+            println!("Exported {} entries to {}", count, file);
+        }
+        ["cache", "import", file] => {
+            let cache = Cache::new(&config.cache)?;
+            let input = std::io::BufReader::new(std::fs::File::open(file)?);
+            let report = cache.import(input)?;
+            println!("Imported {} entries from {}", report.imported, file);
+            if report.skipped > 0 {
+                println!("Skipped {} entries rendered under another cache version", report.skipped);
+            }
+        }
+        _ => anyhow::bail!(USAGE),
+    }
     Ok(())
 }
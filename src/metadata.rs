@@ -0,0 +1,428 @@
+use chrono::{DateTime, SecondsFormat};
+use flate2::{write::ZlibEncoder, Compression};
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::processing::ImageFormat;
+
+/// IPTC's term for a screenshot, so policies that check how an image was made see it as a capture.
+const DIGITAL_SOURCE_TYPE: &str = "http://cv.iptc.org/newscodes/digitalsourcetype/screenCapture";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// What to embed in an image; anything already in it is always removed.
+#[derive(Debug, Clone)]
+pub struct Embed {
+    /// Page the image was captured from.
+    pub source_url: String,
+    /// Unix seconds of the capture.
+    pub captured_at: u64,
+    /// Add an XMP block with the source URL, capture time, and generator version.
+    pub provenance: bool,
+    /// Add an sRGB ICC profile.
+    pub srgb_profile: bool,
+}
+
+/// Removes EXIF, ICC, XMP, and text metadata from an encoded image, then adds what `embed`
+/// asks for. Only the container is rewritten; the pixel data is copied untouched.
+pub fn rewrite(data: &[u8], format: ImageFormat, embed: &Embed) -> anyhow::Result<Vec<u8>> {
+    let xmp = embed.provenance.then(|| xmp_packet(embed));
+    let icc = embed.srgb_profile.then(srgb_profile);
+    match format {
+        ImageFormat::Jpeg => rewrite_jpeg(data, xmp.as_deref(), icc),
+        ImageFormat::Png => rewrite_png(data, xmp.as_deref(), icc),
+        ImageFormat::Webp => rewrite_webp(data, xmp.as_deref(), icc),
+    }
+}
+
+fn xmp_packet(embed: &Embed) -> String {
+    let created = DateTime::from_timestamp(embed.captured_at as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\"",
+            " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+            " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"",
+            " xmlns:Iptc4xmpExt=\"http://iptc.org/std/Iptc4xmpExt/2008-02-29/\"",
+            " dc:source=\"{}\" xmp:CreateDate=\"{}\" xmp:CreatorTool=\"{} {}\"",
+            " Iptc4xmpExt:DigitalSourceType=\"{}\"/>",
+            "</rdf:RDF></x:xmpmeta><?xpacket end=\"r\"?>"
+        ),
+        escape_xml(&embed.source_url),
+        created,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        DIGITAL_SOURCE_TYPE,
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn rewrite_jpeg(data: &[u8], xmp: Option<&str>, icc: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        anyhow::bail!("Not a JPEG");
+    }
+    let mut added = Vec::new();
+    if let Some(xmp) = xmp {
+        push_jpeg_segment(&mut added, 0xE1, &[b"http://ns.adobe.com/xap/1.0/\0".as_slice(), xmp.as_bytes()].concat())?;
+    }
+    if let Some(icc) = icc {
+        push_jpeg_segment(&mut added, 0xE2, &[b"ICC_PROFILE\0\x01\x01".as_slice(), icc].concat())?;
+    }
+
+    let mut output = Vec::with_capacity(data.len() + added.len());
+    output.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    let mut inserted = false;
+    // Segments are read up to the start of the scan, after which the file is copied as is.
+    while pos + 4 <= data.len() && data[pos] == 0xFF && data[pos + 1] != 0xDA {
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            anyhow::bail!("Truncated JPEG segment");
+        }
+        // New segments go after the JFIF header, which decoders expect first.
+        if !inserted && marker != 0xE0 {
+            output.extend_from_slice(&added);
+            inserted = true;
+        }
+        // APP1 (EXIF, XMP), APP2 (ICC), APP13 (IPTC), and comments.
+        if !matches!(marker, 0xE1 | 0xE2 | 0xED | 0xFE) {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    if !inserted {
+        output.extend_from_slice(&added);
+    }
+    output.extend_from_slice(&data[pos..]);
+    Ok(output)
+}
+
+fn push_jpeg_segment(output: &mut Vec<u8>, marker: u8, payload: &[u8]) -> anyhow::Result<()> {
+    let length = u16::try_from(payload.len() + 2).map_err(|_| anyhow::anyhow!("JPEG segment too large"))?;
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.extend_from_slice(payload);
+    Ok(())
+}
+
+fn rewrite_png(data: &[u8], xmp: Option<&str>, icc: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        anyhow::bail!("Not a PNG");
+    }
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let end = pos + 12 + length;
+        if end > data.len() {
+            anyhow::bail!("Truncated PNG chunk");
+        }
+        // Color space chunks are replaced by the profile, if any.
+        if !matches!(kind, b"iCCP" | b"sRGB" | b"gAMA" | b"cHRM" | b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        // Both must come before the image data, so they go straight after the header.
+        if kind == b"IHDR" {
+            if let Some(icc) = icc {
+                let mut compressed = ZlibEncoder::new(b"sRGB\0\0".to_vec(), Compression::default());
+                compressed.write_all(icc)?;
+                push_png_chunk(&mut output, b"iCCP", &compressed.finish()?);
+            }
+            if let Some(xmp) = xmp {
+                push_png_chunk(&mut output, b"iTXt", &[b"XML:com.adobe.xmp\0\0\0\0\0".as_slice(), xmp.as_bytes()].concat());
+            }
+        }
+        pos = end;
+    }
+    Ok(output)
+}
+
+fn push_png_chunk(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(payload);
+    output.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(payload);
+    output.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// VP8X feature flags.
+const WEBP_ICC: u8 = 0x20;
+const WEBP_ALPHA: u8 = 0x10;
+const WEBP_EXIF: u8 = 0x08;
+const WEBP_XMP: u8 = 0x04;
+
+fn rewrite_webp(data: &[u8], xmp: Option<&str>, icc: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        anyhow::bail!("Not a WebP");
+    }
+    let mut extended = None;
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let kind: [u8; 4] = data[pos..pos + 4].try_into()?;
+        let length = u32::from_le_bytes(data[pos + 4..pos + 8].try_into()?) as usize;
+        let end = pos + 8 + length;
+        if end > data.len() {
+            anyhow::bail!("Truncated WebP chunk");
+        }
+        let payload = &data[pos + 8..end];
+        match &kind {
+            b"VP8X" if length >= 10 => extended = Some(payload.to_vec()),
+            b"ICCP" | b"EXIF" | b"XMP " => {}
+            _ => chunks.push((kind, payload)),
+        }
+        pos = end + length % 2;
+    }
+
+    // Metadata needs the extended format; a simple file gets a header describing its one frame.
+    let mut header = match extended {
+        Some(header) => Some(header),
+        None if xmp.is_some() || icc.is_some() => Some(simple_webp_header(&chunks)?),
+        None => None,
+    };
+    if let Some(header) = &mut header {
+        header[0] &= !(WEBP_ICC | WEBP_EXIF | WEBP_XMP);
+        if icc.is_some() {
+            header[0] |= WEBP_ICC;
+        }
+        if xmp.is_some() {
+            header[0] |= WEBP_XMP;
+        }
+    }
+
+    let mut body = Vec::with_capacity(data.len());
+    if let Some(header) = &header {
+        push_webp_chunk(&mut body, b"VP8X", header);
+    }
+    if let Some(icc) = icc {
+        push_webp_chunk(&mut body, b"ICCP", icc);
+    }
+    for (kind, payload) in chunks {
+        push_webp_chunk(&mut body, &kind, payload);
+    }
+    if let Some(xmp) = xmp {
+        push_webp_chunk(&mut body, b"XMP ", xmp.as_bytes());
+    }
+    let mut output = Vec::with_capacity(body.len() + 12);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&body);
+    Ok(output)
+}
+
+/// A VP8X payload for a file holding a single `VP8 ` or `VP8L` frame.
+fn simple_webp_header(chunks: &[([u8; 4], &[u8])]) -> anyhow::Result<Vec<u8>> {
+    let (width, height, alpha) = match chunks.first().map(|(kind, frame)| (kind, *frame)) {
+        // A keyframe header: frame tag, start code, then 14-bit dimensions.
+        Some((b"VP8 ", frame)) if frame.len() >= 10 => {
+            let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3FFF;
+            let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3FFF;
+            (width as u32, height as u32, false)
+        }
+        // A signature byte, then 14-bit dimensions less one and an alpha bit.
+        Some((b"VP8L", frame)) if frame.len() >= 5 => {
+            let bits = u32::from_le_bytes(frame[1..5].try_into()?);
+            ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, bits & (1 << 28) != 0)
+        }
+        _ => anyhow::bail!("Unsupported WebP layout"),
+    };
+    let mut header = vec![if alpha { WEBP_ALPHA } else { 0 }, 0, 0, 0];
+    header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    Ok(header)
+}
+
+fn push_webp_chunk(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(kind);
+    output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    output.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// A compact ICC v2 display profile for sRGB: the D50-adapted primaries and the sRGB tone
+/// curve sampled at 256 points. Built once, since every image gets the same bytes.
+fn srgb_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(|| {
+        let xyz = |x: f64, y: f64, z: f64| {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            for value in [x, y, z] {
+                tag.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+            }
+            tag
+        };
+        let mut description = b"desc\0\0\0\0".to_vec();
+        let name = b"sRGB\0";
+        description.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        description.extend_from_slice(name);
+        // Empty Unicode and ScriptCode descriptions.
+        description.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend_from_slice(&256u32.to_be_bytes());
+        for i in 0..256 {
+            let encoded = i as f64 / 255.0;
+            let linear = if encoded <= 0.04045 { encoded / 12.92 } else { ((encoded + 0.055) / 1.055).powf(2.4) };
+            curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+        }
+
+        let tags: [(&[u8; 4], Vec<u8>); 6] = [
+            (b"desc", description),
+            (b"cprt", b"text\0\0\0\0No copyright, use freely\0".to_vec()),
+            (b"wtpt", xyz(0.9642, 1.0, 0.8249)),
+            (b"rXYZ", xyz(0.4360747, 0.2225045, 0.0139322)),
+            (b"gXYZ", xyz(0.3850649, 0.7168786, 0.0971045)),
+            (b"bXYZ", xyz(0.1430804, 0.0606169, 0.7141733)),
+        ];
+        // The three tone curves share one copy of the data.
+        let table_len = 4 + 12 * (tags.len() + 3);
+        let mut table = ((tags.len() + 3) as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let mut add = |table: &mut Vec<u8>, signatures: &[&[u8; 4]], tag: &[u8]| {
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            let offset = (128 + table_len + data.len()) as u32;
+            for signature in signatures {
+                table.extend_from_slice(*signature);
+                table.extend_from_slice(&offset.to_be_bytes());
+                table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            }
+            data.extend_from_slice(tag);
+        };
+        for (signature, tag) in &tags {
+            add(&mut table, &[*signature], tag);
+        }
+        add(&mut table, &[b"rTRC", b"gTRC", b"bTRC"], &curve);
+
+        let size = (128 + table.len() + data.len()) as u32;
+        let mut profile = Vec::with_capacity(size as usize);
+        profile.extend_from_slice(&size.to_be_bytes());
+        profile.extend_from_slice(&[0; 4]);
+        profile.extend_from_slice(&0x0210_0000u32.to_be_bytes());
+        profile.extend_from_slice(b"mntrRGB XYZ ");
+        // Creation date: 2024-01-01 00:00:00.
+        for field in [2024u16, 1, 1, 0, 0, 0] {
+            profile.extend_from_slice(&field.to_be_bytes());
+        }
+        profile.extend_from_slice(b"acsp");
+        profile.extend_from_slice(&[0; 24]);
+        // Perceptual intent, then the D50 illuminant.
+        profile.extend_from_slice(&0u32.to_be_bytes());
+        for value in [0.9642f64, 1.0, 0.8249] {
+            profile.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+        }
+        profile.resize(128, 0);
+        profile.extend_from_slice(&table);
+        profile.extend_from_slice(&data);
+        profile
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    fn embed(provenance: bool, srgb_profile: bool) -> Embed {
+        Embed { source_url: "https://example.com/?a=1&b=<2>".to_string(), captured_at: 1_700_000_000, provenance, srgb_profile }
+    }
+
+    fn sample() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(16, 8, |x, y| image::Rgb([x as u8 * 16, y as u8 * 32, 128])))
+    }
+
+    fn encoded(format: image::ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        sample().write_to(&mut std::io::Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn xmp_escapes_the_source_url() {
+        let xmp = xmp_packet(&embed(true, false));
+        assert!(xmp.contains("dc:source=\"https://example.com/?a=1&amp;b=&lt;2&gt;\""));
+        assert!(xmp.contains("xmp:CreateDate=\"2023-11-14T22:13:20Z\""));
+    }
+
+    #[test]
+    fn jpeg_loses_exif_and_comments_and_gains_xmp_and_icc() {
+        let original = encoded(image::ImageFormat::Jpeg);
+        let mut data = original[..2].to_vec();
+        push_jpeg_segment(&mut data, 0xE1, b"Exif\0\0camera").unwrap();
+        push_jpeg_segment(&mut data, 0xFE, b"a comment").unwrap();
+        data.extend_from_slice(&original[2..]);
+
+        let stripped = rewrite(&data, ImageFormat::Jpeg, &embed(false, false)).unwrap();
+        assert!(!contains(&stripped, b"Exif") && !contains(&stripped, b"a comment"));
+
+        let rewritten = rewrite(&data, ImageFormat::Jpeg, &embed(true, true)).unwrap();
+        assert!(!contains(&rewritten, b"Exif"));
+        assert!(contains(&rewritten, b"http://ns.adobe.com/xap/1.0/\0"));
+        assert!(contains(&rewritten, b"ICC_PROFILE\0"));
+        assert_eq!(image::load_from_memory(&rewritten).unwrap().to_rgb8().dimensions(), (16, 8));
+    }
+
+    #[test]
+    fn png_loses_text_chunks_and_gains_xmp_and_icc() {
+        let original = encoded(image::ImageFormat::Png);
+        // The signature and IHDR chunk take the first 33 bytes.
+        let mut data = original[..33].to_vec();
+        push_png_chunk(&mut data, b"tEXt", b"Comment\0secret");
+        push_png_chunk(&mut data, b"gAMA", &45455u32.to_be_bytes());
+        data.extend_from_slice(&original[33..]);
+
+        let rewritten = rewrite(&data, ImageFormat::Png, &embed(true, true)).unwrap();
+        assert!(!contains(&rewritten, b"tEXt") && !contains(&rewritten, b"gAMA"));
+        assert!(contains(&rewritten, b"iCCP") && contains(&rewritten, b"XML:com.adobe.xmp"));
+        assert_eq!(image::load_from_memory(&rewritten).unwrap().to_rgb8(), sample().to_rgb8());
+    }
+
+    #[test]
+    fn webp_gets_an_extended_header_for_its_metadata() {
+        let rgba = sample().to_rgba8();
+        let data = webp::Encoder::from_rgba(&rgba, 16, 8).encode(80.0).to_vec();
+
+        let rewritten = rewrite(&data, ImageFormat::Webp, &embed(true, true)).unwrap();
+        assert_eq!(&rewritten[12..16], b"VP8X");
+        assert_eq!(rewritten[20] & (WEBP_ICC | WEBP_XMP), WEBP_ICC | WEBP_XMP);
+        assert!(contains(&rewritten, b"ICCP") && contains(&rewritten, b"XMP "));
+        let decoded = webp::Decoder::new(&rewritten).decode().unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 8));
+
+        // Rewriting again drops what the first pass added.
+        let again = rewrite(&rewritten, ImageFormat::Webp, &embed(false, true)).unwrap();
+        assert!(!contains(&again, b"XMP "));
+        assert_eq!(again[20] & (WEBP_ICC | WEBP_XMP), WEBP_ICC);
+    }
+
+    #[test]
+    fn rejects_data_in_another_format() {
+        let png = encoded(image::ImageFormat::Png);
+        assert!(rewrite(&png, ImageFormat::Jpeg, &embed(true, false)).is_err());
+        assert!(rewrite(&png, ImageFormat::Webp, &embed(true, false)).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use url::Url;
+
+use crate::config::UrlConfig;
+
+/// Rewrites `url` into the form it's cached and rendered under. Parsing converts the host to
+/// punycode, lowercases it, drops a default port, and resolves dot segments; the fragment and
+/// tracking parameters go as configured. URLs that don't parse are returned unchanged for
+/// validation to reject.
+pub fn normalize(url: &str, config: &UrlConfig) -> String {
+    let Ok(mut parsed) = Url::parse(url.trim()) else {
+        return url.to_string();
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return url.to_string();
+    }
+    if config.strip_fragment {
+        parsed.set_fragment(None);
+    }
+    if config.strip_tracking_params {
+        if let Some(query) = parsed.query() {
+            // Kept parameters stay exactly as sent; re-encoding them could change what the site sees.
+            let kept: Vec<&str> = query
+                .split('&')
+                .filter(|pair| !pair.is_empty() && !is_tracking(pair, &config.tracking_params))
+                .collect();
+            let kept = kept.join("&");
+            parsed.set_query((!kept.is_empty()).then_some(kept.as_str()));
+        }
+    }
+    parsed.into()
+}
+
+fn is_tracking(pair: &str, patterns: &[String]) -> bool {
+    let name = pair.split('=').next().unwrap_or_default();
+    patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_tracking() -> UrlConfig {
+        UrlConfig { strip_tracking_params: true, ..UrlConfig::default() }
+    }
+
+    #[test]
+    fn canonicalizes_host_port_and_path() {
+        let config = UrlConfig::default();
+        assert_eq!(normalize("HTTPS://Example.COM:443/a/../b", &config), "https://example.com/b");
+        assert_eq!(normalize("http://example.com:8080", &config), "http://example.com:8080/");
+        assert_eq!(normalize("  https://example.com  ", &config), "https://example.com/");
+        assert_eq!(normalize("https://bücher.example/", &config), "https://xn--bcher-kva.example/");
+    }
+
+    #[test]
+    fn strips_the_fragment_unless_turned_off() {
+        assert_eq!(normalize("https://example.com/#top", &UrlConfig::default()), "https://example.com/");
+        let keep = UrlConfig { strip_fragment: false, ..UrlConfig::default() };
+        assert_eq!(normalize("https://example.com/#/route", &keep), "https://example.com/#/route");
+    }
+
+    #[test]
+    fn strips_tracking_params_only_when_asked() {
+        let url = "https://example.com/?utm_source=x&id=1&fbclid=2";
+        assert_eq!(normalize(url, &UrlConfig::default()), url);
+        assert_eq!(normalize(url, &with_tracking()), "https://example.com/?id=1");
+        assert_eq!(normalize("https://example.com/?utm_medium=a&gclid=b", &with_tracking()), "https://example.com/");
+    }
+
+    #[test]
+    fn keeps_other_params_as_sent() {
+        assert_eq!(normalize("https://example.com/?q=a%20b&&utm_x=1", &with_tracking()), "https://example.com/?q=a%20b");
+        assert_eq!(normalize("https://example.com/?utm=1", &with_tracking()), "https://example.com/?utm=1");
+    }
+
+    #[test]
+    fn leaves_urls_it_does_not_render_alone() {
+        let config = UrlConfig::default();
+        assert_eq!(normalize("ftp://example.com/x#y", &config), "ftp://example.com/x#y");
+        assert_eq!(normalize("not a url", &config), "not a url");
+    }
+}
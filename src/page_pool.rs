@@ -0,0 +1,210 @@
+use chromiumoxide::browser::Browser;
+use chromiumoxide::cdp::browser_protocol::browser::BrowserContextId;
+use chromiumoxide::cdp::browser_protocol::storage::ClearDataForOriginParams;
+use chromiumoxide::cdp::browser_protocol::target::{CreateBrowserContextParams, CreateTargetParams};
+use chromiumoxide::Page;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration, Instant};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::lockdown;
+
+/// How many pages to keep warm and when to replace them.
+#[derive(Debug, Clone)]
+pub struct PagePoolSettings {
+    /// Idle pages kept open; zero opens and closes a page per render.
+    pub size: usize,
+    pub max_uses: u32,
+    pub max_age: Duration,
+}
+
+/// A page checked out of the pool.
+pub struct PooledPage {
+    pub page: Page,
+    uses: u32,
+    created: Instant,
+    /// The browser context the page was opened in by `acquire_isolated`, disposed with it.
+    context: Option<BrowserContextId>,
+}
+
+/// Keeps `about:blank` pages open so renders skip page creation, resetting them between uses
+/// and recycling them after `max_uses` renders or `max_age` to bound leaks in long-lived pages.
+pub struct PagePool {
+    browser: Arc<Mutex<Browser>>,
+    settings: PagePoolSettings,
+    idle: std::sync::Mutex<Vec<PooledPage>>,
+}
+
+impl PagePool {
+    pub fn new(browser: Arc<Mutex<Browser>>, settings: PagePoolSettings) -> Arc<Self> {
+        Arc::new(Self {
+            browser,
+            settings,
+            idle: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Fills the pool up to its size in the background.
+    pub fn warm(self: &Arc<Self>) {
+        let missing = self.settings.size.saturating_sub(self.idle_count());
+        for _ in 0..missing {
+            let pool = self.clone();
+            tokio::spawn(async move {
+                match pool.open().await {
+                    Ok(page) => pool.put_back(page).await,
+                    Err(e) => warn!("Failed to warm page: {}", e),
+                }
+            });
+        }
+    }
+
+    /// Forgets all idle pages, e.g. after the browser they belong to went away.
+    pub fn clear(&self) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Takes an idle page, or opens a new one if none is ready.
+    pub async fn acquire(&self) -> anyhow::Result<PooledPage> {
+        let pooled = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        match pooled {
+            Some(page) => Ok(page),
+            None => self.open().await,
+        }
+    }
+
+    /// Opens a page in a browser context of its own, sharing no cookies or storage with other
+    /// pages, e.g. for renders that log in. It's never returned to the pool.
+    pub async fn acquire_isolated(&self) -> anyhow::Result<PooledPage> {
+        let browser = timeout(Duration::from_secs(10), self.browser.lock())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout acquiring browser lock"))?;
+        let context = timeout(Duration::from_secs(10), browser.create_browser_context(CreateBrowserContextParams::default()))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout creating browser context"))?
+            .map_err(|e| anyhow::anyhow!("Failed to create browser context: {}", e))?;
+        let target = CreateTargetParams::builder()
+            .url("about:blank")
+            .browser_context_id(context.clone())
+            .build()
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let page = match timeout(Duration::from_secs(15), browser.new_page(target)).await {
+            Ok(Ok(page)) => page,
+            Ok(Err(e)) => {
+                let _ = browser.dispose_browser_context(context).await;
+                return Err(anyhow::anyhow!("Failed to create page: {}", e));
+            }
+            Err(_) => {
+                let _ = browser.dispose_browser_context(context).await;
+                return Err(anyhow::anyhow!("Timeout creating page"));
+            }
+        };
+        drop(browser);
+        let pooled = PooledPage { page, uses: 0, created: Instant::now(), context: Some(context) };
+        if let Err(e) = lockdown::guard_page(&pooled.page).await {
+            self.discard_isolated(pooled).await;
+            return Err(e);
+        }
+        Ok(pooled)
+    }
+
+    /// Closes an isolated page and throws away its browser context with everything in it.
+    async fn discard_isolated(&self, pooled: PooledPage) {
+        close(pooled.page).await;
+        if let Some(context) = pooled.context {
+            let browser = self.browser.lock().await;
+            if timeout(Duration::from_secs(5), browser.dispose_browser_context(context)).await.is_err() {
+                warn!("Timeout disposing browser context");
+            }
+        }
+    }
+
+    /// Resets a used page and returns it to the pool, or closes it if it's worn out or broken.
+    /// `visited` is the URL that was rendered, whose storage is cleared.
+    pub async fn release(self: &Arc<Self>, mut pooled: PooledPage, visited: &str, reusable: bool) {
+        if pooled.context.is_some() {
+            self.discard_isolated(pooled).await;
+            return;
+        }
+        pooled.uses += 1;
+        let worn_out = pooled.uses >= self.settings.max_uses || pooled.created.elapsed() >= self.settings.max_age;
+        if !reusable || worn_out || self.settings.size == 0 {
+            debug!("Closing page after {} uses", pooled.uses);
+            close(pooled.page).await;
+            self.warm();
+            return;
+        }
+
+        match reset(&pooled.page, visited).await {
+            Ok(()) => self.put_back(pooled).await,
+            Err(e) => {
+                warn!("Failed to reset page, closing it: {}", e);
+                close(pooled.page).await;
+                self.warm();
+            }
+        }
+    }
+
+    async fn open(&self) -> anyhow::Result<PooledPage> {
+        let browser = timeout(Duration::from_secs(10), self.browser.lock())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout acquiring browser lock"))?;
+        let page = timeout(Duration::from_secs(15), browser.new_page("about:blank"))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout creating page"))?
+            .map_err(|e| anyhow::anyhow!("Failed to create page: {}", e))?;
+        if let Err(e) = lockdown::guard_page(&page).await {
+            close(page).await;
+            return Err(e);
+        }
+        Ok(PooledPage { page, uses: 0, created: Instant::now(), context: None })
+    }
+
+    async fn put_back(&self, pooled: PooledPage) {
+        let surplus = {
+            let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+            if idle.len() < self.settings.size {
+                idle.push(pooled);
+                None
+            } else {
+                Some(pooled)
+            }
+        };
+        if let Some(pooled) = surplus {
+            close(pooled.page).await;
+        }
+    }
+
+    fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// Clears what the visited site stored and navigates back to a blank document.
+async fn reset(page: &Page, visited: &str) -> anyhow::Result<()> {
+    let mut origins = Vec::new();
+    for url in [Some(visited.to_string()), page.url().await.ok().flatten()].into_iter().flatten() {
+        if let Ok(url) = Url::parse(&url) {
+            let origin = url.origin().ascii_serialization();
+            if origin != "null" && !origins.contains(&origin) {
+                origins.push(origin);
+            }
+        }
+    }
+
+    for origin in origins {
+        timeout(Duration::from_secs(5), page.execute(ClearDataForOriginParams::new(origin, "all")))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout clearing storage"))??;
+    }
+
+    timeout(Duration::from_secs(5), page.goto("about:blank"))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timeout navigating to about:blank"))??;
+    Ok(())
+}
+
+async fn close(page: Page) {
+    let _ = timeout(Duration::from_secs(5), page.close()).await;
+}
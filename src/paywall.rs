@@ -0,0 +1,62 @@
+use chromiumoxide::Page;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, warn};
+
+/// Reports why the page looks paywalled or login-walled, or `null`. Overlays only count while
+/// visible; structured-data and meta markers count regardless, since metered pages often show
+/// the article first and the wall later.
+const DETECT_SCRIPT: &str = r#"
+    (() => {
+        const visible = el => {
+            const rect = el.getBoundingClientRect();
+            const style = getComputedStyle(el);
+            return rect.width > 0 && rect.height > 0 && style.display !== 'none' && style.visibility !== 'hidden';
+        };
+        const overlays = [
+            '.tp-modal', '.tp-backdrop', '.piano-offer', '#paywall', '[class*="paywall"]', '[id*="paywall"]',
+            '[class*="regwall"]', '[class*="login-wall"]', '[class*="loginwall"]', '[data-testid*="paywall"]',
+            '.meteredContent',
+        ];
+        for (const selector of overlays) {
+            if ([...document.querySelectorAll(selector)].some(visible)) return 'overlay ' + selector;
+        }
+
+        for (const script of document.querySelectorAll('script[type="application/ld+json"]')) {
+            if (/"isAccessibleForFree"\s*:\s*"?false"?/i.test(script.textContent)) return 'isAccessibleForFree';
+        }
+        const tier = document.querySelector('meta[property="article:content_tier"], meta[name="article:content_tier"]');
+        if (tier && /locked|metered/i.test(tier.content)) return 'content_tier ' + tier.content;
+
+        // A password field in a fixed layer covering most of the viewport is a login wall.
+        const area = innerWidth * innerHeight;
+        for (const input of document.querySelectorAll('input[type="password"]')) {
+            if (!visible(input)) continue;
+            for (let el = input; el; el = el.parentElement) {
+                const rect = el.getBoundingClientRect();
+                if (getComputedStyle(el).position === 'fixed' && rect.width * rect.height > area / 2) return 'login overlay';
+            }
+        }
+        return null;
+    })()
+"#;
+
+/// Whether the loaded page shows a paywall or login wall, or marks its content as paid.
+pub async fn detect(page: &Page, url: &str) -> bool {
+    match timeout(Duration::from_secs(5), page.evaluate(DETECT_SCRIPT)).await {
+        Ok(Ok(result)) => match result.value().and_then(|v| v.as_str().map(str::to_string)) {
+            Some(reason) => {
+                debug!("{} looks paywalled: {}", url, reason);
+                true
+            }
+            None => false,
+        },
+        Ok(Err(e)) => {
+            warn!("Paywall detection failed on {}: {}", url, e);
+            false
+        }
+        Err(_) => {
+            warn!("Timeout detecting a paywall on {}", url);
+            false
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::config::PolitenessConfig;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Hosts tracked before idle entries are pruned.
+const MAX_TRACKED_HOSTS: usize = 10_000;
+
+struct HostState {
+    semaphore: Arc<Semaphore>,
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+/// Throttles outbound renders per target host, so no single site sees a burst of page loads.
+pub struct HostLimiter {
+    max_concurrent: usize,
+    max_per_minute: usize,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+/// Held for the duration of a render; releases the host's concurrency slot on drop.
+pub struct HostPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl HostLimiter {
+    pub fn new(config: &PolitenessConfig) -> Self {
+        Self {
+            max_concurrent: config.max_concurrent_per_host,
+            max_per_minute: config.max_requests_per_minute_per_host,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until `host` has a free concurrency slot and room in its per-minute window.
+    pub async fn acquire(&self, host: &str) -> anyhow::Result<HostPermit> {
+        let state = self.state_for(host).await;
+
+        let permit = if self.max_concurrent > 0 {
+            Some(state.semaphore.clone().acquire_owned().await?)
+        } else {
+            None
+        };
+
+        if self.max_per_minute > 0 {
+            loop {
+                let mut recent = state.recent.lock().await;
+                let now = Instant::now();
+                while recent.front().is_some_and(|t| now.duration_since(*t) >= WINDOW) {
+                    recent.pop_front();
+                }
+                if recent.len() < self.max_per_minute {
+                    recent.push_back(now);
+                    break;
+                }
+                let wait_until = recent[0] + WINDOW;
+                drop(recent);
+                debug!("Rate limit reached for {}, waiting {:?}", host, wait_until - now);
+                tokio::time::sleep_until(wait_until).await;
+            }
+        }
+
+        Ok(HostPermit { _permit: permit })
+    }
+
+    async fn state_for(&self, host: &str) -> Arc<HostState> {
+        let mut hosts = self.hosts.lock().await;
+        if hosts.len() >= MAX_TRACKED_HOSTS && !hosts.contains_key(host) {
+            let max_concurrent = self.max_concurrent;
+            hosts.retain(|_, state| {
+                let idle = max_concurrent == 0 || state.semaphore.available_permits() == max_concurrent;
+                let quiet = state
+                    .recent
+                    .try_lock()
+                    .map(|recent| recent.iter().all(|t| t.elapsed() >= WINDOW))
+                    .unwrap_or(false);
+                !(idle && quiet)
+            });
+        }
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                Arc::new(HostState {
+                    semaphore: Arc::new(Semaphore::new(self.max_concurrent.max(1))),
+                    recent: Mutex::new(VecDeque::new()),
+                })
+            })
+            .clone()
+    }
+}
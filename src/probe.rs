@@ -0,0 +1,142 @@
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::thumbnail::{BasicAuth, USER_AGENT};
+
+/// Pages larger than this are always re-rendered rather than hashed.
+const MAX_PROBE_BYTES: usize = 5 * 1024 * 1024;
+/// Largest `og:image` used in place of a paywalled capture.
+const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Fetches pages and images without a browser: a page's HTML, to tell whether it changed since
+/// it was rendered, and `og:image`s to stand in for captures.
+pub struct ContentProbe {
+    client: reqwest::Client,
+    /// For requests that render despite certificate errors.
+    insecure_client: reqwest::Client,
+}
+
+/// A response fetched for a thumbnail made without a browser.
+pub struct FetchedPage {
+    /// The media type without parameters, lowercased.
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl ContentProbe {
+    pub fn new() -> anyhow::Result<Self> {
+        let builder = || {
+            reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(10))
+                // Redirects are hashed rather than followed, so the probe only ever contacts the requested host.
+                .redirect(reqwest::redirect::Policy::none())
+        };
+        Ok(Self {
+            client: builder().build()?,
+            insecure_client: builder().danger_accept_invalid_certs(true).build()?,
+        })
+    }
+
+    /// Returns the lowercase hex SHA-256 of the response status, redirect target, and body, and
+    /// the body's length, or `None` when the page couldn't be fetched in full.
+    pub async fn content_hash(&self, url: &str, credentials: Option<&BasicAuth>, insecure: bool) -> Option<(String, u64)> {
+        let client = if insecure { &self.insecure_client } else { &self.client };
+        let mut request = client.get(url);
+        if let Some(auth) = credentials {
+            request = request.basic_auth(&auth.username, Some(&auth.password));
+        }
+        let mut response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Content probe of {} failed: {}", url, e);
+                return None;
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(response.status().as_u16().to_be_bytes());
+        if let Some(location) = response.headers().get(reqwest::header::LOCATION) {
+            hasher.update(location.as_bytes());
+        }
+        hasher.update(b"\0");
+
+        let read = read_body(url, &mut response, MAX_PROBE_BYTES, |chunk| hasher.update(chunk)).await?;
+        Some((format!("{:x}", hasher.finalize()), read))
+    }
+
+    /// Downloads an image, or returns `None` if it fails, redirects, or is too large.
+    pub async fn fetch_image(&self, url: &str, insecure: bool) -> Option<Vec<u8>> {
+        let client = if insecure { &self.insecure_client } else { &self.client };
+        let mut response = match client.get(url).send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                debug!("Fetching image {} returned {}", url, response.status());
+                return None;
+            }
+            Err(e) => {
+                debug!("Fetching image {} failed: {}", url, e);
+                return None;
+            }
+        };
+        let mut image = Vec::new();
+        read_body(url, &mut response, MAX_IMAGE_BYTES, |chunk| image.extend_from_slice(chunk)).await?;
+        Some(image)
+    }
+
+    /// Downloads a page or image to make a thumbnail from without a browser, or returns `None`
+    /// if it fails, redirects, or is too large.
+    pub async fn fetch_page(&self, url: &str, credentials: Option<&BasicAuth>, insecure: bool) -> Option<FetchedPage> {
+        let client = if insecure { &self.insecure_client } else { &self.client };
+        let mut request = client.get(url);
+        if let Some(auth) = credentials {
+            request = request.basic_auth(&auth.username, Some(&auth.password));
+        }
+        let mut response = match request.send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                debug!("Fetching {} returned {}", url, response.status());
+                return None;
+            }
+            Err(e) => {
+                debug!("Fetching {} failed: {}", url, e);
+                return None;
+            }
+        };
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let limit = if content_type.starts_with("image/") { MAX_IMAGE_BYTES } else { MAX_PROBE_BYTES };
+        let mut body = Vec::new();
+        read_body(url, &mut response, limit, |chunk| body.extend_from_slice(chunk)).await?;
+        Some(FetchedPage { content_type, body })
+    }
+}
+
+/// Feeds the body to `sink` chunk by chunk, giving up past `limit` bytes. Returns the bytes read.
+async fn read_body(url: &str, response: &mut reqwest::Response, limit: usize, mut sink: impl FnMut(&[u8])) -> Option<u64> {
+    let mut read = 0;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                read += chunk.len();
+                if read > limit {
+                    debug!("Stopped reading {} at {} bytes", url, limit);
+                    return None;
+                }
+                sink(&chunk);
+            }
+            Ok(None) => return Some(read as u64),
+            Err(e) => {
+                debug!("Failed reading {}: {}", url, e);
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,954 @@
+use ab_glyph::{point, Font, FontRef, Glyph, PxScale, ScaleFont};
+use image::{DynamicImage, Pixel, Rgba, RgbaImage};
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::metadata::{self, Embed};
+use crate::server::AppError;
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Webp,
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "webp",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Corner::TopLeft => "top-left",
+            Corner::TopRight => "top-right",
+            Corner::BottomLeft => "bottom-left",
+            Corner::BottomRight => "bottom-right",
+        }
+    }
+}
+
+/// Matches the quality the `image` crate used before JPEG moved to `jpeg-encoder`.
+const JPEG_QUALITY: u8 = 75;
+const WEBP_QUALITY: u8 = 80;
+/// Quality is lowered in these steps when fitting `max_bytes`, down to `MIN_QUALITY`.
+const QUALITY_STEP: usize = 10;
+const MIN_QUALITY: u8 = 20;
+/// Scales tried in turn when a size budget allows downscaling and no quality fits.
+const DOWNSCALE_STEPS: [f64; 4] = [0.85, 0.7, 0.55, 0.4];
+/// Longer side of the copy that smart cropping measures detail on.
+const SALIENCY_SIZE: u32 = 256;
+/// Side of the square blocks whose entropy is measured.
+const SALIENCY_BLOCK: u32 = 8;
+/// Relative spread in window scores below which no region counts as standing out.
+const FLAT_SALIENCY: f64 = 0.05;
+
+const BADGE_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf");
+
+/// An RGBA color written as `#rrggbb` or `#rrggbbaa`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub Rgba<u8>);
+
+impl Color {
+    pub const WHITE: Color = Color(Rgba([255, 255, 255, 255]));
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !hex.is_ascii() || (hex.len() != 6 && hex.len() != 8) {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+        Some(Color(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha])))
+    }
+
+    pub fn to_hex(self) -> String {
+        let [r, g, b, a] = self.0 .0;
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Color::parse(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid color: {}", s)))
+    }
+}
+
+/// Fill used for `contain` padding and for flattening transparency into JPEG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Transparent,
+    Color(Color),
+}
+
+impl Background {
+    pub fn as_rgba(self) -> Rgba<u8> {
+        match self {
+            Background::Transparent => Rgba([0, 0, 0, 0]),
+            Background::Color(color) => color.0,
+        }
+    }
+
+    pub fn cache_key(self) -> String {
+        match self {
+            Background::Transparent => "transparent".to_string(),
+            Background::Color(color) => color.to_hex(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Background {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.eq_ignore_ascii_case("transparent") {
+            return Ok(Background::Transparent);
+        }
+        Color::parse(&s)
+            .map(Background::Color)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid background: {}", s)))
+    }
+}
+
+/// How the capture is mapped onto the requested dimensions.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit within the box, keeping aspect ratio; output may be smaller than requested.
+    #[default]
+    Inside,
+    /// Scale to fit within the box and pad to the exact size with the background.
+    Contain,
+    /// Scale to fill the box and crop the overflow, keeping the top of the page unless
+    /// `smart_crop` picks a focal region.
+    Cover,
+}
+
+impl Fit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fit::Inside => "inside",
+            Fit::Contain => "contain",
+            Fit::Cover => "cover",
+        }
+    }
+}
+
+/// A logo loaded once at startup and composited onto outputs on request.
+pub struct Watermark {
+    image: RgbaImage,
+}
+
+impl Watermark {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let image = image::open(path.as_ref())
+            .map_err(|e| anyhow::anyhow!("Failed to load watermark {:?}: {}", path.as_ref(), e))?
+            .to_rgba8();
+        Ok(Self { image })
+    }
+}
+
+/// Stand-in drawn when a page can't be rendered: the site's initial and domain on its brand
+/// color, under an optional template loaded once at startup.
+pub struct Placeholder {
+    template: Option<RgbaImage>,
+    /// Used when the site's brand color is unknown.
+    color: Color,
+}
+
+impl Placeholder {
+    pub fn load(template: Option<&Path>, color: Color) -> anyhow::Result<Self> {
+        let template = template
+            .map(|path| {
+                image::open(path)
+                    .map(|img| img.to_rgba8())
+                    .map_err(|e| anyhow::anyhow!("Failed to load placeholder template {:?}: {}", path, e))
+            })
+            .transpose()?;
+        Ok(Self { template, color })
+    }
+}
+
+pub struct WatermarkPlacement {
+    pub watermark: Arc<Watermark>,
+    pub corner: Corner,
+    pub opacity: f32,
+    pub margin: u32,
+}
+
+/// A short text label drawn with the bundled font, optionally on a rounded pill.
+pub struct Badge {
+    pub text: String,
+    pub corner: Corner,
+    pub size: f32,
+    pub margin: u32,
+    pub text_color: Color,
+    pub background: Option<Color>,
+}
+
+/// Adjustments applied after resizing and before any overlays.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Filters {
+    /// Unsharp-mask radius (sigma); restores edge contrast lost when downscaling text.
+    pub sharpen: Option<f32>,
+    /// Contrast change in percent; negative values reduce contrast.
+    pub contrast: Option<f32>,
+    pub grayscale: bool,
+    /// Gaussian blur sigma.
+    pub blur: Option<f32>,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.sharpen.is_none() && self.contrast.is_none() && !self.grayscale && self.blur.is_none()
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!(
+            "s{},c{},g{},b{}",
+            self.sharpen.unwrap_or(0.0),
+            self.contrast.unwrap_or(0.0),
+            self.grayscale as u8,
+            self.blur.unwrap_or(0.0),
+        )
+    }
+}
+
+/// Format-specific encoder switches; each is ignored for other formats.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EncodeOptions {
+    /// Emit a progressive rather than baseline JPEG.
+    pub progressive: bool,
+    /// Encode WebP losslessly instead of the default lossy mode.
+    pub lossless: bool,
+}
+
+/// An upper bound on the encoded size.
+#[derive(Debug, Clone, Copy)]
+pub struct SizeBudget {
+    pub max_bytes: usize,
+    /// Shrink the image once even the lowest quality is too large.
+    pub downscale: bool,
+}
+
+/// An encoded thumbnail.
+pub struct Encoded {
+    pub data: Vec<u8>,
+    /// The lossy quality chosen to fit a size budget.
+    pub quality: Option<u8>,
+}
+
+#[derive(Default)]
+pub struct ProcessOptions {
+    pub encode: EncodeOptions,
+    pub size_budget: Option<SizeBudget>,
+    pub fit: Fit,
+    /// With `Fit::Cover`, crop toward the most detailed region instead of the top center.
+    pub smart_crop: bool,
+    /// Defaults to transparent padding, and to white when flattening JPEG.
+    pub background: Option<Background>,
+    pub filters: Filters,
+    pub watermark: Option<WatermarkPlacement>,
+    pub badge: Option<Badge>,
+    /// Metadata to write into the output; encoders write none otherwise.
+    pub metadata: Option<Embed>,
+    /// How many times the requested size the capture was taken at; fitting scales it back down.
+    pub oversample: Option<f32>,
+}
+
+pub fn process_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: &ImageFormat,
+    options: &ProcessOptions,
+) -> Result<Encoded, AppError> {
+    if data.is_empty() {
+        return Err(AppError::ImageProcessing("Empty image data".to_string()));
+    }
+
+    let img = image::load_from_memory(data)
+        .map_err(|e| AppError::ImageProcessing(format!("Failed to load image: {}", e)))?;
+
+    let mut resized = fit_image(img, width, height, options);
+
+    if !options.filters.is_empty() {
+        resized = apply_filters(resized, &options.filters);
+    }
+
+    if let Some(placement) = &options.watermark {
+        resized = apply_watermark(resized, placement);
+    }
+
+    if let Some(badge) = &options.badge {
+        resized = apply_badge(resized, badge)?;
+    }
+
+    let output = match &options.size_budget {
+        Some(budget) => encode_within(resized, format, options, budget)?,
+        None => Encoded {
+            data: encode(&resized, format, options, default_quality(format))?,
+            quality: None,
+        },
+    };
+
+    if output.data.is_empty() {
+        return Err(AppError::ImageProcessing("Encoded image is empty".to_string()));
+    }
+
+    Ok(output)
+}
+
+/// How `compose_grid` lays tiles out: `columns` per row, `gap` pixels between tiles and around
+/// the edge, on a `background` fill.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub columns: u32,
+    pub gap: u32,
+    pub background: Background,
+}
+
+impl Grid {
+    /// Rows needed for `count` tiles, which must be at least one.
+    fn rows(&self, count: u32) -> u32 {
+        (count - 1) / self.columns + 1
+    }
+
+    /// The size each of `count` tiles gets on a `width` x `height` canvas, or `None` when the
+    /// gaps leave no room.
+    pub fn tile_size(&self, count: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let rows = self.rows(count);
+        let tile_width = width.checked_sub(self.gap * (self.columns + 1))? / self.columns;
+        let tile_height = height.checked_sub(self.gap * (rows + 1))? / rows;
+        (tile_width > 0 && tile_height > 0).then_some((tile_width, tile_height))
+    }
+}
+
+/// Composites encoded tiles into one image, row by row, centering a short last row.
+pub fn compose_grid(tiles: &[Vec<u8>], width: u32, height: u32, grid: &Grid, format: &ImageFormat) -> Result<Encoded, AppError> {
+    let count = tiles.len() as u32;
+    let (tile_width, tile_height) = grid
+        .tile_size(count, width, height)
+        .ok_or_else(|| AppError::ImageProcessing("Collage is too small for its tiles and gaps".to_string()))?;
+    let options = ProcessOptions {
+        fit: Fit::Cover,
+        background: Some(grid.background),
+        ..ProcessOptions::default()
+    };
+
+    let rows = grid.rows(count);
+    let used_height = rows * tile_height + (rows + 1) * grid.gap;
+    let mut canvas = RgbaImage::from_pixel(width, height, grid.background.as_rgba());
+    for (index, data) in tiles.iter().enumerate() {
+        let index = index as u32;
+        let tile = image::load_from_memory(data)
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to load collage tile: {}", e)))?;
+        let tile = fit_image(tile, tile_width, tile_height, &options).to_rgba8();
+
+        let (row, column) = (index / grid.columns, index % grid.columns);
+        let in_row = (count - row * grid.columns).min(grid.columns);
+        let row_width = in_row * tile_width + (in_row + 1) * grid.gap;
+        let x = (width - row_width) / 2 + grid.gap + column * (tile_width + grid.gap);
+        let y = (height - used_height) / 2 + grid.gap + row * (tile_height + grid.gap);
+        image::imageops::overlay(&mut canvas, &tile, x as i64, y as i64);
+    }
+
+    let data = encode(&DynamicImage::ImageRgba8(canvas), format, &options, default_quality(format))?;
+    Ok(Encoded { data, quality: None })
+}
+
+/// Draws the placeholder for `domain` at the requested size. The template is scaled to cover the
+/// canvas and drawn over the brand color, so transparent areas show the color through.
+pub fn render_placeholder(
+    placeholder: &Placeholder,
+    domain: &str,
+    brand: Option<Color>,
+    width: u32,
+    height: u32,
+    format: &ImageFormat,
+) -> Result<Encoded, AppError> {
+    let color = brand.unwrap_or(placeholder.color);
+    let mut canvas = RgbaImage::from_pixel(width, height, color.0);
+    let options = ProcessOptions {
+        fit: Fit::Cover,
+        background: Some(Background::Color(color)),
+        ..ProcessOptions::default()
+    };
+    if let Some(template) = &placeholder.template {
+        let template = fit_image(DynamicImage::ImageRgba8(template.clone()), width, height, &options).to_rgba8();
+        image::imageops::overlay(&mut canvas, &template, 0, 0);
+    }
+
+    let [r, g, b, _] = color.0 .0;
+    let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+    let text_color = if luminance > 150.0 { Color(Rgba([17, 24, 39, 255])) } else { Color::WHITE };
+
+    let font = load_font()?;
+    let short_side = width.min(height) as f32;
+    let initial: String = domain.chars().find(|c| c.is_alphanumeric()).into_iter().flat_map(char::to_uppercase).collect();
+    let (glyphs, initial_width, initial_height) = layout_text(&font, &initial, short_side * 0.5);
+    let x = (width as i64 - initial_width as i64) / 2;
+    let y = (height as i64 - initial_height as i64) / 2;
+    draw_glyphs(&mut canvas, &font, glyphs, x, y, text_color);
+
+    let (glyphs, domain_width, domain_height) = layout_text(&font, domain, (short_side * 0.08).max(10.0));
+    if domain_width + 16 <= width {
+        let x = (width - domain_width) as i64 / 2;
+        let y = height as i64 - domain_height as i64 - (height / 16) as i64;
+        draw_glyphs(&mut canvas, &font, glyphs, x, y, text_color);
+    }
+
+    let data = encode(&DynamicImage::ImageRgba8(canvas), format, &options, default_quality(format))?;
+    Ok(Encoded { data, quality: None })
+}
+
+/// The most common saturated color in an icon, averaged within its bucket, or `None` for icons
+/// that are mostly transparent, white, black, or gray.
+pub fn dominant_color(data: &[u8]) -> Option<Color> {
+    let icon = image::load_from_memory(data).ok()?;
+    let icon = icon.resize(32, 32, image::imageops::FilterType::Triangle).to_rgba8();
+
+    let mut buckets: std::collections::HashMap<(u8, u8, u8), (u32, [u32; 3])> = std::collections::HashMap::new();
+    for pixel in icon.pixels() {
+        let [r, g, b, a] = pixel.0;
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        if a < 128 || max < 40 || max - min < 40 {
+            continue;
+        }
+        let entry = buckets.entry((r >> 5, g >> 5, b >> 5)).or_default();
+        entry.0 += 1;
+        entry.1[0] += r as u32;
+        entry.1[1] += g as u32;
+        entry.1[2] += b as u32;
+    }
+
+    let (count, sums) = buckets.into_values().max_by_key(|(count, _)| *count)?;
+    let channel = |sum: u32| (sum / count) as u8;
+    Some(Color(Rgba([channel(sums[0]), channel(sums[1]), channel(sums[2]), 255])))
+}
+
+fn default_quality(format: &ImageFormat) -> u8 {
+    match format {
+        ImageFormat::Webp => WEBP_QUALITY,
+        _ => JPEG_QUALITY,
+    }
+}
+
+/// Re-encodes at decreasing quality, then at decreasing size if allowed, until the output fits.
+fn encode_within(img: DynamicImage, format: &ImageFormat, options: &ProcessOptions, budget: &SizeBudget) -> Result<Encoded, AppError> {
+    let lossy = match format {
+        ImageFormat::Jpeg => true,
+        ImageFormat::Webp => !options.encode.lossless,
+        ImageFormat::Png => false,
+    };
+    let qualities: Vec<u8> = if lossy {
+        let mut qualities: Vec<u8> = (MIN_QUALITY..=default_quality(format)).rev().step_by(QUALITY_STEP).collect();
+        // The steps start from the default, so they don't necessarily land on the minimum.
+        if qualities.last() != Some(&MIN_QUALITY) {
+            qualities.push(MIN_QUALITY);
+        }
+        qualities
+    } else {
+        vec![default_quality(format)]
+    };
+    let scales = std::iter::once(1.0).chain(DOWNSCALE_STEPS.into_iter().filter(|_| budget.downscale));
+
+    let mut smallest = usize::MAX;
+    for scale in scales {
+        let scaled;
+        let candidate = if scale < 1.0 {
+            let width = ((img.width() as f64 * scale).round() as u32).max(1);
+            let height = ((img.height() as f64 * scale).round() as u32).max(1);
+            scaled = img.resize(width, height, image::imageops::FilterType::Lanczos3);
+            &scaled
+        } else {
+            &img
+        };
+        for &quality in &qualities {
+            let data = encode(candidate, format, options, quality)?;
+            if data.len() <= budget.max_bytes {
+                return Ok(Encoded { data, quality: lossy.then_some(quality) });
+            }
+            smallest = smallest.min(data.len());
+        }
+    }
+
+    Err(AppError::Render(
+        "max_bytes_unreachable",
+        format!("the smallest encoding was {} bytes, over max_bytes of {}", smallest, budget.max_bytes),
+    ))
+}
+
+fn encode(img: &DynamicImage, format: &ImageFormat, options: &ProcessOptions, quality: u8) -> Result<Vec<u8>, AppError> {
+    let mut output = Vec::new();
+    match format {
+        ImageFormat::Webp => {
+            let rgba = img.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height());
+            let encoded = if options.encode.lossless {
+                encoder.encode_lossless()
+            } else {
+                encoder.encode(quality as f32)
+            };
+            output.extend_from_slice(&encoded);
+        }
+        ImageFormat::Jpeg => {
+            let background = match options.background {
+                Some(Background::Color(color)) => color.0,
+                _ => Color::WHITE.0,
+            };
+            let rgb = flatten(img, background);
+            let width = u16::try_from(rgb.width())
+                .map_err(|_| AppError::ImageProcessing("Image too wide for JPEG".to_string()))?;
+            let height = u16::try_from(rgb.height())
+                .map_err(|_| AppError::ImageProcessing("Image too tall for JPEG".to_string()))?;
+            let mut encoder = jpeg_encoder::Encoder::new(&mut output, quality);
+            encoder.set_progressive(options.encode.progressive);
+            encoder.encode(&rgb, width, height, jpeg_encoder::ColorType::Rgb)
+                .map_err(|e| AppError::ImageProcessing(format!("JPEG encoding failed: {}", e)))?;
+        }
+        ImageFormat::Png => {
+            img.write_to(&mut std::io::Cursor::new(&mut output), image::ImageFormat::Png)
+                .map_err(|e| AppError::ImageProcessing(format!("PNG encoding failed: {}", e)))?;
+        }
+    }
+    // Embedded here so size budgets account for it.
+    if let Some(embed) = &options.metadata {
+        output = metadata::rewrite(&output, *format, embed)
+            .map_err(|e| AppError::ImageProcessing(format!("Embedding metadata failed: {}", e)))?;
+    }
+    Ok(output)
+}
+
+fn fit_image(img: DynamicImage, width: u32, height: u32, options: &ProcessOptions) -> DynamicImage {
+    let filter = image::imageops::FilterType::Lanczos3;
+    match options.fit {
+        Fit::Inside => img.resize(width, height, filter),
+        Fit::Contain => {
+            let resized = img.resize(width, height, filter).to_rgba8();
+            let background = options.background.unwrap_or(Background::Transparent).as_rgba();
+            let mut canvas = RgbaImage::from_pixel(width, height, background);
+            let x = (width - resized.width()) / 2;
+            let y = (height - resized.height()) / 2;
+            image::imageops::overlay(&mut canvas, &resized, x as i64, y as i64);
+            DynamicImage::ImageRgba8(canvas)
+        }
+        Fit::Cover => {
+            let scale = (width as f64 / img.width() as f64).max(height as f64 / img.height() as f64);
+            let scaled_width = ((img.width() as f64 * scale).round() as u32).max(width);
+            let scaled_height = ((img.height() as f64 * scale).round() as u32).max(height);
+            let scaled = img.resize_exact(scaled_width, scaled_height, filter);
+            let (x, y) = if options.smart_crop {
+                focal_crop(&scaled, width, height)
+            } else {
+                ((scaled_width - width) / 2, 0)
+            };
+            scaled.crop_imm(x, y, width, height)
+        }
+    }
+}
+
+/// Picks the origin of the `width` x `height` window holding the most detail. Each pixel is
+/// scored by edge strength plus the entropy of its surrounding block, so the crop follows people,
+/// text, and products rather than flat backgrounds. Keeps the top center when nothing stands out.
+fn focal_crop(img: &DynamicImage, width: u32, height: u32) -> (u32, u32) {
+    let default = ((img.width() - width) / 2, 0);
+    let scale = (SALIENCY_SIZE as f64 / img.width().max(img.height()) as f64).min(1.0);
+    let sample_width = ((img.width() as f64 * scale).round() as u32).max(1);
+    let sample_height = ((img.height() as f64 * scale).round() as u32).max(1);
+    let sample = img.resize_exact(sample_width, sample_height, image::imageops::FilterType::Triangle).to_luma8();
+    let saliency = saliency_map(&sample);
+    let at = |x: u32, y: u32| saliency[(y * sample_width + x) as usize];
+
+    if img.width() > width {
+        let profile: Vec<f64> = (0..sample_width).map(|x| (0..sample_height).map(|y| at(x, y)).sum()).collect();
+        let window = (width as f64 * scale).round() as usize;
+        let offset = best_window(&profile, window, default.0 as f64 * scale);
+        (((offset as f64 / scale).round() as u32).min(img.width() - width), 0)
+    } else if img.height() > height {
+        let profile: Vec<f64> = (0..sample_height).map(|y| (0..sample_width).map(|x| at(x, y)).sum()).collect();
+        let window = (height as f64 * scale).round() as usize;
+        let offset = best_window(&profile, window, 0.0);
+        (0, ((offset as f64 / scale).round() as u32).min(img.height() - height))
+    } else {
+        default
+    }
+}
+
+/// Per-pixel saliency in row-major order: gradient magnitude plus the luminance entropy of the
+/// pixel's block, each scaled to 0–1.
+fn saliency_map(luma: &image::GrayImage) -> Vec<f64> {
+    let (width, height) = luma.dimensions();
+    let value = |x: u32, y: u32| luma.get_pixel(x.min(width - 1), y.min(height - 1)).0[0] as f64;
+
+    let blocks_across = width / SALIENCY_BLOCK + 1;
+    let mut entropy = vec![0.0; (blocks_across * (height / SALIENCY_BLOCK + 1)) as usize];
+    for (index, block) in entropy.iter_mut().enumerate() {
+        let (bx, by) = (index as u32 % blocks_across * SALIENCY_BLOCK, index as u32 / blocks_across * SALIENCY_BLOCK);
+        let mut histogram = [0u32; 16];
+        let mut count = 0;
+        for y in by..(by + SALIENCY_BLOCK).min(height) {
+            for x in bx..(bx + SALIENCY_BLOCK).min(width) {
+                histogram[(value(x, y) as usize) >> 4] += 1;
+                count += 1;
+            }
+        }
+        *block = histogram
+            .iter()
+            .filter(|&&n| n > 0)
+            .map(|&n| {
+                let p = n as f64 / count as f64;
+                -p * p.log2()
+            })
+            .sum::<f64>()
+            / 4.0;
+    }
+
+    let mut saliency = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = value(x + 1, y) - value(x.saturating_sub(1), y);
+            let dy = value(x, y + 1) - value(x, y.saturating_sub(1));
+            let edge = ((dx * dx + dy * dy).sqrt() / 360.0).min(1.0);
+            let block = (y / SALIENCY_BLOCK * blocks_across + x / SALIENCY_BLOCK) as usize;
+            saliency.push(edge + entropy[block]);
+        }
+    }
+    saliency
+}
+
+/// Start of the `window`-long run of `profile` with the largest sum. Near-ties go to the start
+/// closest to `preferred`, and a profile with no clear peak returns `preferred` itself.
+fn best_window(profile: &[f64], window: usize, preferred: f64) -> usize {
+    let window = window.clamp(1, profile.len());
+    let mut sums = Vec::with_capacity(profile.len() - window + 1);
+    let mut sum: f64 = profile[..window].iter().sum();
+    sums.push(sum);
+    for end in window..profile.len() {
+        sum += profile[end] - profile[end - window];
+        sums.push(sum);
+    }
+
+    let max = sums.iter().cloned().fold(f64::MIN, f64::max);
+    let min = sums.iter().cloned().fold(f64::MAX, f64::min);
+    let last = (sums.len() - 1) as f64;
+    if max <= 0.0 || (max - min) / max < FLAT_SALIENCY {
+        return preferred.round().clamp(0.0, last) as usize;
+    }
+    (0..sums.len())
+        .filter(|&start| sums[start] >= max * (1.0 - FLAT_SALIENCY / 2.0))
+        .min_by(|&a, &b| (a as f64 - preferred).abs().total_cmp(&(b as f64 - preferred).abs()))
+        .unwrap_or(0)
+}
+
+/// Composites the image over an opaque background, since JPEG has no alpha channel.
+fn flatten(img: &DynamicImage, background: Rgba<u8>) -> image::RgbImage {
+    let rgba = img.to_rgba8();
+    let mut canvas = RgbaImage::from_pixel(rgba.width(), rgba.height(), background);
+    image::imageops::overlay(&mut canvas, &rgba, 0, 0);
+    DynamicImage::ImageRgba8(canvas).to_rgb8()
+}
+
+fn apply_filters(mut img: DynamicImage, filters: &Filters) -> DynamicImage {
+    if let Some(sigma) = filters.blur {
+        img = img.blur(sigma);
+    }
+    if let Some(sigma) = filters.sharpen {
+        img = img.unsharpen(sigma, 1);
+    }
+    if let Some(contrast) = filters.contrast {
+        img = img.adjust_contrast(contrast);
+    }
+    if filters.grayscale {
+        // Keep an RGBA buffer so later overlays can still draw in color.
+        img = DynamicImage::ImageRgba8(img.grayscale().to_rgba8());
+    }
+    img
+}
+
+fn apply_watermark(img: DynamicImage, placement: &WatermarkPlacement) -> DynamicImage {
+    let mut base = img.to_rgba8();
+    let margin = placement.margin;
+    let max_width = base.width().saturating_sub(margin.saturating_mul(2));
+    let max_height = base.height().saturating_sub(margin.saturating_mul(2));
+    if max_width == 0 || max_height == 0 {
+        return DynamicImage::ImageRgba8(base);
+    }
+
+    let mut logo = placement.watermark.image.clone();
+    if logo.width() > max_width || logo.height() > max_height {
+        logo = DynamicImage::ImageRgba8(logo)
+            .resize(max_width, max_height, image::imageops::FilterType::Lanczos3)
+            .to_rgba8();
+    }
+
+    let opacity = placement.opacity.clamp(0.0, 1.0);
+    if opacity < 1.0 {
+        for pixel in logo.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+        }
+    }
+
+    let (x, y) = corner_origin(placement.corner, &base, logo.width(), logo.height(), margin);
+    image::imageops::overlay(&mut base, &logo, x, y);
+
+    DynamicImage::ImageRgba8(base)
+}
+
+fn load_font() -> Result<FontRef<'static>, AppError> {
+    FontRef::try_from_slice(BADGE_FONT)
+        .map_err(|e| AppError::ImageProcessing(format!("Failed to load badge font: {}", e)))
+}
+
+/// Lays `text` out on one line, returning its glyphs positioned from the top-left of the line
+/// box and the box's size.
+fn layout_text(font: &FontRef, text: &str, size: f32) -> (Vec<Glyph>, u32, u32) {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    (glyphs, caret.ceil() as u32, (scaled.ascent() - scaled.descent()).ceil() as u32)
+}
+
+/// Draws laid-out glyphs with their line box's top-left at `(x, y)`.
+fn draw_glyphs(base: &mut RgbaImage, font: &FontRef, glyphs: Vec<Glyph>, x: i64, y: i64, color: Color) {
+    for glyph in glyphs {
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            let gx = x + bounds.min.x as i64;
+            let gy = y + bounds.min.y as i64;
+            outlined.draw(|px, py, coverage| {
+                blend_pixel(base, gx + px as i64, gy + py as i64, color, coverage);
+            });
+        }
+    }
+}
+
+fn apply_badge(img: DynamicImage, badge: &Badge) -> Result<DynamicImage, AppError> {
+    let font = load_font()?;
+    let (glyphs, text_width, text_height) = layout_text(&font, &badge.text, badge.size);
+    let (pad_x, pad_y) = match badge.background {
+        Some(_) => {
+            let pad_y = (badge.size * 0.3).round() as u32;
+            ((text_height + pad_y * 2) / 2, pad_y)
+        }
+        None => (0, 0),
+    };
+    let box_width = text_width + pad_x * 2;
+    let box_height = text_height + pad_y * 2;
+
+    let mut base = img.to_rgba8();
+    let (x, y) = corner_origin(badge.corner, &base, box_width, box_height, badge.margin);
+
+    if let Some(background) = badge.background {
+        let radius = box_height as f32 / 2.0;
+        for py in 0..box_height {
+            for px in 0..box_width {
+                // Distance to the pill's spine gives anti-aliased rounded ends.
+                let fx = px as f32 + 0.5;
+                let fy = py as f32 + 0.5;
+                let dx = (radius - fx).max(fx - (box_width as f32 - radius)).max(0.0);
+                let dy = fy - radius;
+                let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+                blend_pixel(&mut base, x + px as i64, y + py as i64, background, coverage);
+            }
+        }
+    }
+
+    draw_glyphs(&mut base, &font, glyphs, x + pad_x as i64, y + pad_y as i64, badge.text_color);
+
+    Ok(DynamicImage::ImageRgba8(base))
+}
+
+/// Top-left position of a `width`x`height` box placed `margin` pixels in from `corner`.
+fn corner_origin(corner: Corner, base: &RgbaImage, width: u32, height: u32, margin: u32) -> (i64, i64) {
+    let right = base.width() as i64 - width as i64 - margin as i64;
+    let bottom = base.height() as i64 - height as i64 - margin as i64;
+    match corner {
+        Corner::TopLeft => (margin as i64, margin as i64),
+        Corner::TopRight => (right, margin as i64),
+        Corner::BottomLeft => (margin as i64, bottom),
+        Corner::BottomRight => (right, bottom),
+    }
+}
+
+fn blend_pixel(img: &mut RgbaImage, x: i64, y: i64, color: Color, coverage: f32) {
+    if x < 0 || y < 0 || x >= img.width() as i64 || y >= img.height() as i64 || coverage <= 0.0 {
+        return;
+    }
+    let mut color = color.0;
+    color[3] = (color[3] as f32 * coverage.min(1.0)).round() as u8;
+    img.get_pixel_mut(x as u32, y as u32).blend(&color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise, which compresses poorly enough for quality to matter.
+    fn noise(width: u32, height: u32) -> DynamicImage {
+        let mut state = 0x2545_f491_u32;
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |_, _| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let [r, g, b, _] = state.to_le_bytes();
+            image::Rgb([r, g, b])
+        }))
+    }
+
+    fn budget(max_bytes: usize, downscale: bool) -> SizeBudget {
+        SizeBudget { max_bytes, downscale }
+    }
+
+    #[test]
+    fn uses_the_default_quality_when_it_fits() {
+        let encoded = encode_within(noise(64, 64), &ImageFormat::Jpeg, &ProcessOptions::default(), &budget(usize::MAX, false)).unwrap();
+        assert_eq!(encoded.quality, Some(JPEG_QUALITY));
+    }
+
+    #[test]
+    fn steps_down_to_the_minimum_quality() {
+        let options = ProcessOptions::default();
+        let smallest = encode(&noise(64, 64), &ImageFormat::Jpeg, &options, MIN_QUALITY).unwrap();
+        let encoded = encode_within(noise(64, 64), &ImageFormat::Jpeg, &options, &budget(smallest.len(), false)).unwrap();
+        assert_eq!(encoded.quality, Some(MIN_QUALITY));
+        assert_eq!(encoded.data, smallest);
+    }
+
+    #[test]
+    fn downscales_once_quality_runs_out() {
+        let options = ProcessOptions::default();
+        let smallest = encode(&noise(64, 64), &ImageFormat::Jpeg, &options, MIN_QUALITY).unwrap();
+        let encoded = encode_within(noise(64, 64), &ImageFormat::Jpeg, &options, &budget(smallest.len() - 1, true)).unwrap();
+        assert!(encoded.data.len() < smallest.len());
+        assert!(image::load_from_memory(&encoded.data).unwrap().width() < 64);
+    }
+
+    #[test]
+    fn fails_when_nothing_fits() {
+        let result = encode_within(noise(64, 64), &ImageFormat::Jpeg, &ProcessOptions::default(), &budget(10, true));
+        assert!(matches!(result, Err(AppError::Render("max_bytes_unreachable", _))));
+    }
+
+    #[test]
+    fn lossless_formats_report_no_quality() {
+        let encoded = encode_within(noise(8, 8), &ImageFormat::Png, &ProcessOptions::default(), &budget(usize::MAX, false)).unwrap();
+        assert_eq!(encoded.quality, None);
+    }
+
+    #[test]
+    fn best_window_finds_the_peak() {
+        let profile = [0.0, 0.0, 1.0, 5.0, 6.0, 1.0, 0.0, 0.0];
+        assert_eq!(best_window(&profile, 2, 0.0), 3);
+        assert_eq!(best_window(&profile, 3, 0.0), 2);
+    }
+
+    #[test]
+    fn best_window_keeps_the_preferred_start_on_a_flat_profile() {
+        assert_eq!(best_window(&[1.0; 10], 4, 3.4), 3);
+        assert_eq!(best_window(&[1.0; 10], 4, 9.0), 6);
+        assert_eq!(best_window(&[0.0; 10], 4, 2.0), 2);
+    }
+
+    #[test]
+    fn best_window_breaks_near_ties_toward_the_preferred_start() {
+        let profile = [5.0, 0.0, 0.0, 0.0, 0.0, 0.0, 5.0];
+        assert_eq!(best_window(&profile, 1, 0.0), 0);
+        assert_eq!(best_window(&profile, 1, 5.0), 6);
+    }
+
+    #[test]
+    fn best_window_clamps_the_window_to_the_profile() {
+        assert_eq!(best_window(&[1.0, 2.0, 3.0], 10, 1.0), 0);
+        assert_eq!(best_window(&[0.0, 0.0, 9.0], 0, 0.0), 2);
+    }
+
+    fn badge(corner: Corner, background: Option<Color>) -> Badge {
+        Badge {
+            text: "NEW".to_string(),
+            corner,
+            size: 20.0,
+            margin: 6,
+            text_color: Color(Rgba([0, 0, 0, 255])),
+            background,
+        }
+    }
+
+    /// The smallest box holding every pixel that isn't white, as (left, top, right, bottom).
+    fn drawn_bounds(img: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        img.enumerate_pixels().filter(|(_, _, pixel)| pixel.0 != [255; 4]).fold(None, |bounds, (x, y, _)| {
+            let (left, top, right, bottom) = bounds.unwrap_or((x, y, x, y));
+            Some((left.min(x), top.min(y), right.max(x), bottom.max(y)))
+        })
+    }
+
+    fn white(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([255; 4])))
+    }
+
+    #[test]
+    fn badge_pill_sits_in_its_corner_inside_the_margin() {
+        let font = load_font().unwrap();
+        let (_, text_width, text_height) = layout_text(&font, "NEW", 20.0);
+        let pad_y = 6;
+        let (box_width, box_height) = (text_width + text_height + pad_y * 2, text_height + pad_y * 2);
+
+        let red = Color(Rgba([255, 0, 0, 255]));
+        let img = apply_badge(white(200, 100), &badge(Corner::BottomRight, Some(red))).unwrap().into_rgba8();
+        assert_eq!(drawn_bounds(&img), Some((200 - 6 - box_width, 100 - 6 - box_height, 200 - 7, 100 - 7)));
+        // The pill is solid along its middle row, and the text is drawn over it.
+        let middle = 100 - 6 - box_height / 2;
+        let is_red = |x, y| matches!(img.get_pixel(x, y).0, [255, g, b, 255] if g < 8 && b < 8);
+        assert!(is_red(200 - 7, middle) && is_red(200 - 6 - box_width, middle));
+        assert!(img.pixels().any(|pixel| pixel.0[0] < 64));
+    }
+
+    #[test]
+    fn badge_text_without_a_pill_stays_inside_its_line_box() {
+        let font = load_font().unwrap();
+        let (_, text_width, text_height) = layout_text(&font, "NEW", 20.0);
+        let img = apply_badge(white(200, 100), &badge(Corner::TopLeft, None)).unwrap().into_rgba8();
+        let (left, top, right, bottom) = drawn_bounds(&img).unwrap();
+        assert!(left >= 6 && top >= 6);
+        assert!(right < 6 + text_width && bottom < 6 + text_height);
+        assert!(img.pixels().all(|pixel| pixel.0[0] == pixel.0[2]));
+    }
+}
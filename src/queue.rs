@@ -0,0 +1,348 @@
+use futures::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::time::{timeout, Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::alerts::Alerter;
+use crate::cache::now_secs;
+use crate::config::{Config, QueueConfig, SessionConfig};
+use crate::diagnostics::Diagnostics;
+use crate::session;
+use crate::thumbnail::{Provenance, RenderError, RenderOptions, ThumbnailGenerator, ThumbnailResult};
+use crate::trace::{Trace, Tracer};
+
+/// Approximate number of jobs kept in the stream before old ones are trimmed.
+const MAX_STREAM_LENGTH: usize = 10_000;
+
+/// A render request as it travels from an API node to a worker.
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderJob {
+    id: String,
+    /// Pub/sub channel of the API node waiting for the result.
+    reply_to: String,
+    url: String,
+    width: u32,
+    height: u32,
+    options: RenderOptions,
+    /// The session profile to render with, loaded by the worker from its own config.
+    session: Option<String>,
+    /// Unix seconds after which nobody is waiting for the result any more.
+    deadline: u64,
+}
+
+/// A finished render, as sent back by the worker.
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderedJob {
+    image_data: Vec<u8>,
+    title: Option<String>,
+    description: Option<String>,
+    final_url: Option<String>,
+    og_image: Option<String>,
+    paywalled: bool,
+    language: Option<String>,
+    excerpt: Option<String>,
+    diagnostics: Option<Diagnostics>,
+    provenance: Provenance,
+    /// Time the worker spent rendering, excluding its own wait for a slot.
+    render_time_ms: u64,
+    trace: Option<Trace>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum JobOutcome {
+    Rendered(Box<RenderedJob>),
+    Budget(RenderError),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JobResult {
+    id: String,
+    outcome: JobOutcome,
+}
+
+type Waiters = Arc<Mutex<HashMap<String, oneshot::Sender<JobOutcome>>>>;
+
+/// Hands renders to workers over a Redis stream instead of running Chrome in this process.
+pub struct RenderQueue {
+    connection: ConnectionManager,
+    stream: String,
+    reply_to: String,
+    waiters: Waiters,
+}
+
+impl RenderQueue {
+    pub async fn connect(redis_url: &str, config: &QueueConfig) -> anyhow::Result<Self> {
+        let client = redis::Client::open(redis_url)?;
+        let mut connection = client.get_connection_manager().await?;
+        // Create the group up front so jobs submitted before any worker starts are still delivered.
+        ensure_group(&mut connection, &config.stream, &config.group).await?;
+        let reply_to = format!("{}:results:{}", config.stream, uuid::Uuid::new_v4());
+        let waiters: Waiters = Arc::default();
+        info!("Submitting renders to {} on {}", config.stream, redis_url);
+
+        tokio::spawn(listen_for_results(client, reply_to.clone(), waiters.clone()));
+
+        Ok(Self {
+            connection,
+            stream: config.stream.clone(),
+            reply_to,
+            waiters,
+        })
+    }
+
+    /// Enqueues a render and waits up to `wait` for a worker to publish the result.
+    pub async fn generate(
+        &self,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        wait: Duration,
+    ) -> anyhow::Result<ThumbnailResult> {
+        let job = RenderJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            reply_to: self.reply_to.clone(),
+            url: url.to_string(),
+            width,
+            height,
+            options: options.clone(),
+            session: options.session.as_ref().map(|session| session.name.clone()),
+            deadline: now_secs() + wait.as_secs() + 1,
+        };
+        let payload = bincode::serialize(&job)?;
+
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner()).insert(job.id.clone(), tx);
+        let submitted = Instant::now();
+
+        let mut connection = self.connection.clone();
+        let added: redis::RedisResult<String> = connection
+            .xadd_maxlen(&self.stream, redis::streams::StreamMaxlen::Approx(MAX_STREAM_LENGTH), "*", &[("job", payload)])
+            .await;
+        let entry_id = match added {
+            Ok(entry_id) => entry_id,
+            Err(e) => {
+                self.forget(&job.id);
+                anyhow::bail!("Failed to enqueue render job: {}", e);
+            }
+        };
+        debug!("Enqueued render job {} for {}", job.id, url);
+
+        let outcome = match timeout(wait, rx).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(_)) => anyhow::bail!("Result listener stopped"),
+            Err(_) => {
+                self.forget(&job.id);
+                // Jobs can carry credentials, so one nobody will take any more isn't left behind.
+                let deleted: redis::RedisResult<()> = connection.xdel(&self.stream, &[&entry_id]).await;
+                if let Err(e) = deleted {
+                    warn!("Failed to delete abandoned render job {}: {}", job.id, e);
+                }
+                anyhow::bail!("No worker returned render job {} within {:?}", job.id, wait);
+            }
+        };
+
+        match outcome {
+            JobOutcome::Rendered(rendered) => {
+                let RenderedJob { image_data, title, description, final_url, og_image, paywalled, language, excerpt, diagnostics, provenance, render_time_ms, trace } = *rendered;
+                // Everything but the render itself, including time in the stream, counts as queueing.
+                Ok(ThumbnailResult {
+                    image_data,
+                    title,
+                    description,
+                    final_url,
+                    og_image,
+                    paywalled,
+                    language,
+                    excerpt,
+                    diagnostics,
+                    provenance,
+                    queue_time: submitted.elapsed().saturating_sub(Duration::from_millis(render_time_ms)),
+                    trace,
+                })
+            }
+            JobOutcome::Budget(e) => Err(e.into()),
+            JobOutcome::Failed(message) => Err(anyhow::anyhow!(message)),
+        }
+    }
+
+    pub async fn is_healthy(&self) -> bool {
+        let mut connection = self.connection.clone();
+        let pong: redis::RedisResult<String> = redis::cmd("PING").query_async(&mut connection).await;
+        pong.is_ok()
+    }
+
+    fn forget(&self, id: &str) {
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    }
+}
+
+async fn ensure_group<C: redis::aio::ConnectionLike + Send>(connection: &mut C, stream: &str, group: &str) -> anyhow::Result<()> {
+    let created: redis::RedisResult<()> = connection.xgroup_create_mkstream(stream, group, "$").await;
+    match created {
+        Err(e) if e.code() != Some("BUSYGROUP") => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Delivers results published to this node's channel to their waiting requests, resubscribing
+/// whenever the connection drops.
+async fn listen_for_results(client: redis::Client, channel: String, waiters: Waiters) {
+    loop {
+        match client.get_async_pubsub().await {
+            Ok(mut pubsub) => match pubsub.subscribe(&channel).await {
+                Ok(()) => {
+                    let mut messages = pubsub.into_on_message();
+                    while let Some(message) = messages.next().await {
+                        let result: JobResult = match bincode::deserialize(message.get_payload_bytes()) {
+                            Ok(result) => result,
+                            Err(e) => {
+                                warn!("Ignoring malformed render result: {}", e);
+                                continue;
+                            }
+                        };
+                        let waiter = waiters.lock().unwrap_or_else(|e| e.into_inner()).remove(&result.id);
+                        if let Some(waiter) = waiter {
+                            let _ = waiter.send(result.outcome);
+                        }
+                    }
+                    warn!("Result subscription on {} ended", channel);
+                }
+                Err(e) => error!("Failed to subscribe to {}: {}", channel, e),
+            },
+            Err(e) => error!("Failed to connect for render results: {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Runs a render worker: consumes jobs from the stream with its own browser and publishes
+/// results back to the API node that submitted them.
+pub async fn run_worker(config: &Config) -> anyhow::Result<()> {
+    let queue = &config.queue;
+    let Some(redis_url) = &queue.redis_url else {
+        anyhow::bail!("queue.redis_url must be set to run a worker");
+    };
+
+    let client = redis::Client::open(redis_url.as_str())?;
+    let publisher = client.get_connection_manager().await?;
+    // Blocking reads get their own connection so they don't stall acks and publishes.
+    let mut reader = client.get_multiplexed_async_connection().await?;
+
+    ensure_group(&mut reader, &queue.stream, &queue.group).await?;
+    let generator = Arc::new(ThumbnailGenerator::new(&config.browser, &config.render).await?);
+    tokio::spawn(Alerter::new(&config.alerts)?.watch_browser(generator.subscribe()));
+
+    let sessions = Arc::new(config.sessions.clone());
+    let consumer = format!("worker-{}", uuid::Uuid::new_v4());
+    let slots = Arc::new(Semaphore::new(config.render.max_concurrent_renders.max(1)));
+    info!("Worker {} consuming {} as part of group {}", consumer, queue.stream, queue.group);
+
+    loop {
+        // Only claim a job when there's a free slot, so queued work stays available to other workers.
+        let permit = slots.clone().acquire_owned().await?;
+        let options = StreamReadOptions::default().group(&queue.group, &consumer).count(1).block(5000);
+        let reply: Option<StreamReadReply> = match reader.xread_options(&[&queue.stream], &[">"], &options).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                error!("Failed to read render jobs: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let Some(entry) = reply.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids).next() else {
+            continue;
+        };
+
+        let (generator, sessions) = (generator.clone(), sessions.clone());
+        let mut publisher = publisher.clone();
+        let (stream, group) = (queue.stream.clone(), queue.group.clone());
+        tokio::spawn(async move {
+            let _permit = permit;
+            match entry.get::<Vec<u8>>("job").map(|bytes| bincode::deserialize::<RenderJob>(&bytes)) {
+                Some(Ok(job)) => process_job(&generator, &mut publisher, &sessions, job).await,
+                _ => warn!("Discarding malformed render job {}", entry.id),
+            }
+            let acked: redis::RedisResult<()> = publisher.xack(&stream, &group, &[&entry.id]).await;
+            if let Err(e) = acked {
+                error!("Failed to acknowledge render job {}: {}", entry.id, e);
+            }
+            // Jobs can carry credentials, so they're deleted rather than left for trimming.
+            let deleted: redis::RedisResult<()> = publisher.xdel(&stream, &[&entry.id]).await;
+            if let Err(e) = deleted {
+                error!("Failed to delete render job {}: {}", entry.id, e);
+            }
+        });
+    }
+}
+
+async fn process_job(generator: &ThumbnailGenerator, publisher: &mut ConnectionManager, sessions: &[SessionConfig], mut job: RenderJob) {
+    if now_secs() > job.deadline {
+        debug!("Skipping expired render job {} for {}", job.id, job.url);
+        return;
+    }
+    info!("Rendering job {} for {}", job.id, job.url);
+
+    let started = Instant::now();
+    let tracer = Tracer::new(job.options.trace);
+    let rendered = match load_session(sessions, job.session.as_deref()).await {
+        Ok(session) => {
+            job.options.session = session;
+            generator.generate(&job.url, job.width, job.height, &job.options, &tracer).await
+        }
+        Err(e) => Err(e),
+    };
+    let outcome = match rendered {
+        Ok(result) => JobOutcome::Rendered(Box::new(RenderedJob {
+            render_time_ms: started.elapsed().saturating_sub(result.queue_time).as_millis() as u64,
+            image_data: result.image_data,
+            title: result.title,
+            description: result.description,
+            final_url: result.final_url,
+            og_image: result.og_image,
+            paywalled: result.paywalled,
+            language: result.language,
+            excerpt: result.excerpt,
+            diagnostics: result.diagnostics,
+            provenance: result.provenance,
+            trace: tracer.finish(),
+        })),
+        Err(e) => match e.downcast::<RenderError>() {
+            Ok(render_error) => JobOutcome::Budget(render_error),
+            Err(e) => JobOutcome::Failed(e.to_string()),
+        },
+    };
+
+    let payload = match bincode::serialize(&JobResult { id: job.id.clone(), outcome }) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Failed to serialize result of render job {}: {}", job.id, e);
+            return;
+        }
+    };
+    let published: redis::RedisResult<()> = publisher.publish(&job.reply_to, payload).await;
+    if let Err(e) = published {
+        error!("Failed to publish result of render job {}: {}", job.id, e);
+    }
+}
+
+/// Loads a job's session profile from the worker's own config, so login state never passes
+/// through the stream.
+async fn load_session(sessions: &[SessionConfig], name: Option<&str>) -> anyhow::Result<Option<session::SessionState>> {
+    let Some(name) = name else {
+        return Ok(None);
+    };
+    let profile = sessions
+        .iter()
+        .find(|session| session.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Session profile {} isn't configured on this worker", name))?;
+    Ok(Some(session::load(profile).await?))
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::config::RobotsConfig;
+
+/// Largest robots.txt we'll read; anything past this is ignored, as crawlers commonly do.
+const MAX_ROBOTS_BYTES: usize = 512 * 1024;
+
+/// Whether a URL may be rendered, and the robots.txt fetched to decide if the cached rules
+/// didn't answer.
+pub struct RobotsVerdict {
+    pub allowed: bool,
+    /// The robots.txt URL and the bytes it returned, `None` when the fetch failed.
+    pub fetched: Option<(String, Option<u64>)>,
+}
+
+/// Fetches robots.txt per origin and answers whether a URL may be rendered.
+pub struct RobotsChecker {
+    client: reqwest::Client,
+    user_agent: String,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Arc<RobotsRules>)>>,
+}
+
+impl RobotsChecker {
+    pub fn new(config: &RobotsConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(config.user_agent.clone())
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self {
+            client,
+            user_agent: config.user_agent.to_lowercase(),
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn is_allowed(&self, url: &str) -> RobotsVerdict {
+        let Ok(url) = Url::parse(url) else {
+            return RobotsVerdict { allowed: false, fetched: None };
+        };
+        let origin = url.origin().ascii_serialization();
+        let (rules, fetched) = self.rules_for(&origin).await;
+
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        RobotsVerdict { allowed: rules.is_allowed(&path), fetched }
+    }
+
+    /// The origin's rules, and the fetch made for them when they weren't cached.
+    async fn rules_for(&self, origin: &str) -> (Arc<RobotsRules>, Option<(String, Option<u64>)>) {
+        if let Some((fetched_at, rules)) = self.cache.lock().await.get(origin) {
+            if fetched_at.elapsed() < self.ttl {
+                return (rules.clone(), None);
+            }
+        }
+
+        let robots_url = format!("{}/robots.txt", origin);
+        let (rules, received) = self.fetch(&robots_url).await;
+        let rules = Arc::new(rules);
+        self.cache.lock().await.insert(origin.to_string(), (Instant::now(), rules.clone()));
+        (rules, Some((robots_url, received)))
+    }
+
+    /// Fetches and parses a robots.txt, also returning the bytes received or `None` on failure.
+    async fn fetch(&self, robots_url: &str) -> (RobotsRules, Option<u64>) {
+        debug!("Fetching {}", robots_url);
+
+        let mut response = match self.client.get(robots_url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to fetch {}: {}; treating as disallowed", robots_url, e);
+                return (RobotsRules::disallow_all(), None);
+            }
+        };
+
+        let status = response.status();
+        if status.is_client_error() {
+            // A missing robots.txt means there are no restrictions.
+            return (RobotsRules::allow_all(), Some(0));
+        }
+        if !status.is_success() {
+            warn!("{} returned {}; treating as disallowed", robots_url, status);
+            return (RobotsRules::disallow_all(), Some(0));
+        }
+
+        let mut body = Vec::new();
+        // Read only up to the cap, so a huge file isn't downloaded or held for the whole timeout.
+        while body.len() < MAX_ROBOTS_BYTES {
+            match response.chunk().await {
+                Ok(Some(chunk)) => body.extend_from_slice(&chunk[..chunk.len().min(MAX_ROBOTS_BYTES - body.len())]),
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read {}: {}; treating as disallowed", robots_url, e);
+                    return (RobotsRules::disallow_all(), None);
+                }
+            }
+        }
+        let received = body.len() as u64;
+        (RobotsRules::parse(&String::from_utf8_lossy(&body), &self.user_agent), Some(received))
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// The rules from a robots.txt that apply to our user agent.
+#[derive(Debug)]
+pub struct RobotsRules {
+    rules: Vec<Rule>,
+}
+
+impl RobotsRules {
+    fn allow_all() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule { allow: false, pattern: "/".to_string() }],
+        }
+    }
+
+    /// Parses robots.txt, keeping the groups naming `user_agent`, or the `*` groups if none do.
+    fn parse(contents: &str, user_agent: &str) -> Self {
+        let mut groups: Vec<(Vec<String>, Vec<Rule>)> = Vec::new();
+        let mut in_agent_lines = false;
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if !in_agent_lines {
+                        groups.push((Vec::new(), Vec::new()));
+                        in_agent_lines = true;
+                    }
+                    if let Some((agents, _)) = groups.last_mut() {
+                        agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                field @ ("allow" | "disallow") => {
+                    in_agent_lines = false;
+                    // An empty Disallow means "allow everything" and adds no rule.
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if let Some((_, rules)) = groups.last_mut() {
+                        rules.push(Rule { allow: field == "allow", pattern: value.to_string() });
+                    }
+                }
+                _ => in_agent_lines = false,
+            }
+        }
+
+        let names_us = |agents: &Vec<String>| agents.iter().any(|a| a != "*" && user_agent.contains(a.as_str()));
+        let specific = groups.iter().any(|(agents, _)| names_us(agents));
+        let rules = groups
+            .into_iter()
+            .filter(|(agents, _)| if specific { names_us(agents) } else { agents.iter().any(|a| a == "*") })
+            .flat_map(|(_, rules)| rules)
+            .collect();
+
+        Self { rules }
+    }
+
+    /// The longest matching pattern wins; on a tie, Allow wins.
+    fn is_allowed(&self, path: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+}
+
+/// Matches a robots.txt path pattern supporting `*` wildcards and a trailing `$` anchor.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "\
+User-agent: *
+Disallow: /private # members only
+Allow: /private/public
+
+User-agent: TinBot
+User-agent: otherbot
+Disallow: /
+";
+
+    #[test]
+    fn patterns_match_prefixes_wildcards_and_anchors() {
+        assert!(pattern_matches("/private", "/private/page"));
+        assert!(!pattern_matches("/private", "/public"));
+        assert!(pattern_matches("/a*b", "/axxb/c"));
+        assert!(!pattern_matches("/a*b", "/axxc"));
+        assert!(pattern_matches("/*.pdf$", "/docs/file.pdf"));
+        assert!(!pattern_matches("/*.pdf$", "/docs/file.pdf?download"));
+        assert!(pattern_matches("/exact$", "/exact"));
+        assert!(!pattern_matches("/exact$", "/exact/"));
+        assert!(pattern_matches("/*/edit$", "/a/b/edit"));
+    }
+
+    #[test]
+    fn the_wildcard_group_applies_when_no_group_names_us() {
+        let rules = RobotsRules::parse(ROBOTS, "examplebot/1.0");
+        assert!(!rules.is_allowed("/private/page"));
+        assert!(rules.is_allowed("/private/public/page"));
+        assert!(rules.is_allowed("/other"));
+    }
+
+    #[test]
+    fn a_group_naming_us_replaces_the_wildcard_group() {
+        let rules = RobotsRules::parse(ROBOTS, "tinbot/1.0");
+        assert!(!rules.is_allowed("/other"));
+        assert!(rules.is_allowed("/robots.txt"));
+    }
+
+    #[test]
+    fn allow_wins_a_tie_and_an_empty_disallow_allows_everything() {
+        let rules = RobotsRules::parse("User-agent: *\nDisallow: /a\nAllow: /a\n", "tinbot");
+        assert!(rules.is_allowed("/a/b"));
+        let rules = RobotsRules::parse("User-agent: *\nDisallow:\n", "tinbot");
+        assert!(rules.is_allowed("/anything"));
+    }
+
+    #[test]
+    fn disallow_all_blocks_everything_but_robots_txt() {
+        let rules = RobotsRules::disallow_all();
+        assert!(!rules.is_allowed("/"));
+        assert!(rules.is_allowed("/robots.txt"));
+    }
+}
@@ -0,0 +1,64 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Cache namespace the self-test writes its entry under, apart from real thumbnails.
+pub const NAMESPACE: &str = "_self_test";
+pub const WIDTH: u32 = 640;
+pub const HEIGHT: u32 = 400;
+/// Tries before reporting a failure, since a browser that's still starting can fail the first.
+pub const ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubling for each one after.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Fraction of the capture that must be text-dark; the page's text covers several times this.
+const MIN_DARK_FRACTION: f64 = 0.005;
+
+/// Black text on white, so a browser that can't draw text captures a blank page.
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><title>tin self-test</title></head>
+<body style="margin:0;background:#fff;color:#000;font:bold 48px sans-serif">
+<p style="margin:40px">Thumbnail self-test</p>
+<p style="margin:40px;font-family:serif">ABCDEFGHIJKLM 0123456789</p>
+</body></html>"#;
+
+/// How the startup self-test went, as reported by `/readyz`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SelfTest {
+    Running,
+    Passed { duration_ms: u64 },
+    /// `stage` is `render`, `capture`, `encode`, or `cache`.
+    Failed { stage: &'static str, error: String },
+    /// Turned off with `render.self_test = false`.
+    Skipped,
+    /// No browser could be started; only cached and browserless thumbnails are served.
+    Degraded { reason: String },
+}
+
+impl SelfTest {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, SelfTest::Passed { .. } | SelfTest::Skipped | SelfTest::Degraded { .. })
+    }
+}
+
+/// The test page as a `data:` URL, so the self-test needs no network.
+pub fn page_url() -> String {
+    format!("data:text/html;base64,{}", general_purpose::STANDARD.encode(PAGE))
+}
+
+/// Checks that a capture of the test page actually shows its text.
+pub fn check_capture(screenshot: &[u8]) -> Result<(), String> {
+    let image = image::load_from_memory(screenshot)
+        .map_err(|e| format!("Capture can't be decoded: {}", e))?
+        .into_luma8();
+    let total = image.pixels().len();
+    if total == 0 {
+        return Err("Capture is empty".to_string());
+    }
+    let dark = image.pixels().filter(|p| p.0[0] < 128).count();
+    if (dark as f64) < total as f64 * MIN_DARK_FRACTION {
+        return Err(format!("Capture shows no text ({} of {} pixels dark); are fonts installed?", dark, total));
+    }
+    Ok(())
+}
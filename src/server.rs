@@ -1,20 +1,199 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    async_trait,
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts, Path, Query, State,
+    },
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use base64::{Engine as _, engine::general_purpose};
+use ring::hmac;
+use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tracing::{error, info, debug};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use url::Url;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tracing::{error, info, debug, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
-use crate::{cache::Cache, thumbnail::ThumbnailGenerator};
+use crate::{
+    alerts::Alerter,
+    audit::{AuditEntry, AuditLog, AuditOutcome, Requester},
+    auth::{domain_matches, ApiKey, Auth, AuthError, Tenant, UsageSnapshot},
+    browserless::{self, PageMeta},
+    breaker::CircuitBreaker,
+    cache::{self, Cache, CachedData, EntryInfo, VersionInfo},
+    config::{CompressionConfig, Config, SessionConfig, SiteProfile, MAX_OVERSAMPLE},
+    consent::ConsentMode,
+    cors,
+    diagnostics::Diagnostics,
+    full_page::StripEncoder,
+    hooks::{Hooks, ThumbnailEvent},
+    image_pool::ImagePool,
+    links::LinkStore,
+    metadata::{self, Embed},
+    normalize,
+    probe::ContentProbe,
+    politeness::HostLimiter,
+    processing::{
+        compose_grid, dominant_color, process_image, render_placeholder, Background, Badge, Color, Corner, EncodeOptions, Encoded, Filters, Fit, Grid, ImageFormat, Placeholder,
+        ProcessOptions, SizeBudget, Watermark, WatermarkPlacement,
+    },
+    robots::RobotsChecker,
+    self_test::{self, SelfTest},
+    session::{self, SessionState},
+    signing::{SignedPayload, Signer},
+    queue::RenderQueue,
+    thumbnail::{
+        Action, BasicAuth, ColorScheme, Contrast, DomSnapshot, ForcedColors, FullPageLimits, HostRules, MediaFeatures, RedirectPolicy, ReducedMotion, RenderError,
+        Provenance, RenderOptions, StripSender, ThumbnailGenerator, ThumbnailResult,
+    },
+    trace::{Trace, Tracer},
+    usage::{self, UsageEvent, UsageStore},
+};
 
 pub struct AppState {
-    generator: ThumbnailGenerator,
-    cache: Cache,
+    generator: Renderer,
+    cache: Arc<Cache>,
+    config: Config,
+    watermark: Option<Arc<Watermark>>,
+    placeholder: Arc<Placeholder>,
+    images: ImagePool,
+    signer: Option<Signer>,
+    /// Consulted when robots.txt compliance is enabled globally or for the caller's tenant.
+    robots: RobotsChecker,
+    hosts: HostLimiter,
+    breaker: CircuitBreaker,
+    /// Replaced when the config is reloaded.
+    auth: RwLock<Arc<Auth>>,
+    /// Replaced when the config is reloaded.
+    sites: RwLock<Arc<Vec<SiteProfile>>>,
+    /// Replaced when the config is reloaded.
+    sessions: RwLock<Arc<Vec<SessionConfig>>>,
+    usage: UsageStore,
+    audit: Arc<AuditLog>,
+    links: LinkStore,
+    probe: ContentProbe,
+    settings: RwLock<RuntimeSettings>,
+    self_test: RwLock<SelfTest>,
+    log: LogHandle,
+    /// Sent each freshly rendered thumbnail.
+    hooks: Hooks,
+    alerts: Arc<Alerter>,
+    /// Keys the credential hashes in cache keys.
+    key_secret: hmac::Key,
+}
+
+/// Where renders happen: a browser in this process, or workers behind the queue.
+enum Renderer {
+    Local(ThumbnailGenerator),
+    Queue(Box<RenderQueue>),
+    /// The browser couldn't be started, for the given reason; only thumbnails that need no
+    /// browser are served.
+    Unavailable(String),
+}
+
+impl Renderer {
+    async fn generate(
+        &self,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        wait: Duration,
+        tracer: &Tracer,
+    ) -> anyhow::Result<ThumbnailResult> {
+        match self {
+            // Queue workers time their renders and send the trace back with the result.
+            Renderer::Local(generator) => generator.generate(url, width, height, options, tracer).await,
+            Renderer::Queue(queue) => queue.generate(url, width, height, options, wait).await,
+            Renderer::Unavailable(reason) => Err(anyhow::anyhow!("No browser is available: {}", reason)),
+        }
+    }
+
+    async fn is_healthy(&self) -> bool {
+        match self {
+            Renderer::Local(generator) => generator.is_healthy().await,
+            Renderer::Queue(queue) => queue.is_healthy().await,
+            Renderer::Unavailable(_) => false,
+        }
+    }
+
+    /// Whether pages can be rendered at all; otherwise thumbnails come from the cache or are
+    /// made without a browser.
+    fn is_available(&self) -> bool {
+        match self {
+            Renderer::Local(generator) => generator.is_running(),
+            Renderer::Queue(_) => true,
+            Renderer::Unavailable(_) => false,
+        }
+    }
+
+    /// The in-process browser, whose settings can be changed at runtime.
+    fn local(&self) -> Option<&ThumbnailGenerator> {
+        match self {
+            Renderer::Local(generator) => Some(generator),
+            Renderer::Queue(_) | Renderer::Unavailable(_) => None,
+        }
+    }
+}
+
+/// Changes the log filter of the running process.
+pub type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Settings the admin API can change without a restart. Render concurrency and the navigation
+/// budget live on the generator.
+struct RuntimeSettings {
+    request_timeout: Duration,
+    allowed_domains: Vec<String>,
+    blocked_domains: Vec<String>,
+}
+
+impl AppState {
+    fn auth(&self) -> Arc<Auth> {
+        self.auth.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// The request's API key and its tenant; `None` when no tenants are configured.
+struct Caller(Option<ApiKey>);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Caller {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        match state.auth().authenticate(&parts.headers) {
+            Ok(key) => Ok(Caller(key)),
+            Err(AuthError::MissingKey) => Err(AppError::Unauthorized("An API key is required".to_string())),
+            Err(AuthError::InvalidKey) => Err(AppError::Unauthorized("Invalid API key".to_string())),
+        }
+    }
+}
+
+/// Guards the `/admin` endpoints.
+struct Admin;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if state.auth().is_admin(&parts.headers) {
+            Ok(Admin)
+        } else {
+            Err(AppError::Unauthorized("A valid admin key is required".to_string()))
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,76 +205,459 @@ pub struct ThumbnailRequest {
     height: u32,
     #[serde(default = "default_format")]
     format: ImageFormat,
+    /// Progressive JPEG encoding; ignored for other formats.
+    #[serde(default)]
+    progressive: bool,
+    /// Lossless WebP encoding; ignored for other formats.
+    #[serde(default)]
+    lossless: bool,
+    #[serde(default)]
+    fit: Fit,
+    /// With `fit=cover`, crop toward the most detailed region instead of the top center.
+    #[serde(default)]
+    smart_crop: bool,
+    /// Hex color or `transparent`, used for `contain` padding and JPEG flattening.
+    background: Option<Background>,
+    /// Unsharp-mask radius applied after resizing.
+    sharpen: Option<f32>,
+    /// Contrast adjustment in percent (-100 to 100).
+    contrast: Option<f32>,
+    #[serde(default)]
+    grayscale: bool,
+    /// Gaussian blur sigma.
+    blur: Option<f32>,
+    /// Corner to stamp the configured watermark in; no watermark when absent.
+    watermark: Option<Corner>,
+    watermark_opacity: Option<f32>,
+    watermark_margin: Option<u32>,
+    /// Short text label such as the domain name, drawn with the bundled font.
+    badge: Option<String>,
+    badge_position: Option<Corner>,
+    badge_size: Option<f32>,
+    /// Draw the badge on a rounded background pill (default true).
+    badge_pill: Option<bool>,
+    /// Run the page's scripts (default true); false captures the server-rendered HTML.
+    javascript: Option<bool>,
+    /// Render despite TLS certificate errors; only for tenants with `allow_insecure_tls`.
+    #[serde(default)]
+    ignore_tls_errors: bool,
+    /// Redirects followed before giving up; capped at `render.max_redirects`.
+    max_redirects: Option<u32>,
+    /// `false` fails renders that redirect to another origin.
+    cross_origin_redirects: Option<bool>,
+    /// Render fresh, bypassing the cache, and report console errors and failed requests.
+    #[serde(default)]
+    debug: bool,
+    /// Report how long each stage of the request took, and log it.
+    #[serde(default)]
+    trace: bool,
+    /// Render fresh and replace the cached entry; also set by `Cache-Control: no-cache`.
+    #[serde(default)]
+    refresh: bool,
+    /// With `refresh`, fetch the page's HTML first and keep the cached entry if it's unchanged.
+    #[serde(default)]
+    conditional: bool,
+    /// Emulated `prefers-reduced-motion`.
+    prefers_reduced_motion: Option<ReducedMotion>,
+    /// Emulated `prefers-contrast`.
+    prefers_contrast: Option<Contrast>,
+    /// Emulated `forced-colors`.
+    forced_colors: Option<ForcedColors>,
+    /// How to deal with cookie consent dialogs; defaults to the site profile, then `render.consent`.
+    consent: Option<ConsentMode>,
+    /// Use the page's `og:image` instead of the capture when the page is paywalled.
+    #[serde(default)]
+    og_image_fallback: bool,
+    /// Settle animations, videos, and carousels before capture for repeatable thumbnails.
+    #[serde(default)]
+    stabilize: bool,
+    /// Largest acceptable encoded size; quality is lowered until the image fits.
+    max_bytes: Option<usize>,
+    /// With `max_bytes`, also shrink the image when the lowest quality is still too large.
+    #[serde(default)]
+    allow_downscale: bool,
+    /// HTTP basic-auth credentials for the target site (POST only).
+    auth: Option<BasicAuth>,
+    /// Interactions performed before the screenshot (POST only).
+    #[serde(default)]
+    actions: Vec<Action>,
+    #[serde(default)]
+    response_mode: ResponseMode,
+    /// What to return instead of an error when the page can't be rendered.
+    fallback: Option<Fallback>,
+    /// Serve the render made at this Unix time, as listed by `/thumbnail/history`, instead of
+    /// the current one. Never renders.
+    version: Option<u64>,
+    /// Render logged in, with the named session profile from the config.
+    session: Option<String>,
+    /// Capture at this many device pixels per CSS pixel, then downscale; defaults to
+    /// `render.oversample`.
+    oversample: Option<f32>,
+    /// Only look in the cache, failing with 404 on a miss; set for `HEAD` requests, which
+    /// shouldn't pay for a render whose body is thrown away.
+    #[serde(skip)]
+    cache_only: bool,
 }
 
-#[derive(Debug, Deserialize, Default, Clone, Copy)]
+/// How `/thumbnail` hands back the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "lowercase")]
-pub enum ImageFormat {
+enum ResponseMode {
+    /// Base64 in the JSON body.
     #[default]
-    Webp,
-    Jpeg,
-    Png,
-}
-
-impl ImageFormat {
-    fn as_str(&self) -> &'static str {
-        match self {
-            ImageFormat::Webp => "webp",
-            ImageFormat::Jpeg => "jpeg",
-            ImageFormat::Png => "png",
-        }
-    }
-
-    fn content_type(&self) -> &'static str {
-        match self {
-            ImageFormat::Webp => "image/webp",
-            ImageFormat::Jpeg => "image/jpeg",
-            ImageFormat::Png => "image/png",
-        }
-    }
+    Inline,
+    /// The image itself as the body, like `/thumbnail/raw`.
+    Binary,
+    /// A short-lived `/images/{hash}` link in the JSON body.
+    Url,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CachedData {
-    image_data: Vec<u8>,
-    title: Option<String>,
-    description: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Fallback {
+    /// A generated image with the site's initial and brand color.
+    Placeholder,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ThumbnailResponse {
     pub url: String,
-    pub image_data: String,
+    /// Base64 image, unless `response_mode=url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_data: Option<String>,
+    /// Where to fetch the image for `response_mode=url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    /// Unix seconds after which `image_url` stops working.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_expires_at: Option<u64>,
     pub content_type: String,
     pub title: Option<String>,
     pub description: Option<String>,
     pub cached: bool,
+    /// A conditional refresh found the page unchanged and returned the cached entry.
+    pub not_modified: bool,
+    /// The page showed a paywall or login wall, or marked its content as paid.
+    pub paywalled: bool,
+    /// Rendering failed and `fallback=placeholder` returned a generated image instead.
+    pub placeholder: bool,
+    /// No browser is available, so the image is the page's own image or `og:image`.
+    pub degraded: bool,
+    /// The page's primary language as an ISO 639-1 code.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The start of the page's main content as plain text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
+    /// Where the page ended up after redirects, when it differs from `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    /// Encoder quality chosen to fit `max_bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Console errors and failed requests seen while rendering, for `debug` requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Diagnostics>,
+    /// How the image was rendered; absent for placeholders and entries from older exports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// When each stage of the request started and how long it took, for `trace` requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Trace>,
+    /// Base64 Ed25519 signature over the image hash and metadata, when signing is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+/// A finished thumbnail, whether served from cache or freshly rendered.
+struct Thumbnail {
+    image_data: Vec<u8>,
+    title: Option<String>,
+    description: Option<String>,
+    final_url: Option<String>,
+    /// Encoder quality chosen to fit `max_bytes`.
+    quality: Option<u8>,
+    diagnostics: Option<Diagnostics>,
+    provenance: Option<Provenance>,
+    cached: bool,
+    /// Served from cache without a render confirming it's current: the page was unchanged on a
+    /// refresh, or no browser is available to render it.
+    stale: bool,
+    not_modified: bool,
+    paywalled: bool,
+    language: Option<String>,
+    excerpt: Option<String>,
+    placeholder: bool,
+    /// Made from the page's own image or `og:image` because no browser is available.
+    degraded: bool,
+    /// Zero for cache hits.
+    render_time: Duration,
+    queue_time: Duration,
+    trace: Option<Trace>,
+}
+
+impl Thumbnail {
+    fn from_cache(cached: CachedData, not_modified: bool) -> Self {
+        Self {
+            image_data: cached.image_data,
+            title: cached.title,
+            description: cached.description,
+            final_url: cached.final_url,
+            quality: cached.quality,
+            diagnostics: None,
+            provenance: cached.provenance,
+            cached: true,
+            stale: not_modified,
+            not_modified,
+            paywalled: cached.paywalled,
+            language: cached.language,
+            excerpt: cached.excerpt,
+            placeholder: false,
+            degraded: false,
+            render_time: Duration::ZERO,
+            queue_time: Duration::ZERO,
+            trace: None,
+        }
+    }
+
+    fn cache_status(&self) -> &'static str {
+        match (self.cached, self.stale) {
+            (false, _) => "MISS",
+            (true, false) => "HIT",
+            (true, true) => "STALE",
+        }
+    }
+
+    /// Cache status and timing headers sent with every thumbnail response.
+    fn headers(&self) -> [(HeaderName, String); 3] {
+        [
+            (HeaderName::from_static("x-cache"), self.cache_status().to_string()),
+            (HeaderName::from_static("x-render-time-ms"), self.render_time.as_millis().to_string()),
+            (HeaderName::from_static("x-queue-time-ms"), self.queue_time.as_millis().to_string()),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SigningKeyResponse {
+    pub key_id: String,
+    pub algorithm: &'static str,
+    /// Base64 of the raw 32-byte public key.
+    pub public_key: String,
 }
 
 #[derive(Debug, Serialize)]
 pub struct HealthResponse {
     pub status: String,
     pub chrome_available: bool,
+    /// Size of the cache store on disk as of the last maintenance run.
+    pub cache_size_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantUsageResponse {
+    pub name: String,
+    pub namespace: String,
+    pub usage: UsageSnapshot,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollageRequest {
+    /// Pages to render, laid out in order.
+    urls: Vec<String>,
+    #[serde(default = "default_collage_width")]
+    width: u32,
+    #[serde(default = "default_collage_height")]
+    height: u32,
+    #[serde(default = "default_format")]
+    format: ImageFormat,
+    /// Tiles per row; defaults to one row, or two for four URLs.
+    columns: Option<u32>,
+    /// Pixels between tiles and around the edge.
+    #[serde(default = "default_collage_gap")]
+    gap: u32,
+    /// Fill behind and between tiles; defaults to white.
+    background: Option<Background>,
+    /// `/thumbnail` parameters applied to every tile, except its URL, size, and format.
+    #[serde(default)]
+    tile: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    response_mode: ResponseMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollageResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_expires_at: Option<u64>,
+    pub content_type: String,
+    pub tiles: Vec<CollageTile>,
+}
+
+/// What each page in a collage rendered as, in layout order.
+#[derive(Debug, Serialize)]
+pub struct CollageTile {
+    pub url: String,
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    pub cached: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FullPageQuery {
+    url: String,
+    /// Viewport width, which is also the width of the image.
+    #[serde(default = "default_full_page_width")]
+    width: u32,
+    /// Viewport height; the page is captured in strips this tall.
+    #[serde(default = "default_full_page_viewport_height")]
+    viewport_height: u32,
+    /// Cuts the image off at this height; capped at `limits.max_full_page_height`.
+    max_height: Option<u32>,
+    javascript: Option<bool>,
+    consent: Option<ConsentMode>,
 }
 
+const MAX_BADGE_CHARS: usize = 64;
+const MAX_ACTIONS: usize = 20;
+const DEFAULT_CACHE_PAGE: usize = 100;
+const MAX_CACHE_PAGE: usize = 1000;
+/// Smallest `max_bytes` accepted; below this not even a tiny image header fits comfortably.
+const MIN_MAX_BYTES: usize = 512;
+const LINK_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+const AUDIT_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+const DEFAULT_AUDIT_PAGE: usize = 100;
+const MAX_AUDIT_PAGE: usize = 1000;
+/// Renders one `/ws` connection may have running at once.
+const MAX_WS_IN_FLIGHT: usize = 8;
+/// Upper bound on the sum of `wait` actions in one request.
+const MAX_ACTION_WAIT_MS: u64 = 10_000;
+/// How long a placeholder waits for the site's favicon before using the configured color.
+const FAVICON_TIMEOUT: Duration = Duration::from_secs(3);
+const MIN_COLLAGE_URLS: usize = 2;
+const MAX_COLLAGE_URLS: usize = 4;
+const MAX_COLLAGE_GAP: u32 = 64;
+
 fn default_width() -> u32 { 640 }
 fn default_height() -> u32 { 400 }
 fn default_format() -> ImageFormat { ImageFormat::Webp }
+fn default_collage_width() -> u32 { 1200 }
+fn default_collage_height() -> u32 { 630 }
+fn default_collage_gap() -> u32 { 8 }
+fn default_full_page_width() -> u32 { 1280 }
+fn default_full_page_viewport_height() -> u32 { 800 }
 
-pub async fn create_app() -> anyhow::Result<Router> {
-    let cache = Cache::new(".thumbnail_cache")?;
-    let generator = ThumbnailGenerator::new().await?;
+pub async fn create_app(config: &Config, cache: Arc<Cache>, log: LogHandle) -> anyhow::Result<Router> {
+    if config.cache.purge_old_versions {
+        cache.purge_old_versions().await?;
+    }
+    let watermark = config.watermark.path.as_ref().map(Watermark::load).transpose()?.map(Arc::new);
+    let placeholder = Arc::new(Placeholder::load(config.placeholder.template.as_deref(), config.placeholder.color)?);
+    let signer = config.signing.as_ref().map(Signer::load).transpose()?;
+    let robots = RobotsChecker::new(&config.robots)?;
+    let usage = UsageStore::new(cache.open_tree("usage")?);
+    // Opened even when disabled, so maintenance doesn't drop earlier entries as a stale tree.
+    let audit = Arc::new(AuditLog::new(cache.open_tree("audit")?, config.audit.enabled)?);
+    let links = LinkStore::new(cache.open_tree("image_links")?, Duration::from_secs(config.links.ttl_secs));
+    let generator = match &config.queue.redis_url {
+        Some(redis_url) => Renderer::Queue(Box::new(RenderQueue::connect(redis_url, &config.queue).await?)),
+        None => match ThumbnailGenerator::new(&config.browser, &config.render).await {
+            Ok(generator) => Renderer::Local(generator),
+            Err(e) if config.browser.degraded_mode => {
+                error!("Starting without a browser: {}", e);
+                Renderer::Unavailable(e.to_string())
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    let hooks = Hooks::new(&config.hooks, audit.clone()).await?;
+    let key_secret = hmac::Key::new(hmac::HMAC_SHA256, &cache.secret("cache_key")?);
+    let alerts = Alerter::new(&config.alerts)?;
+    if let Renderer::Local(generator) = &generator {
+        tokio::spawn(alerts.clone().watch_browser(generator.subscribe()));
+    }
+    let self_test = match &generator {
+        Renderer::Unavailable(reason) => SelfTest::Degraded { reason: reason.clone() },
+        _ if config.render.self_test => SelfTest::Running,
+        _ => SelfTest::Skipped,
+    };
     
     let state = Arc::new(AppState {
         generator,
         cache,
+        config: config.clone(),
+        watermark,
+        placeholder,
+        images: ImagePool::new(&config.processing),
+        signer,
+        robots,
+        hosts: HostLimiter::new(&config.politeness),
+        breaker: CircuitBreaker::new(&config.circuit_breaker),
+        auth: RwLock::new(Arc::new(Auth::new(&config.auth))),
+        sites: RwLock::new(Arc::new(config.sites.clone())),
+        sessions: RwLock::new(Arc::new(config.sessions.clone())),
+        usage,
+        audit,
+        links,
+        probe: ContentProbe::new()?,
+        settings: RwLock::new(RuntimeSettings {
+            request_timeout: Duration::from_secs(config.render.request_timeout_secs),
+            allowed_domains: lowercase(&config.access.allowed_domains),
+            blocked_domains: lowercase(&config.access.blocked_domains),
+        }),
+        self_test: RwLock::new(self_test),
+        log,
+        hooks,
+        alerts,
+        key_secret,
     });
 
+    let every = Duration::from_secs(config.cache.maintenance_interval_secs.max(1));
+    tokio::spawn(state.cache.clone().run_maintenance(every));
+    tokio::spawn(purge_expired_links(state.clone()));
+    let checked = state.clone();
+    tokio::spawn(state.alerts.clone().run_checks(config.cache.path.clone(), move || {
+        let state = checked.clone();
+        async move { state.generator.is_healthy().await }
+    }));
+    if config.audit.retention_days > 0 {
+        tokio::spawn(purge_old_audit_entries(state.clone()));
+    }
+    if matches!(*state.self_test.read().unwrap_or_else(|e| e.into_inner()), SelfTest::Running) {
+        tokio::spawn(run_self_test(state.clone()));
+    }
+    tokio::spawn(watch_config(state.clone()));
+
     let app = Router::new()
         .route("/thumbnail", get(handle_get_thumbnail))
         .route("/thumbnail", post(handle_post_thumbnail))
+        .route("/thumbnail/raw", get(handle_raw_thumbnail))
+        .route("/thumbnail/full", get(handle_full_page))
+        .route("/thumbnail/history", get(handle_history))
+        .route("/collage", post(handle_collage))
+        .route("/ws", get(handle_ws))
+        .route("/images/:token", get(handle_linked_image))
         .route("/health", get(health_check))
-        .layer(tower_http::cors::CorsLayer::permissive())
+        .route("/readyz", get(handle_readyz))
+        .route("/signing-key", get(handle_signing_key))
+        .route("/admin/tenants", get(handle_tenant_usage))
+        .route("/admin/settings", get(handle_get_settings).patch(handle_update_settings))
+        .route("/admin/allowlist", post(handle_allowlist_add))
+        .route("/admin/allowlist/:domain", delete(handle_allowlist_remove))
+        .route("/admin/audit", get(handle_audit))
+        .route("/admin/reload", post(handle_reload))
+        .route("/usage", get(handle_usage))
+        .route("/debug/dom", get(handle_debug_dom))
+        .route("/cache", put(handle_put_cache))
+        .route("/cache/entries", get(handle_cache_entries))
+        .layer(compression_layer(&config.compression))
+        .layer(cors::layer(&config.cors)?)
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .with_state(state);
 
@@ -104,30 +666,257 @@ pub async fn create_app() -> anyhow::Result<Router> {
 
 async fn handle_get_thumbnail(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<ThumbnailRequest>,
-) -> Result<impl IntoResponse, AppError> {
+    Caller(key): Caller,
+    Query(mut params): Query<ThumbnailRequest>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     info!("GET /thumbnail with params: {:?}", params);
-    generate_thumbnail(state, params).await
+    apply_cache_control(&mut params, key.as_ref(), &headers);
+    generate_thumbnail(state, key, params, &headers).await
 }
 
 async fn handle_post_thumbnail(
     State(state): State<Arc<AppState>>,
-    Json(params): Json<ThumbnailRequest>,
-) -> Result<impl IntoResponse, AppError> {
+    Caller(key): Caller,
+    headers: HeaderMap,
+    Json(mut params): Json<ThumbnailRequest>,
+) -> Result<Response, AppError> {
     info!("POST /thumbnail with params: {:?}", params);
-    generate_thumbnail(state, params).await
+    apply_cache_control(&mut params, key.as_ref(), &headers);
+    generate_thumbnail(state, key, params, &headers).await
 }
 
-fn build_cache_key(url: &str, width: u32, height: u32, format: &ImageFormat) -> String {
-    format!("{}:{}:{}:{}", url, width, height, format.as_str())
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    pub url: String,
+    /// Newest first; the current render, if any, comes first.
+    pub versions: Vec<VersionInfo>,
 }
 
-async fn generate_thumbnail(
-    state: Arc<AppState>,
-    params: ThumbnailRequest,
-) -> Result<impl IntoResponse, AppError> {
-    info!("Generating thumbnail for {} ({}x{}) format={:?}", params.url, params.width, params.height, params.format);
-    
+/// Lists the renders kept for a thumbnail, taking the same parameters as `GET /thumbnail` since
+/// they pick the cache entry. Fetch one with `GET /thumbnail?version=<created_at>`.
+async fn handle_history(
+    State(state): State<Arc<AppState>>,
+    Caller(key): Caller,
+    Query(mut params): Query<ThumbnailRequest>,
+) -> Result<Json<HistoryResponse>, AppError> {
+    info!("GET /thumbnail/history for {}", params.url);
+    normalize_url(&state, &mut params);
+    validate_request(&state, &params)?;
+    let tenant = key.as_ref().map(|k| k.tenant.as_ref());
+    let host = Url::parse(&params.url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .ok_or_else(|| AppError::BadRequest(format!("URL has no host: {}", params.url)))?;
+    check_host_allowed(&state, tenant, &host)?;
+    if let Some(name) = &params.session {
+        session_profile(&state, tenant, name)?;
+    }
+
+    let namespace = tenant.map_or(state.config.cache.namespace.as_str(), |t| t.namespace.as_str());
+    let cache_key = build_cache_key(&state, &build_process_options(&state, &params), &params);
+    let versions = state.cache.history(namespace, &cache_key)?;
+    Ok(Json(HistoryResponse { url: params.url, versions }))
+}
+
+/// Compresses JSON and other text responses as the client accepts. Images, including streamed
+/// full-page captures, are already compressed and pass through untouched.
+fn compression_layer(config: &CompressionConfig) -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(config.min_bytes)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::SSE);
+    CompressionLayer::new()
+        .gzip(config.enabled)
+        .br(config.enabled)
+        .zstd(config.enabled)
+        .compress_when(predicate)
+}
+
+/// Rewrites the URL so equivalent forms share a cache entry; see `UrlConfig`.
+fn normalize_url(state: &AppState, params: &mut ThumbnailRequest) {
+    let normalized = normalize::normalize(&params.url, &state.config.urls);
+    if normalized != params.url {
+        debug!("Normalized {} to {}", params.url, normalized);
+        params.url = normalized;
+    }
+}
+
+/// Treats `Cache-Control: no-cache` from an authenticated caller as `refresh`. Browsers send it
+/// on every reload, so anonymous callers could otherwise force a render per request.
+fn apply_cache_control(params: &mut ThumbnailRequest, key: Option<&ApiKey>, headers: &HeaderMap) {
+    if key.is_none() {
+        return;
+    }
+    let no_cache = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"));
+    params.refresh |= no_cache;
+}
+
+fn build_cache_key(state: &AppState, options: &ProcessOptions, params: &ThumbnailRequest) -> String {
+    let mut key = format!("{}:{}:{}:{}", params.url, params.width, params.height, params.format.as_str());
+    if options.encode.progressive {
+        key.push_str(":progressive");
+    }
+    if options.encode.lossless {
+        key.push_str(":lossless");
+    }
+    if let Some(budget) = &options.size_budget {
+        key.push_str(&format!(":max_bytes={}{}", budget.max_bytes, if budget.downscale { ",downscale" } else { "" }));
+    }
+    if options.fit != Fit::Inside {
+        key.push_str(&format!(":fit={}", options.fit.as_str()));
+    }
+    if options.smart_crop {
+        key.push_str(":smart_crop");
+    }
+    if let Some(background) = options.background {
+        key.push_str(&format!(":bg={}", background.cache_key()));
+    }
+    if !options.filters.is_empty() {
+        key.push_str(&format!(":fx={}", options.filters.cache_key()));
+    }
+    if let Some(placement) = &options.watermark {
+        key.push_str(&format!(":wm={},{},{}", placement.corner.as_str(), placement.opacity, placement.margin));
+    }
+    if let Some(badge) = &options.badge {
+        key.push_str(&format!(
+            ":badge={},{},{},{},{}",
+            badge.corner.as_str(),
+            badge.size,
+            badge.text_color.to_hex(),
+            badge.background.map(|c| c.to_hex()).unwrap_or_default(),
+            badge.text,
+        ));
+    }
+    if let Some(auth) = &params.auth {
+        // Pages behind auth differ per account, but the credentials themselves must not be stored.
+        let tag = keyed_digest(state, &[auth.username.as_bytes(), b"\0", auth.password.as_bytes()]);
+        key.push_str(&format!(":auth={}", tag));
+    }
+    if params.javascript == Some(false) {
+        key.push_str(":nojs");
+    }
+    if params.ignore_tls_errors {
+        key.push_str(":insecure");
+    }
+    if params.stabilize {
+        key.push_str(":stable");
+    }
+    if let Some(consent) = params.consent {
+        key.push_str(&format!(":consent={}", consent.as_str()));
+    }
+    if params.og_image_fallback {
+        key.push_str(":og_fallback");
+    }
+    if let Some(reduced_motion) = params.prefers_reduced_motion {
+        key.push_str(&format!(":motion={}", reduced_motion.as_str()));
+    }
+    if let Some(contrast) = params.prefers_contrast {
+        key.push_str(&format!(":contrast={}", contrast.as_str()));
+    }
+    if let Some(forced_colors) = params.forced_colors {
+        key.push_str(&format!(":forced_colors={}", forced_colors.as_str()));
+    }
+    if let Some(max_redirects) = params.max_redirects {
+        key.push_str(&format!(":max_redirects={}", max_redirects));
+    }
+    if params.cross_origin_redirects == Some(false) {
+        key.push_str(":same_origin");
+    }
+    if let Some(session) = &params.session {
+        key.push_str(&format!(":session={}", session));
+    }
+    if let Some(oversample) = options.oversample {
+        key.push_str(&format!(":oversample={}", oversample));
+    }
+    if let Some(embed) = &options.metadata {
+        if embed.provenance {
+            key.push_str(":provenance");
+        }
+        if embed.srgb_profile {
+            key.push_str(":srgb");
+        }
+    }
+    if !params.actions.is_empty() {
+        // Typed text can be a password, so it gets the same keyed digest as credentials.
+        let actions = serde_json::to_vec(&params.actions).unwrap_or_default();
+        key.push_str(&format!(":actions={}", keyed_digest(state, &[&actions])));
+    }
+    let host = Url::parse(&params.url).ok().and_then(|u| u.host_str().map(str::to_string));
+    if let Some(profile) = host.and_then(|host| site_profile(state, &host)) {
+        // So renders stop being served once a config reload changes how the site is rendered.
+        let profile = serde_json::to_vec(&profile).unwrap_or_default();
+        key.push_str(&format!(":site={:x}", Sha256::digest(&profile)));
+    }
+    key
+}
+
+/// Hex HMAC of `parts` under the store's cache key secret. Cache keys are listed and sent to
+/// hooks, and a plain hash of a weak secret in them could be brute-forced.
+fn keyed_digest(state: &AppState, parts: &[&[u8]]) -> String {
+    let mut context = hmac::Context::with_key(&state.key_secret);
+    for part in parts {
+        context.update(part);
+    }
+    context.sign().as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn build_process_options(state: &AppState, params: &ThumbnailRequest) -> ProcessOptions {
+    let badge_config = &state.config.badge;
+    ProcessOptions {
+        encode: EncodeOptions {
+            progressive: params.progressive && matches!(params.format, ImageFormat::Jpeg),
+            lossless: params.lossless && matches!(params.format, ImageFormat::Webp),
+        },
+        size_budget: params.max_bytes.map(|max_bytes| SizeBudget {
+            max_bytes,
+            downscale: params.allow_downscale,
+        }),
+        fit: params.fit,
+        smart_crop: params.smart_crop,
+        background: params.background,
+        filters: Filters {
+            sharpen: params.sharpen,
+            contrast: params.contrast,
+            grayscale: params.grayscale,
+            blur: params.blur,
+        },
+        watermark: params.watermark.zip(state.watermark.as_ref()).map(|(corner, watermark)| WatermarkPlacement {
+            watermark: watermark.clone(),
+            corner,
+            opacity: params.watermark_opacity.unwrap_or(state.config.watermark.opacity),
+            margin: params.watermark_margin.unwrap_or(state.config.watermark.margin),
+        }),
+        badge: params.badge.clone().map(|text| Badge {
+            text,
+            corner: params.badge_position.unwrap_or(Corner::BottomLeft),
+            size: params.badge_size.unwrap_or(badge_config.size),
+            margin: badge_config.margin,
+            text_color: badge_config.text_color,
+            background: params.badge_pill.unwrap_or(true).then_some(badge_config.background),
+        }),
+        // Stamped with the capture time by `process_in_pool`.
+        metadata: Some(embedded_metadata(state, &params.url, 0)).filter(|e| e.provenance || e.srgb_profile),
+        oversample: Some(params.oversample.unwrap_or(state.config.render.oversample)).filter(|factor| *factor != 1.0),
+    }
+}
+
+/// What `[metadata]` says to write into a thumbnail of `url`.
+fn embedded_metadata(state: &AppState, url: &str, captured_at: u64) -> Embed {
+    Embed {
+        source_url: url.to_string(),
+        captured_at,
+        provenance: state.config.metadata.embed_provenance,
+        srgb_profile: state.config.metadata.embed_srgb_profile,
+    }
+}
+
+fn validate_request(state: &AppState, params: &ThumbnailRequest) -> Result<(), AppError> {
     if params.url.is_empty() {
         return Err(AppError::BadRequest("URL cannot be empty".to_string()));
     }
@@ -136,155 +925,2187 @@ async fn generate_thumbnail(
         return Err(AppError::BadRequest(format!("Invalid URL scheme: {}", params.url)));
     }
 
-    let cache_key = build_cache_key(&params.url, params.width, params.height, &params.format);
-    debug!("Cache key: {}", cache_key);
-    
-    if let Some(cached_bytes) = state.cache.get(&cache_key).await? {
-        info!("Cache hit for {}", params.url);
-        let cached: CachedData = bincode::deserialize(&cached_bytes)
-            .map_err(|e| AppError::Internal(format!("Cache deserialization failed: {}", e)))?;
-        
-        let response = ThumbnailResponse {
-            url: params.url.clone(),
-            image_data: general_purpose::STANDARD.encode(&cached.image_data),
-            content_type: params.format.content_type().to_string(),
-            title: cached.title,
-            description: cached.description,
-            cached: true,
-        };
-        return Ok((StatusCode::OK, Json(response)));
+    let limits = &state.config.limits;
+    if params.url.len() > limits.max_url_length {
+        return Err(AppError::InvalidParameter(
+            "url_too_long",
+            format!("URL is {} bytes; the maximum is {}", params.url.len(), limits.max_url_length),
+        ));
+    }
+    if params.width == 0 || params.height == 0 {
+        return Err(AppError::InvalidParameter("invalid_dimensions", "width and height must be positive".to_string()));
+    }
+    if params.width > limits.max_width {
+        return Err(AppError::InvalidParameter(
+            "width_too_large",
+            format!("width {} exceeds the maximum of {}", params.width, limits.max_width),
+        ));
+    }
+    if params.height > limits.max_height {
+        return Err(AppError::InvalidParameter(
+            "height_too_large",
+            format!("height {} exceeds the maximum of {}", params.height, limits.max_height),
+        ));
+    }
+    let pixels = params.width as u64 * params.height as u64;
+    if pixels > limits.max_pixels {
+        return Err(AppError::InvalidParameter(
+            "too_many_pixels",
+            format!("{}x{} is {} pixels; the maximum is {}", params.width, params.height, pixels, limits.max_pixels),
+        ));
     }
 
-    info!("Cache miss - generating thumbnail for {}", params.url);
+    if params.watermark.is_some() && state.watermark.is_none() {
+        return Err(AppError::BadRequest("Watermark requested but none is configured".to_string()));
+    }
+    if params.watermark_opacity.is_some_and(|o| !(0.0..=1.0).contains(&o)) {
+        return Err(AppError::BadRequest("watermark_opacity must be between 0 and 1".to_string()));
+    }
 
-    let result = match tokio::time::timeout(
-        std::time::Duration::from_secs(45),
-        state.generator.generate(&params.url, params.width, params.height)
-    ).await {
-        Ok(Ok(result)) => result,
-        Ok(Err(e)) => {
-            error!("Thumbnail generation failed for {}: {}", params.url, e);
-            return Err(AppError::ThumbnailGeneration(format!("Failed to generate thumbnail: {}", e)));
-        }
-        Err(_) => {
-            error!("Thumbnail generation timed out for {}", params.url);
-            return Err(AppError::Timeout);
+    if let Some(badge) = &params.badge {
+        if badge.is_empty() || badge.chars().count() > MAX_BADGE_CHARS {
+            return Err(AppError::BadRequest(format!("badge must be 1-{} characters", MAX_BADGE_CHARS)));
         }
-    };
+    }
+    if params.badge_size.is_some_and(|s| !(6.0..=128.0).contains(&s)) {
+        return Err(AppError::BadRequest("badge_size must be between 6 and 128".to_string()));
+    }
 
-    let processed = match process_image(&result.image_data, params.width, params.height, &params.format) {
-        Ok(data) => data,
-        Err(e) => {
-            error!("Image processing failed for {}: {}", params.url, e);
-            return Err(e);
+    if params.smart_crop && params.fit != Fit::Cover {
+        return Err(AppError::BadRequest("smart_crop requires fit=cover".to_string()));
+    }
+    if params.background == Some(Background::Transparent) && matches!(params.format, ImageFormat::Jpeg) {
+        return Err(AppError::BadRequest("JPEG does not support a transparent background".to_string()));
+    }
+    if params.sharpen.is_some_and(|s| !(0.0..=10.0).contains(&s)) {
+        return Err(AppError::BadRequest("sharpen must be between 0 and 10".to_string()));
+    }
+    if params.contrast.is_some_and(|c| !(-100.0..=100.0).contains(&c)) {
+        return Err(AppError::BadRequest("contrast must be between -100 and 100".to_string()));
+    }
+    if params.blur.is_some_and(|b| !(0.0..=20.0).contains(&b)) {
+        return Err(AppError::BadRequest("blur must be between 0 and 20".to_string()));
+    }
+
+    if params.actions.len() > MAX_ACTIONS {
+        return Err(AppError::BadRequest(format!("At most {} actions are allowed", MAX_ACTIONS)));
+    }
+    let mut total_wait = 0u64;
+    for action in &params.actions {
+        match action {
+            Action::Click(selector) | Action::Type { selector, .. } if selector.trim().is_empty() => {
+                return Err(AppError::BadRequest("Action selectors cannot be empty".to_string()));
+            }
+            Action::Wait(ms) => total_wait = total_wait.saturating_add(*ms),
+            _ => {}
         }
-    };
-    
-    let cached_data = CachedData {
-        image_data: processed.clone(),
-        title: result.title.clone(),
-        description: result.description.clone(),
-    };
-    let cached_bytes = bincode::serialize(&cached_data)
-        .map_err(|e| AppError::Internal(format!("Cache serialization failed: {}", e)))?;
-    
-    if let Err(e) = state.cache.put(&cache_key, &cached_bytes).await {
-        error!("Failed to cache result for {}: {}", params.url, e);
+    }
+    if total_wait > MAX_ACTION_WAIT_MS {
+        return Err(AppError::BadRequest(format!("Actions may wait at most {} ms in total", MAX_ACTION_WAIT_MS)));
     }
 
-    let response = ThumbnailResponse {
-        url: params.url,
-        image_data: general_purpose::STANDARD.encode(&processed),
-        content_type: params.format.content_type().to_string(),
-        title: result.title,
-        description: result.description,
-        cached: false,
-    };
+    if params.max_bytes.is_some_and(|max| max < MIN_MAX_BYTES) {
+        return Err(AppError::BadRequest(format!("max_bytes must be at least {}", MIN_MAX_BYTES)));
+    }
 
-    Ok((StatusCode::OK, Json(response)))
-}
+    let oversample = params.oversample.unwrap_or(state.config.render.oversample);
+    if !(1.0..=MAX_OVERSAMPLE).contains(&oversample) {
+        return Err(AppError::BadRequest(format!("oversample must be between 1 and {}", MAX_OVERSAMPLE)));
+    }
+    let captured = (pixels as f64 * (oversample as f64).powi(2)) as u64;
+    if captured > state.config.processing.max_source_pixels {
+        return Err(AppError::InvalidParameter(
+            "too_many_pixels",
+            format!(
+                "oversampling {}x{} by {} captures {} pixels; the maximum is {}",
+                params.width, params.height, oversample, captured, state.config.processing.max_source_pixels
+            ),
+        ));
+    }
 
-async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "ok".to_string(),
-        chrome_available: state.generator.is_healthy().await,
-    })
+    Ok(())
 }
 
-fn process_image(
-    data: &[u8],
-    width: u32,
-    height: u32,
-    format: &ImageFormat,
-) -> Result<Vec<u8>, AppError> {
-    if data.is_empty() {
-        return Err(AppError::ImageProcessing("Empty image data".to_string()));
+async fn generate_thumbnail(
+    state: Arc<AppState>,
+    key: Option<ApiKey>,
+    mut params: ThumbnailRequest,
+    request_headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    normalize_url(&state, &mut params);
+    let thumbnail = render_thumbnail(&state, key.as_ref(), &params).await?;
+    if params.response_mode == ResponseMode::Binary {
+        return Ok(image_response(thumbnail, params.format.content_type(), request_headers));
     }
+    let headers = thumbnail.headers();
+    let response = build_response(&state, &params, thumbnail)?;
+    Ok((StatusCode::OK, headers, Json(response)).into_response())
+}
 
-    let img = image::load_from_memory(data)
-        .map_err(|e| AppError::ImageProcessing(format!("Failed to load image: {}", e)))?;
-    
-    let resized = img.resize(width, height, image::imageops::FilterType::Lanczos3);
-    
-    let mut output = Vec::new();
-    match format {
-        ImageFormat::Webp => {
-            resized.write_to(&mut std::io::Cursor::new(&mut output), image::ImageFormat::WebP)
-                .map_err(|e| AppError::ImageProcessing(format!("WebP encoding failed: {}", e)))?;
-        }
-        ImageFormat::Jpeg => {
-            let rgb = resized.to_rgb8();
-            rgb.write_to(&mut std::io::Cursor::new(&mut output), image::ImageFormat::Jpeg)
-                .map_err(|e| AppError::ImageProcessing(format!("JPEG encoding failed: {}", e)))?;
+/// Encodes a thumbnail for the JSON APIs, signing it when a key is configured.
+fn build_response(state: &AppState, params: &ThumbnailRequest, thumbnail: Thumbnail) -> Result<ThumbnailResponse, AppError> {
+    let content_type = params.format.content_type();
+
+    let signature = state
+        .signer
+        .as_ref()
+        .map(|signer| {
+            let payload = SignedPayload {
+                url: &params.url,
+                content_type,
+                image_sha256: format!("{:x}", Sha256::digest(&thumbnail.image_data)),
+                title: thumbnail.title.as_deref(),
+                description: thumbnail.description.as_deref(),
+            };
+            signer.sign(&payload).map(|signature| (signature, signer.key_id().to_string()))
+        })
+        .transpose()?;
+    let (signature, key_id) = signature.unzip();
+
+    let (image_data, image_url, image_expires_at) = match params.response_mode {
+        ResponseMode::Url => {
+            let (image_url, expires_at) = link_image(state, &thumbnail.image_data, content_type)?;
+            (None, Some(image_url), Some(expires_at))
         }
-        ImageFormat::Png => {
-            resized.write_to(&mut std::io::Cursor::new(&mut output), image::ImageFormat::Png)
-                .map_err(|e| AppError::ImageProcessing(format!("PNG encoding failed: {}", e)))?;
+        ResponseMode::Inline | ResponseMode::Binary => {
+            (Some(general_purpose::STANDARD.encode(&thumbnail.image_data)), None, None)
         }
-    }
-    
-    if output.is_empty() {
-        return Err(AppError::ImageProcessing("Encoded image is empty".to_string()));
-    }
-    
-    Ok(output)
+    };
+
+    Ok(ThumbnailResponse {
+        url: params.url.clone(),
+        image_data,
+        image_url,
+        image_expires_at,
+        content_type: content_type.to_string(),
+        title: thumbnail.title,
+        description: thumbnail.description,
+        cached: thumbnail.cached,
+        not_modified: thumbnail.not_modified,
+        paywalled: thumbnail.paywalled,
+        language: thumbnail.language,
+        excerpt: thumbnail.excerpt,
+        placeholder: thumbnail.placeholder,
+        degraded: thumbnail.degraded,
+        final_url: thumbnail.final_url,
+        quality: thumbnail.quality,
+        diagnostics: thumbnail.diagnostics,
+        provenance: thumbnail.provenance,
+        trace: thumbnail.trace,
+        signature,
+        key_id,
+    })
 }
 
-#[derive(Debug)]
-pub enum AppError {
-    Timeout,
-    BadRequest(String),
-    ThumbnailGeneration(String),
-    ImageProcessing(String),
-    Internal(String),
+/// Stores the image for `response_mode=url`, returning its link and expiry.
+fn link_image(state: &AppState, data: &[u8], content_type: &str) -> Result<(String, u64), AppError> {
+    let (token, expires_at) = state.links.put(data, content_type)?;
+    let base = state.config.links.base_url.as_deref().unwrap_or("").trim_end_matches('/');
+    Ok((format!("{}/images/{}", base, token), expires_at))
 }
 
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AppError::Timeout => write!(f, "Timeout"),
-            AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
-            AppError::ThumbnailGeneration(msg) => write!(f, "Thumbnail generation failed: {}", msg),
-            AppError::ImageProcessing(msg) => write!(f, "Image processing failed: {}", msg),
-            AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
-        }
-    }
+/// A thumbnail request sent over `/ws`; `id` is echoed on every event about it.
+#[derive(Debug, Deserialize)]
+struct WsRequest {
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    params: ThumbnailRequest,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WsEvent {
+    Accepted {
+        id: Option<serde_json::Value>,
+    },
+    Result {
+        id: Option<serde_json::Value>,
+        #[serde(flatten)]
+        thumbnail: Box<ThumbnailResponse>,
+        render_time_ms: u64,
+        queue_time_ms: u64,
+    },
+    Error {
+        id: Option<serde_json::Value>,
+        error: String,
+        code: &'static str,
+    },
+}
+
+impl WsEvent {
+    fn error(id: Option<serde_json::Value>, error: AppError) -> Self {
+        let (_, code, message) = error.parts();
+        error!("WebSocket request failed: {}", message);
+        WsEvent::Error { id, error: message, code }
+    }
+}
+
+/// Renders many thumbnails over one connection, for interactive clients where a request per
+/// thumbnail would dominate.
+async fn handle_ws(
+    State(state): State<Arc<AppState>>,
+    Caller(key): Caller,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| serve_ws(socket, state, key))
+}
+
+async fn serve_ws(socket: WebSocket, state: Arc<AppState>, key: Option<ApiKey>) {
+    let (mut sink, mut messages) = socket.split();
+    let (events, mut outgoing) = mpsc::unbounded_channel::<WsEvent>();
+
+    let writer = tokio::spawn(async move {
+        while let Some(event) = outgoing.recv().await {
+            let Ok(text) = serde_json::to_string(&event) else {
+                continue;
+            };
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Requests beyond the limit wait here, which stops reading further messages until one finishes.
+    let slots = Arc::new(Semaphore::new(MAX_WS_IN_FLIGHT));
+    while let Some(Ok(message)) = messages.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let request = match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(value) => {
+                let id = value.get("id").cloned();
+                serde_json::from_value::<WsRequest>(value).map_err(|e| (id, e))
+            }
+            Err(e) => Err((None, e)),
+        };
+        let mut request = match request {
+            Ok(request) => request,
+            Err((id, e)) => {
+                let _ = events.send(WsEvent::error(id, AppError::BadRequest(format!("Invalid request: {}", e))));
+                continue;
+            }
+        };
+
+        normalize_url(&state, &mut request.params);
+        if request.params.response_mode == ResponseMode::Binary {
+            let error = AppError::BadRequest("response_mode=binary is not available over /ws".to_string());
+            let _ = events.send(WsEvent::error(request.id, error));
+            continue;
+        }
+
+        let Ok(permit) = slots.clone().acquire_owned().await else {
+            break;
+        };
+        let _ = events.send(WsEvent::Accepted { id: request.id.clone() });
+
+        let (state, key, events) = (state.clone(), key.clone(), events.clone());
+        tokio::spawn(async move {
+            let _permit = permit;
+            let WsRequest { id, params } = request;
+            let event = match render_thumbnail(&state, key.as_ref(), &params).await {
+                Ok(thumbnail) => {
+                    let (render_time, queue_time) = (thumbnail.render_time, thumbnail.queue_time);
+                    match build_response(&state, &params, thumbnail) {
+                        Ok(thumbnail) => WsEvent::Result {
+                            id,
+                            thumbnail: Box::new(thumbnail),
+                            render_time_ms: render_time.as_millis() as u64,
+                            queue_time_ms: queue_time.as_millis() as u64,
+                        },
+                        Err(e) => WsEvent::error(id, e),
+                    }
+                }
+                Err(e) => WsEvent::error(id, e),
+            };
+            let _ = events.send(event);
+        });
+    }
+
+    // The writer finishes once in-flight renders have reported or the client has gone.
+    drop(events);
+    let _ = writer.await;
+}
+
+/// Serves the encoded image itself. HEAD requests get the same headers without the body, and
+/// only for cached thumbnails.
+async fn handle_raw_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Caller(key): Caller,
+    method: Method,
+    Query(mut params): Query<ThumbnailRequest>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info!("{} /thumbnail/raw with params: {:?}", method, params);
+    params.cache_only = method == Method::HEAD;
+    apply_cache_control(&mut params, key.as_ref(), &headers);
+    normalize_url(&state, &mut params);
+    let thumbnail = render_thumbnail(&state, key.as_ref(), &params).await?;
+    Ok(image_response(thumbnail, params.format.content_type(), &headers))
+}
+
+/// The image as the response body, answering `If-None-Match` with 304.
+fn image_response(thumbnail: Thumbnail, content_type: &str, request_headers: &HeaderMap) -> Response {
+    let etag = format!("\"{:x}\"", Sha256::digest(&thumbnail.image_data));
+    let timing = thumbnail.headers();
+
+    if etag_matches(request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, timing, [(header::ETAG, etag)]).into_response();
+    }
+
+    let mut response = (
+        StatusCode::OK,
+        timing,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::ETAG, etag),
+        ],
+        thumbnail.image_data,
+    ).into_response();
+    if let Some(quality) = thumbnail.quality {
+        response.headers_mut().insert(HeaderName::from_static("x-image-quality"), u16::from(quality).into());
+    }
+    if thumbnail.placeholder {
+        response.headers_mut().insert(HeaderName::from_static("x-placeholder"), HeaderValue::from_static("true"));
+    }
+    if thumbnail.degraded {
+        response.headers_mut().insert(HeaderName::from_static("x-degraded"), HeaderValue::from_static("true"));
+    }
+    response
+}
+
+fn etag_matches(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"))
+}
+
+/// Captures a whole page as one PNG, streamed to the client as each viewport-high strip is
+/// taken and encoded, so memory stays flat however long the page is. Full-page captures aren't
+/// cached; they count against the tenant like a render.
+async fn handle_full_page(
+    State(state): State<Arc<AppState>>,
+    Caller(key): Caller,
+    Query(mut query): Query<FullPageQuery>,
+) -> Result<Response, AppError> {
+    info!("GET /thumbnail/full for {}", query.url);
+    query.url = normalize::normalize(&query.url, &state.config.urls);
+    let (audit_key, url) = (key.clone(), query.url.clone());
+    let response = stream_full_page(state.clone(), key, query).await;
+    // Captures that got as far as streaming are audited when the stream ends.
+    if let Err(e) = &response {
+        audit(&state, requester(audit_key.as_ref()), "full_page", &url, Err(e));
+    }
+    response
+}
+
+async fn stream_full_page(state: Arc<AppState>, key: Option<ApiKey>, query: FullPageQuery) -> Result<Response, AppError> {
+    if let Renderer::Unavailable(reason) = &state.generator {
+        return Err(AppError::BrowserUnavailable(format!("Full-page captures need a browser: {}", reason)));
+    }
+    if state.generator.local().is_none() {
+        return Err(AppError::BadRequest("Full-page captures need a local browser and are unavailable in queue mode".to_string()));
+    }
+    let tenant = key.as_ref().map(|k| k.tenant.as_ref());
+    if let Some(tenant) = tenant {
+        tenant.usage.record_request();
+        if !tenant.try_acquire_rate() {
+            return Err(AppError::RateLimited(format!("Tenant {} exceeded its request rate", tenant.name)));
+        }
+    }
+
+    let limits = &state.config.limits;
+    if query.url.len() > limits.max_url_length {
+        return Err(AppError::InvalidParameter(
+            "url_too_long",
+            format!("URL is {} bytes; the maximum is {}", query.url.len(), limits.max_url_length),
+        ));
+    }
+    let host = Url::parse(&query.url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .and_then(|u| u.host_str().map(str::to_string))
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid URL: {}", query.url)))?;
+    if query.width == 0 || query.viewport_height == 0 || query.width > limits.max_width || query.viewport_height > limits.max_height {
+        return Err(AppError::InvalidParameter(
+            "invalid_dimensions",
+            "width and viewport_height are outside the configured limits".to_string(),
+        ));
+    }
+    if query.max_height == Some(0) {
+        return Err(AppError::InvalidParameter("invalid_dimensions", "max_height must be positive".to_string()));
+    }
+    let max_height = query.max_height.map_or(limits.max_full_page_height, |h| h.min(limits.max_full_page_height));
+
+    check_host_allowed(&state, tenant, &host)?;
+    if let Some(retry_after) = state.breaker.check(&host) {
+        return Err(AppError::CircuitOpen(host, retry_after));
+    }
+    let respect_robots = tenant.and_then(|t| t.respect_robots).unwrap_or(state.config.robots.enabled);
+    if respect_robots && !robots_allows(&state, key.as_ref(), &query.url).await {
+        info!("robots.txt disallows {}", query.url);
+        return Err(AppError::Blocked("robots_disallowed", format!("robots.txt disallows {}", query.url)));
+    }
+
+    let mut options = RenderOptions {
+        disable_javascript: query.javascript == Some(false),
+        ignore_certificate_errors: state.config.render.ignore_certificate_errors,
+        consent: state.config.render.consent,
+        ..RenderOptions::default()
+    };
+    options.redirects.hosts = host_rules(&state, tenant);
+    if let Some(profile) = site_profile(&state, &host) {
+        apply_site_profile(&mut options, &profile, query.width, query.viewport_height);
+    }
+    // The image is as wide as the viewport it was captured at.
+    options.viewport = None;
+    if let Some(consent) = query.consent {
+        options.consent = consent;
+    }
+
+    let started = Instant::now();
+    let deadline = Duration::from_secs(state.config.limits.max_full_page_secs.max(1));
+    let (info, info_rx) = oneshot::channel();
+    let (strips, strips_rx) = mpsc::channel(1);
+    let capture = tokio::spawn({
+        let (state, host, url) = (state.clone(), host.clone(), query.url.clone());
+        let (width, height) = (query.width, query.viewport_height);
+        async move {
+            let Some(generator) = state.generator.local() else {
+                return Ok(());
+            };
+            let _host_permit = state.hosts.acquire(&host).await?;
+            let limits = FullPageLimits { max_height, deadline: deadline.saturating_sub(started.elapsed()) };
+            generator.capture_full_page(&url, width, height, &options, limits, StripSender { info, strips }).await
+        }
+    });
+
+    let request_timeout = state.settings.read().unwrap_or_else(|e| e.into_inner()).request_timeout;
+    let info = match tokio::time::timeout(request_timeout, info_rx).await {
+        Ok(Ok(info)) => info,
+        Ok(Err(_)) => {
+            let error = match capture.await {
+                Ok(Err(e)) => capture_error(&state, &host, e),
+                Ok(Ok(())) => AppError::Internal("Capture ended without loading the page".to_string()),
+                Err(e) => AppError::Internal(format!("Capture task failed: {}", e)),
+            };
+            error!("Full-page capture failed for {}: {}", query.url, error);
+            record_usage(&state, key.as_ref(), &UsageEvent::Error);
+            return Err(error);
+        }
+        Err(_) => {
+            error!("Full-page capture timed out for {}", query.url);
+            capture.abort();
+            state.breaker.record_failure(&host);
+            record_usage(&state, key.as_ref(), &UsageEvent::Error);
+            return Err(AppError::Timeout);
+        }
+    };
+    state.breaker.record_success(&host);
+    // Dropping the strip receiver on the way out stops the capture.
+    check_redirect_allowed(&state, tenant, info.final_url.as_deref())?;
+
+    let encoder = StripEncoder::new(info.width, info.height)?;
+    let strip_pixels = info.width as u64 * query.viewport_height as u64;
+    let stream = FullPageStream { encoder, strips: strips_rx, capture, sent: 0 };
+    let final_url = info.final_url.filter(|final_url| *final_url != query.url);
+    let body = futures::stream::unfold(Some(stream), move |stream| {
+        let (state, key, url, host) = (state.clone(), key.clone(), query.url.clone(), host.clone());
+        let final_url = final_url.clone();
+        async move {
+            let FullPageStream { mut encoder, mut strips, capture, sent } = stream?;
+            let next = match strips.recv().await {
+                Some(strip) => {
+                    let pushed = state
+                        .images
+                        .run(strip_pixels, move || encoder.push(&strip).map(|chunk| (encoder, chunk)))
+                        .await;
+                    pushed.map(|(encoder, chunk)| {
+                        let sent = sent + chunk.len() as u64;
+                        (chunk, Some(FullPageStream { encoder, strips, capture, sent }))
+                    })
+                }
+                // The capture drops its sender once it has finished or failed.
+                None => {
+                    let finished = match capture.await {
+                        Ok(Ok(())) => encoder.finish(),
+                        Ok(Err(e)) => Err(capture_error(&state, &host, e)),
+                        Err(e) => Err(AppError::Internal(format!("Capture task failed: {}", e))),
+                    };
+                    finished.map(|chunk| {
+                        let bytes = sent + chunk.len() as u64;
+                        record_usage(&state, key.as_ref(), &UsageEvent::Render { bytes, render_time: started.elapsed() });
+                        audit(&state, requester(key.as_ref()), "full_page", &url, Ok((AuditOutcome::Rendered, final_url, bytes)));
+                        info!("Streamed full-page capture of {} ({} bytes)", url, bytes);
+                        (chunk, None)
+                    })
+                }
+            };
+            Some(match next {
+                Ok((chunk, stream)) => (Ok(chunk), stream),
+                Err(e) => {
+                    // Headers are already sent, so all that's left is to cut the body short.
+                    error!("Full-page capture of {} failed mid-stream: {}", url, e);
+                    record_usage(&state, key.as_ref(), &UsageEvent::Error);
+                    audit(&state, requester(key.as_ref()), "full_page", &url, Err(&e));
+                    (Err(e), None)
+                }
+            })
+        }
+    });
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, ImageFormat::Png.content_type())],
+        Body::from_stream(body),
+    ).into_response())
+}
+
+/// What the `/thumbnail/full` body carries from one chunk to the next.
+struct FullPageStream {
+    encoder: StripEncoder,
+    strips: mpsc::Receiver<Vec<u8>>,
+    capture: JoinHandle<anyhow::Result<()>>,
+    /// Bytes of PNG sent so far.
+    sent: u64,
+}
+
+/// Turns a failed capture into its response error, counting hangs against the host as renders do.
+fn capture_error(state: &AppState, host: &str, e: anyhow::Error) -> AppError {
+    match e.downcast::<RenderError>() {
+        Ok(render_error) => {
+            if matches!(render_error, RenderError::NavigationTimeout(_)) {
+                state.breaker.record_failure(host);
+            }
+            render_failure(&render_error)
+        }
+        Err(e) => {
+            state.breaker.record_failure(host);
+            AppError::ThumbnailGeneration(format!("Failed to capture page: {}", e))
+        }
+    }
+}
+
+/// Renders each page as a tile through the usual thumbnail path, so tiles are cached and charged
+/// like single thumbnails, then composites them into one grid image.
+async fn handle_collage(
+    State(state): State<Arc<AppState>>,
+    Caller(key): Caller,
+    Json(params): Json<CollageRequest>,
+) -> Result<Response, AppError> {
+    info!("POST /collage for {:?}", params.urls);
+    let grid = collage_grid(&state, &params)?;
+    let count = params.urls.len() as u32;
+    let (tile_width, tile_height) = grid
+        .tile_size(count, params.width, params.height)
+        .ok_or_else(|| AppError::BadRequest("width and height leave no room for tiles after gaps".to_string()))?;
+
+    let mut requests = params
+        .urls
+        .iter()
+        .map(|url| collage_tile_request(&params.tile, url, tile_width, tile_height))
+        .collect::<Result<Vec<_>, _>>()?;
+    for request in &mut requests {
+        normalize_url(&state, request);
+    }
+    let thumbnails = futures::future::try_join_all(
+        requests.iter().map(|request| render_thumbnail(&state, key.as_ref(), request)),
+    ).await?;
+
+    let tiles: Vec<Vec<u8>> = thumbnails.iter().map(|t| t.image_data.clone()).collect();
+    let (width, height, format) = (params.width, params.height, params.format);
+    // Each tile is decoded next to the canvas.
+    let pixels = width as u64 * height as u64 * 2;
+    let encoded = state.images.run(pixels, move || compose_grid(&tiles, width, height, &grid, &format)).await?;
+    let content_type = params.format.content_type();
+
+    if params.response_mode == ResponseMode::Binary {
+        return Ok(([(header::CONTENT_TYPE, content_type)], encoded.data).into_response());
+    }
+    let (image_data, image_url, image_expires_at) = match params.response_mode {
+        ResponseMode::Url => {
+            let (image_url, expires_at) = link_image(&state, &encoded.data, content_type)?;
+            (None, Some(image_url), Some(expires_at))
+        }
+        ResponseMode::Inline | ResponseMode::Binary => (Some(general_purpose::STANDARD.encode(&encoded.data)), None, None),
+    };
+    let tiles = params
+        .urls
+        .into_iter()
+        .zip(thumbnails)
+        .map(|(url, thumbnail)| CollageTile {
+            url,
+            title: thumbnail.title,
+            final_url: thumbnail.final_url,
+            cached: thumbnail.cached,
+        })
+        .collect();
+    Ok(Json(CollageResponse {
+        image_data,
+        image_url,
+        image_expires_at,
+        content_type: content_type.to_string(),
+        tiles,
+    }).into_response())
+}
+
+/// Checks the collage's own parameters; each tile is validated like a `/thumbnail` request.
+fn collage_grid(state: &AppState, params: &CollageRequest) -> Result<Grid, AppError> {
+    let count = params.urls.len();
+    if !(MIN_COLLAGE_URLS..=MAX_COLLAGE_URLS).contains(&count) {
+        return Err(AppError::BadRequest(format!(
+            "A collage takes {}-{} URLs, not {}",
+            MIN_COLLAGE_URLS, MAX_COLLAGE_URLS, count
+        )));
+    }
+    let limits = &state.config.limits;
+    if params.width == 0 || params.height == 0 {
+        return Err(AppError::InvalidParameter("invalid_dimensions", "width and height must be positive".to_string()));
+    }
+    if params.width > limits.max_width || params.height > limits.max_height
+        || params.width as u64 * params.height as u64 > limits.max_pixels
+    {
+        return Err(AppError::InvalidParameter(
+            "collage_too_large",
+            format!("{}x{} exceeds the configured size limits", params.width, params.height),
+        ));
+    }
+    let columns = params.columns.unwrap_or(if count == 4 { 2 } else { count as u32 });
+    if columns == 0 || columns as usize > count {
+        return Err(AppError::BadRequest(format!("columns must be between 1 and {}", count)));
+    }
+    if params.gap > MAX_COLLAGE_GAP {
+        return Err(AppError::BadRequest(format!("gap must be at most {}", MAX_COLLAGE_GAP)));
+    }
+    if params.background == Some(Background::Transparent) && matches!(params.format, ImageFormat::Jpeg) {
+        return Err(AppError::BadRequest("JPEG does not support a transparent background".to_string()));
+    }
+    Ok(Grid {
+        columns,
+        gap: params.gap,
+        background: params.background.unwrap_or(Background::Color(Color::WHITE)),
+    })
+}
+
+/// A tile's `/thumbnail` request: the caller's tile options with the URL and size filled in.
+/// Tiles are PNG so the collage is only lossy-encoded once.
+fn collage_tile_request(
+    options: &serde_json::Map<String, serde_json::Value>,
+    url: &str,
+    width: u32,
+    height: u32,
+) -> Result<ThumbnailRequest, AppError> {
+    let mut tile = options.clone();
+    tile.insert("url".to_string(), url.into());
+    tile.insert("width".to_string(), width.into());
+    tile.insert("height".to_string(), height.into());
+    tile.insert("format".to_string(), "png".into());
+    tile.remove("response_mode");
+    serde_json::from_value(serde_json::Value::Object(tile))
+        .map_err(|e| AppError::BadRequest(format!("Invalid tile options: {}", e)))
+}
+
+/// Serves an image stored for a `response_mode=url` response. The random token is the
+/// capability, so no API key is needed.
+async fn handle_linked_image(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let image = state
+        .links
+        .get(&token)?
+        .ok_or_else(|| AppError::NotFound("Image link is unknown or has expired".to_string()))?;
+
+    let etag = format!("\"{}\"", image.hash);
+    let max_age = image.expires_at.saturating_sub(cache::now_secs());
+    let cache_control = format!("private, max-age={}", max_age);
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)]).into_response());
+    }
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, image.content_type),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        image.data,
+    ).into_response())
+}
+
+/// Removes audit entries past `audit.retention_days` once an hour.
+async fn purge_old_audit_entries(state: Arc<AppState>) {
+    let retention = state.config.audit.retention_days.saturating_mul(86_400);
+    let mut interval = tokio::time::interval(AUDIT_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state.audit.purge_before(cache::now_secs().saturating_sub(retention)) {
+            Ok(0) => {}
+            Ok(removed) => info!("Purged {} audit entries past retention", removed),
+            Err(e) => error!("Failed to purge old audit entries: {}", e),
+        }
+    }
+}
+
+async fn purge_expired_links(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(LINK_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state.links.purge_expired() {
+            Ok(0) => {}
+            Ok(removed) => debug!("Purged {} expired image links", removed),
+            Err(e) => error!("Failed to purge expired image links: {}", e),
+        }
+    }
+}
+
+/// Renders on behalf of `key`, charging the outcome to its tenant.
+async fn render_thumbnail(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    params: &ThumbnailRequest,
+) -> Result<Thumbnail, AppError> {
+    let tenant = key.map(|k| k.tenant.as_ref());
+    if let Some(tenant) = tenant {
+        tenant.usage.record_request();
+        if !tenant.try_acquire_rate() {
+            let e = AppError::RateLimited(format!("Tenant {} exceeded its request rate", tenant.name));
+            audit(state, requester(key), "thumbnail", &params.url, Err(&e));
+            return Err(e);
+        }
+    }
+
+    let namespace = tenant.map_or(state.config.cache.namespace.as_str(), |t| t.namespace.as_str());
+    let result = render_for_namespace(state, key, namespace, params).await;
+
+    let event = match &result {
+        Ok(thumbnail) if thumbnail.cached => UsageEvent::CacheHit { bytes: thumbnail.image_data.len() as u64 },
+        Ok(thumbnail) => UsageEvent::Render {
+            bytes: thumbnail.image_data.len() as u64,
+            render_time: thumbnail.render_time,
+        },
+        Err(_) => UsageEvent::Error,
+    };
+    record_usage(state, key, &event);
+    state.alerts.record(matches!(&result, Err(e) if e.is_render_failure()));
+
+    let result = match result {
+        Err(e) if params.fallback == Some(Fallback::Placeholder) && e.is_render_failure() => {
+            warn!("Serving a placeholder for {}: {}", params.url, e);
+            placeholder_thumbnail(state, key, params, !matches!(e.untraced(), AppError::CircuitOpen(..))).await
+        }
+        result => result,
+    };
+    let outcome = match &result {
+        Ok(thumbnail) => {
+            let outcome = match (thumbnail.placeholder, thumbnail.cached) {
+                (true, _) => AuditOutcome::Placeholder,
+                (false, true) => AuditOutcome::CacheHit,
+                (false, false) => AuditOutcome::Rendered,
+            };
+            Ok((outcome, thumbnail.final_url.clone(), thumbnail.image_data.len() as u64))
+        }
+        Err(e) => Err(e),
+    };
+    audit(state, requester(key), "thumbnail", &params.url, outcome);
+    result
+}
+
+/// The tenant and key fingerprint a request is audited under.
+fn requester(key: Option<&ApiKey>) -> Requester<'_> {
+    key.map_or(Requester { tenant: usage::ANONYMOUS, key: "-" }, |k| Requester { tenant: &k.tenant.name, key: &k.id })
+}
+
+/// Appends a page request to the audit log: its outcome, final URL, and bytes served, or its error.
+fn audit(
+    state: &AppState,
+    requester: Requester,
+    endpoint: &str,
+    url: &str,
+    outcome: Result<(AuditOutcome, Option<String>, u64), &AppError>,
+) {
+    let (outcome, final_url, status, code, bytes_served) = match outcome {
+        Ok((outcome, final_url, bytes)) => (outcome, final_url, StatusCode::OK, None, bytes),
+        Err(e) => {
+            let (status, code, _) = e.parts();
+            (AuditOutcome::Error, None, status, Some(code.to_string()), 0)
+        }
+    };
+    let entry = AuditEntry {
+        at: cache::now_secs(),
+        tenant: requester.tenant.to_string(),
+        key: requester.key.to_string(),
+        endpoint: endpoint.to_string(),
+        url: url.to_string(),
+        final_url,
+        outcome,
+        status: status.as_u16(),
+        code,
+        bytes_served,
+    };
+    if let Err(e) = state.audit.record(&entry) {
+        error!("Failed to record audit entry for {}: {}", url, e);
+    }
+}
+
+/// Appends a fetch made while serving `requester`, with the bytes received or `None` if it failed.
+fn audit_fetch(state: &AppState, requester: Requester, endpoint: &str, url: &str, received: Option<u64>) {
+    if let Err(e) = state.audit.record(&AuditEntry::fetch(requester, endpoint, url, received)) {
+        error!("Failed to record audit entry for {}: {}", url, e);
+    }
+}
+
+/// Asks robots.txt whether `url` may be rendered, auditing the fetch if one was made.
+async fn robots_allows(state: &AppState, key: Option<&ApiKey>, url: &str) -> bool {
+    let verdict = state.robots.is_allowed(url).await;
+    if let Some((robots_url, received)) = verdict.fetched {
+        audit_fetch(state, requester(key), "robots", &robots_url, received);
+    }
+    verdict.allowed
+}
+
+/// Counts a request's outcome against its tenant and in the usage store.
+fn record_usage(state: &AppState, key: Option<&ApiKey>, event: &UsageEvent) {
+    if let Some(tenant) = key.map(|k| k.tenant.as_ref()) {
+        match event {
+            UsageEvent::CacheHit { .. } => tenant.usage.record_cache_hit(),
+            UsageEvent::Render { .. } => tenant.usage.record_render(),
+            UsageEvent::Error => tenant.usage.record_error(),
+        }
+    }
+    let (tenant_name, key_id) = key.map_or((usage::ANONYMOUS, "-"), |k| (k.tenant.name.as_str(), k.id.as_str()));
+    if let Err(e) = state.usage.record(tenant_name, key_id, cache::now_secs(), event) {
+        error!("Failed to record usage for {}: {}", tenant_name, e);
+    }
+}
+
+/// Generates the `fallback=placeholder` image, tinted with the site's favicon color when
+/// `fetch_favicon` is set and the favicon can be fetched quickly. Placeholders aren't cached,
+/// so the next request tries the page again.
+async fn placeholder_thumbnail(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    params: &ThumbnailRequest,
+    fetch_favicon: bool,
+) -> Result<Thumbnail, AppError> {
+    let url = Url::parse(&params.url).map_err(|e| AppError::BadRequest(format!("Invalid URL {}: {}", params.url, e)))?;
+    let host = url.host_str().unwrap_or_default();
+    let domain = host.strip_prefix("www.").unwrap_or(host);
+
+    let favicon = if fetch_favicon {
+        let favicon = format!("{}://{}/favicon.ico", url.scheme(), url.authority());
+        let icon = tokio::time::timeout(FAVICON_TIMEOUT, state.probe.fetch_image(&favicon, params.ignore_tls_errors))
+            .await
+            .ok()
+            .flatten();
+        audit_fetch(state, requester(key), "favicon", &favicon, icon.as_ref().map(|icon| icon.len() as u64));
+        icon.filter(|icon| state.images.check_source(icon).is_ok())
+    } else {
+        None
+    };
+
+    let (placeholder, domain) = (state.placeholder.clone(), domain.to_string());
+    let (width, height, format) = (params.width, params.height, params.format);
+    let pixels = width as u64 * height as u64;
+    let encoded = state
+        .images
+        .run(pixels, move || {
+            let brand = favicon.and_then(|icon| dominant_color(&icon));
+            render_placeholder(&placeholder, &domain, brand, width, height, &format)
+        })
+        .await?;
+    Ok(Thumbnail {
+        image_data: encoded.data,
+        title: None,
+        description: None,
+        final_url: None,
+        quality: None,
+        diagnostics: None,
+        provenance: None,
+        cached: false,
+        stale: false,
+        not_modified: false,
+        paywalled: false,
+        language: None,
+        excerpt: None,
+        placeholder: true,
+        degraded: false,
+        render_time: Duration::ZERO,
+        queue_time: Duration::ZERO,
+        trace: None,
+    })
+}
+
+/// Resizes and encodes the source on the image pool, once its size has been checked.
+/// `captured_at` is the time embedded as the image's creation date.
+async fn process_in_pool(
+    state: &AppState,
+    source: Vec<u8>,
+    params: &ThumbnailRequest,
+    mut options: ProcessOptions,
+    captured_at: u64,
+) -> Result<Encoded, AppError> {
+    if let Some(embed) = &mut options.metadata {
+        embed.captured_at = captured_at;
+    }
+    let pixels = state.images.check_source(&source)? + params.width as u64 * params.height as u64;
+    let (width, height, format) = (params.width, params.height, params.format);
+    state.images.run(pixels, move || process_image(&source, width, height, &format, &options)).await
+}
+
+/// Downloads a paywalled page's `og:image` to use instead of its capture, if its host may be
+/// fetched and it's in a format we can decode.
+async fn fetch_og_image(state: &AppState, key: Option<&ApiKey>, url: &str, params: &ThumbnailRequest) -> Option<Vec<u8>> {
+    let tenant = key.map(|k| k.tenant.as_ref());
+    let host = Url::parse(url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .and_then(|u| u.host_str().map(str::to_string))?;
+    if check_host_allowed(state, tenant, &host).is_err() {
+        info!("Not using og:image {} from a host that isn't allowed", url);
+        return None;
+    }
+    let insecure = params.ignore_tls_errors || state.config.render.ignore_certificate_errors;
+    let image = state.probe.fetch_image(url, insecure).await;
+    audit_fetch(state, requester(key), "og_image", url, image.as_ref().map(|image| image.len() as u64));
+    let image = image?;
+    if let Err(e) = state.images.check_source(&image) {
+        info!("Not using og:image {}: {}", url, e);
+        return None;
+    }
+    match image::guess_format(&image) {
+        Ok(image::ImageFormat::Png | image::ImageFormat::Jpeg | image::ImageFormat::WebP) => {
+            info!("Using og:image {} for paywalled {}", url, params.url);
+            Some(image)
+        }
+        _ => {
+            info!("Not using og:image {} in an unsupported format", url);
+            None
+        }
+    }
+}
+
+/// The named session profile, if the caller's tenant may use it.
+fn session_profile(state: &AppState, tenant: Option<&Tenant>, name: &str) -> Result<SessionConfig, AppError> {
+    let sessions = state.sessions.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let profile = sessions
+        .iter()
+        .find(|session| session.name == name)
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown session: {}", name)))?;
+    let allowed = profile.tenants.iter().any(|t| t == "*") || tenant.is_some_and(|t| profile.tenants.contains(&t.name));
+    if !allowed {
+        return Err(AppError::Blocked("session_not_allowed", format!("Session {} is not enabled for this key", name)));
+    }
+    Ok(profile.clone())
+}
+
+/// The first configured site profile matching `host`.
+fn site_profile(state: &AppState, host: &str) -> Option<SiteProfile> {
+    let sites = state.sites.read().unwrap_or_else(|e| e.into_inner()).clone();
+    sites.iter().find(|site| site.hosts.iter().any(|pattern| domain_matches(pattern, host))).cloned()
+}
+
+fn apply_site_profile(options: &mut RenderOptions, profile: &SiteProfile, width: u32, height: u32) {
+    debug!("Applying site profile for {:?}", profile.hosts);
+    options.wait = profile.wait;
+    options.wait_for_selector = profile.wait_for_selector.clone();
+    options.extra_delay_ms = profile.delay_ms;
+    options.hide_selectors = profile.hide_selectors.clone();
+    options.viewport = match (profile.viewport_width, profile.viewport_height) {
+        (None, None) => None,
+        (w, h) => Some((w.unwrap_or(width), h.unwrap_or(height))),
+    };
+    if profile.dark_mode {
+        options.media.color_scheme = Some(ColorScheme::Dark);
+    }
+    if let Some(consent) = profile.consent {
+        options.consent = consent;
+    }
+}
+
+/// Checks a target host against the global allowlist and the tenant's allowed domains.
+fn check_host_allowed(state: &AppState, tenant: Option<&Tenant>, host: &str) -> Result<(), AppError> {
+    let globally_allowed = {
+        let settings = state.settings.read().unwrap_or_else(|e| e.into_inner());
+        (settings.allowed_domains.is_empty() || settings.allowed_domains.iter().any(|d| domain_matches(d, host)))
+            && !settings.blocked_domains.iter().any(|d| domain_matches(d, host))
+    };
+    if !globally_allowed {
+        return Err(AppError::Blocked("domain_not_allowed", format!("{} is not an allowed domain", host)));
+    }
+    if tenant.is_some_and(|t| !t.allows_host(host)) {
+        return Err(AppError::Blocked("domain_not_allowed", format!("{} is not an allowed domain for this key", host)));
+    }
+    Ok(())
+}
+
+/// The rules `check_host_allowed` applies, for the browser to check each navigation against.
+fn host_rules(state: &AppState, tenant: Option<&Tenant>) -> HostRules {
+    let settings = state.settings.read().unwrap_or_else(|e| e.into_inner());
+    HostRules {
+        allowed: settings.allowed_domains.clone(),
+        blocked: settings.blocked_domains.clone(),
+        tenant_allowed: tenant.map(|t| t.allowed_domains.clone()).unwrap_or_default(),
+    }
+}
+
+/// The response for a render that failed a budget or policy; a redirect to a host that isn't
+/// allowed is refused like a request for it.
+fn render_failure(e: &RenderError) -> AppError {
+    match e {
+        RenderError::DisallowedRedirect(_) => AppError::Blocked(e.code(), e.to_string()),
+        _ => AppError::Render(e.code(), e.to_string()),
+    }
+}
+
+/// Applies `check_host_allowed` to the URL a render was redirected to, if any.
+fn check_redirect_allowed(state: &AppState, tenant: Option<&Tenant>, final_url: Option<&str>) -> Result<(), AppError> {
+    // Chrome's own error pages aren't fetched from anywhere.
+    let Some(url) = final_url.and_then(|u| Url::parse(u).ok()).filter(|u| matches!(u.scheme(), "http" | "https")) else {
+        return Ok(());
+    };
+    let Some(host) = url.host_str() else {
+        return Ok(());
+    };
+    let allowed = check_host_allowed(state, tenant, host);
+    if allowed.is_err() {
+        info!("Rejecting render redirected to {}", url);
+    }
+    allowed
+}
+
+/// Returns the thumbnail from `namespace`'s cache, rendering and caching it on a miss.
+async fn render_for_namespace(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    namespace: &str,
+    params: &ThumbnailRequest,
+) -> Result<Thumbnail, AppError> {
+    let tracer = Tracer::new(params.trace);
+    let result = render_traced(state, key, namespace, params, &tracer).await;
+    let Some(trace) = tracer.finish() else {
+        return result;
+    };
+    info!("Trace for {}: {}", params.url, trace);
+    // Slow pages are the ones worth tracing, so failures and timeouts keep the stages they got through.
+    match result {
+        Ok(thumbnail) => Ok(Thumbnail { trace: Some(trace), ..thumbnail }),
+        Err(e) => Err(AppError::Traced(Box::new(e), trace)),
+    }
+}
+
+async fn render_traced(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    namespace: &str,
+    params: &ThumbnailRequest,
+    tracer: &Tracer,
+) -> Result<Thumbnail, AppError> {
+    let tenant = key.map(|k| k.tenant.as_ref());
+    info!("Generating thumbnail for {} ({}x{}) format={:?}", params.url, params.width, params.height, params.format);
+
+    validate_request(state, params)?;
+
+    let host = Url::parse(&params.url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .ok_or_else(|| AppError::BadRequest(format!("URL has no host: {}", params.url)))?;
+
+    check_host_allowed(state, tenant, &host)?;
+    // Anonymous callers never get to turn certificate checks off; the config can do it for everyone.
+    if params.ignore_tls_errors && !tenant.is_some_and(|t| t.allow_insecure_tls) {
+        return Err(AppError::Blocked(
+            "insecure_tls_not_allowed",
+            "ignore_tls_errors is not enabled for this key".to_string(),
+        ));
+    }
+    // Checked before the cache, since entries rendered with a session show what's behind its login.
+    let session = params.session.as_deref().map(|name| session_profile(state, tenant, name)).transpose()?;
+
+    let options = build_process_options(state, params);
+    let cache_key = build_cache_key(state, &options, params);
+    debug!("Cache key: {}:{}", namespace, cache_key);
+
+    if let Some(version) = params.version {
+        let entry = state.cache.get_version(namespace, &cache_key, version).await?.ok_or_else(|| {
+            AppError::NotFound(format!("No render of {} from {} is kept for these options", params.url, version))
+        })?;
+        check_redirect_allowed(state, tenant, entry.final_url.as_deref())?;
+        return Ok(Thumbnail::from_cache(entry, false));
+    }
+    
+    // Debug requests want diagnostics from a fresh render; refreshes replace the entry.
+    let looking = Instant::now();
+    let fresh = (params.debug || params.refresh) && !params.cache_only;
+    let cached = if fresh { None } else { state.cache.get(namespace, &cache_key).await? };
+    if let Some(cached) = cached {
+        tracer.record("cache_lookup", looking);
+        info!("Cache hit for {}", params.url);
+        // The allowlists may have changed since the entry was rendered.
+        check_redirect_allowed(state, tenant, cached.final_url.as_deref())?;
+        let mut thumbnail = Thumbnail::from_cache(cached, false);
+        thumbnail.stale = !state.generator.is_available();
+        return Ok(thumbnail);
+    }
+
+    tracer.record("cache_lookup", looking);
+    if params.cache_only {
+        return Err(AppError::NotFound(format!("No cached thumbnail of {} for these options", params.url)));
+    }
+    info!("Cache miss - generating thumbnail for {}", params.url);
+
+    if let Some(retry_after) = state.breaker.check(&host) {
+        return Err(AppError::CircuitOpen(host, retry_after));
+    }
+
+    let respect_robots = tenant.and_then(|t| t.respect_robots).unwrap_or(state.config.robots.enabled);
+    if respect_robots && !robots_allows(state, key, &params.url).await {
+        info!("robots.txt disallows {}", params.url);
+        return Err(AppError::Blocked("robots_disallowed", format!("robots.txt disallows {}", params.url)));
+    }
+
+    if !state.generator.is_available() {
+        return browserless_thumbnail(state, key, params, options, tracer).await;
+    }
+
+    let session = match session {
+        // Workers load the profile themselves, so the login never passes through Redis.
+        Some(profile) if matches!(state.generator, Renderer::Queue(_)) => {
+            Some(SessionState { name: profile.name, ..Default::default() })
+        }
+        Some(profile) => Some(session::load(&profile).await.map_err(|e| {
+            error!("Session profile {} can't be used: {}", profile.name, e);
+            AppError::Internal(format!("Session profile {} can't be used", profile.name))
+        })?),
+        None => None,
+    };
+
+    // The probe fetches without the session, so it would only see the login page.
+    let content_hash = if params.conditional && session.is_none() {
+        let insecure = params.ignore_tls_errors || state.config.render.ignore_certificate_errors;
+        let probed = state.probe.content_hash(&params.url, params.auth.as_ref(), insecure).await;
+        audit_fetch(state, requester(key), "probe", &params.url, probed.as_ref().map(|(_, read)| *read));
+        probed.map(|(hash, _)| hash)
+    } else {
+        None
+    };
+    if let (true, false, Some(hash)) = (params.refresh, params.debug, &content_hash) {
+        let unchanged = state.cache.get(namespace, &cache_key).await?.filter(|c| c.content_hash.as_ref() == Some(hash));
+        if let Some(cached) = unchanged {
+            info!("{} is unchanged since it was rendered", params.url);
+            check_redirect_allowed(state, tenant, cached.final_url.as_deref())?;
+            return Ok(Thumbnail::from_cache(cached, true));
+        }
+    }
+
+    let started = Instant::now();
+
+    let request_timeout = state.settings.read().unwrap_or_else(|e| e.into_inner()).request_timeout;
+    let render_config = &state.config.render;
+    let render = async {
+        let _host_permit = state.hosts.acquire(&host).await?;
+        tracer.record("host_wait", started);
+        let host_wait = started.elapsed();
+        let wait = request_timeout.saturating_sub(host_wait);
+        let mut render_options = RenderOptions {
+            credentials: params.auth.clone(),
+            actions: params.actions.clone(),
+            disable_javascript: params.javascript == Some(false),
+            ignore_certificate_errors: params.ignore_tls_errors || state.config.render.ignore_certificate_errors,
+            redirects: RedirectPolicy {
+                max_redirects: params.max_redirects.map_or(render_config.max_redirects, |max| max.min(render_config.max_redirects)),
+                allow_cross_origin: render_config.allow_cross_origin_redirects && params.cross_origin_redirects != Some(false),
+                hosts: host_rules(state, tenant),
+            },
+            diagnostics: params.debug,
+            stabilize: params.stabilize,
+            media: MediaFeatures {
+                reduced_motion: params.prefers_reduced_motion,
+                contrast: params.prefers_contrast,
+                forced_colors: params.forced_colors,
+                color_scheme: None,
+            },
+            consent: render_config.consent,
+            trace: params.trace,
+            session: session.clone(),
+            oversample: options.oversample,
+            ..RenderOptions::default()
+        };
+        if let Some(profile) = site_profile(state, &host) {
+            apply_site_profile(&mut render_options, &profile, params.width, params.height);
+        }
+        if let Some(consent) = params.consent {
+            render_options.consent = consent;
+        }
+        let mut result = state
+            .generator
+            .generate(&params.url, params.width, params.height, &render_options, wait, tracer)
+            .await?;
+        result.queue_time += host_wait;
+        anyhow::Ok(result)
+    };
+
+    let mut result = match tokio::time::timeout(request_timeout, render).await {
+        Ok(Ok(result)) => {
+            state.breaker.record_success(&host);
+            result
+        }
+        Ok(Err(e)) => {
+            error!("Thumbnail generation failed for {}: {}", params.url, e);
+            if let Some(render_error) = e.downcast_ref::<RenderError>() {
+                // A page over budget or failing an action did respond; only hangs count against the host.
+                if matches!(render_error, RenderError::NavigationTimeout(_)) {
+                    state.breaker.record_failure(&host);
+                }
+                return Err(render_failure(render_error));
+            }
+            state.breaker.record_failure(&host);
+            return Err(AppError::ThumbnailGeneration(format!("Failed to generate thumbnail: {}", e)));
+        }
+        Err(_) => {
+            error!("Thumbnail generation timed out for {}", params.url);
+            state.breaker.record_failure(&host);
+            return Err(AppError::Timeout);
+        }
+    };
+
+    let captured_at = cache::now_secs();
+    if let Some(trace) = result.trace.take() {
+        tracer.extend(trace, Instant::now());
+    }
+
+    // Redirects can lead anywhere, so the page that was actually rendered must pass the same checks.
+    check_redirect_allowed(state, tenant, result.final_url.as_deref())?;
+
+    let og_image = match (params.og_image_fallback && result.paywalled, &result.og_image) {
+        (true, Some(og_image)) => fetch_og_image(state, key, og_image, params).await,
+        _ => None,
+    };
+    let source = og_image.unwrap_or(result.image_data);
+
+    let encoding = Instant::now();
+    let processed = match process_in_pool(state, source, params, options, captured_at).await {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Image processing failed for {}: {}", params.url, e);
+            return Err(e);
+        }
+    };
+    tracer.record("encode", encoding);
+    
+    let render_time = started.elapsed().saturating_sub(result.queue_time);
+
+    let cached_data = CachedData {
+        image_data: processed.data,
+        url: params.url.clone(),
+        title: result.title,
+        description: result.description,
+        final_url: result.final_url.filter(|url| *url != params.url),
+        quality: processed.quality,
+        content_hash,
+        paywalled: result.paywalled,
+        language: result.language,
+        excerpt: result.excerpt,
+        provenance: Some(result.provenance),
+        created_at: captured_at,
+    };
+    
+    let writing = Instant::now();
+    if let Err(e) = state.cache.put(namespace, &cache_key, &cached_data).await {
+        error!("Failed to cache result for {}: {}", params.url, e);
+    }
+    tracer.record("cache_write", writing);
+
+    if !state.hooks.is_empty() {
+        let image = bytes::Bytes::from(cached_data.image_data.clone());
+        state.hooks.publish(ThumbnailEvent {
+            url: params.url.clone(),
+            final_url: cached_data.final_url.clone(),
+            tenant: tenant.map(|t| t.name.clone()),
+            namespace: namespace.to_string(),
+            cache_key: cache_key.clone(),
+            format: params.format.as_str().to_string(),
+            content_type: params.format.content_type().to_string(),
+            title: cached_data.title.clone(),
+            description: cached_data.description.clone(),
+            created_at: cached_data.created_at,
+            size: image.len() as u64,
+            image_sha256: format!("{:x}", Sha256::digest(&image)),
+            image,
+            key: requester(key).key.to_string(),
+        });
+    }
+
+    Ok(Thumbnail {
+        image_data: cached_data.image_data,
+        title: cached_data.title,
+        description: cached_data.description,
+        final_url: cached_data.final_url,
+        quality: cached_data.quality,
+        diagnostics: result.diagnostics,
+        provenance: cached_data.provenance,
+        cached: false,
+        stale: false,
+        not_modified: false,
+        paywalled: cached_data.paywalled,
+        language: cached_data.language,
+        excerpt: cached_data.excerpt,
+        placeholder: false,
+        degraded: false,
+        render_time,
+        queue_time: result.queue_time,
+        trace: None,
+    })
+}
+
+/// Makes a thumbnail without a browser, from the URL itself when it's an image or from the
+/// page's `og:image`. The result isn't cached, so the page is rendered properly once a browser
+/// is back.
+async fn browserless_thumbnail(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    params: &ThumbnailRequest,
+    options: ProcessOptions,
+    tracer: &Tracer,
+) -> Result<Thumbnail, AppError> {
+    let unavailable = |why: &str| AppError::BrowserUnavailable(format!("No browser is available and {}", why));
+    if !params.actions.is_empty() || params.debug {
+        return Err(unavailable("actions and debug renders need one"));
+    }
+    if params.session.is_some() {
+        return Err(unavailable("sessions need one"));
+    }
+    let started = Instant::now();
+    let insecure = params.ignore_tls_errors || state.config.render.ignore_certificate_errors;
+    let page = state.probe.fetch_page(&params.url, params.auth.as_ref(), insecure).await;
+    audit_fetch(state, requester(key), "page", &params.url, page.as_ref().map(|page| page.body.len() as u64));
+    let page = page.ok_or_else(|| unavailable("the page couldn't be fetched directly"))?;
+    tracer.record("fetch", started);
+
+    let (source, meta) = if page.content_type.starts_with("image/") {
+        info!("Using {} directly without a browser", params.url);
+        (page.body, PageMeta::default())
+    } else if page.content_type.contains("html") {
+        let base = Url::parse(&params.url).map_err(|e| AppError::BadRequest(format!("Invalid URL {}: {}", params.url, e)))?;
+        let mut meta = browserless::extract_meta(&page.body, &base);
+        let og_image = match meta.og_image.take() {
+            Some(og_image) => fetch_og_image(state, key, &og_image, params).await,
+            None => None,
+        };
+        (og_image.ok_or_else(|| unavailable("the page has no usable og:image"))?, meta)
+    } else {
+        return Err(unavailable(&format!("{} can't be shown without one", page.content_type)));
+    };
+
+    let encoding = Instant::now();
+    let processed = process_in_pool(state, source, params, options, cache::now_secs()).await?;
+    tracer.record("encode", encoding);
+    Ok(Thumbnail {
+        image_data: processed.data,
+        title: meta.title,
+        description: meta.description,
+        final_url: None,
+        quality: processed.quality,
+        diagnostics: None,
+        provenance: None,
+        cached: false,
+        stale: false,
+        not_modified: false,
+        paywalled: false,
+        language: None,
+        excerpt: None,
+        placeholder: false,
+        degraded: true,
+        render_time: started.elapsed(),
+        queue_time: Duration::ZERO,
+        trace: None,
+    })
+}
+
+async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+        chrome_available: state.generator.is_healthy().await,
+        cache_size_bytes: state.cache.size_on_disk(),
+    })
+}
+
+/// Reports ready once the startup self-test has passed, for orchestrators to hold traffic until
+/// the browser has shown it can actually render.
+async fn handle_readyz(State(state): State<Arc<AppState>>) -> Response {
+    let self_test = state.self_test.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let status = if self_test.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(self_test)).into_response()
+}
+
+/// Renders the built-in test page, checks the capture shows its text, encodes it, and round-trips
+/// it through the cache, retrying with backoff and recording the first stage to fail.
+async fn run_self_test(state: Arc<AppState>) {
+    let started = Instant::now();
+    let mut backoff = self_test::RETRY_BACKOFF;
+    let mut attempt = 1;
+    let outcome = loop {
+        let result = self_test_stages(&state).await;
+        // The entry only proves the cache round-trips, so it isn't left behind.
+        if let Err(e) = state.cache.remove(self_test::NAMESPACE, "page").await {
+            warn!("Failed to remove the self-test cache entry: {}", e);
+        }
+        match result {
+            Ok(()) => {
+                info!("Self-test passed in {:?}", started.elapsed());
+                break SelfTest::Passed { duration_ms: started.elapsed().as_millis() as u64 };
+            }
+            Err((stage, error)) if attempt < self_test::ATTEMPTS => {
+                warn!("Self-test attempt {} failed at {}: {}; retrying in {:?}", attempt, stage, error, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err((stage, error)) => {
+                error!("Self-test failed at {}: {}", stage, error);
+                break SelfTest::Failed { stage, error };
+            }
+        }
+    };
+    *state.self_test.write().unwrap_or_else(|e| e.into_inner()) = outcome;
+}
+
+async fn self_test_stages(state: &AppState) -> Result<(), (&'static str, String)> {
+    let url = self_test::page_url();
+    let (width, height) = (self_test::WIDTH, self_test::HEIGHT);
+    let options = RenderOptions {
+        consent: ConsentMode::Off,
+        ..RenderOptions::default()
+    };
+    let request_timeout = state.settings.read().unwrap_or_else(|e| e.into_inner()).request_timeout;
+    let tracer = Tracer::disabled();
+    let render = state.generator.generate(&url, width, height, &options, request_timeout, &tracer);
+    let result = match tokio::time::timeout(request_timeout, render).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => return Err(("render", e.to_string())),
+        Err(_) => return Err(("render", "Timed out".to_string())),
+    };
+
+    let screenshot = result.image_data;
+    let checked = screenshot.clone();
+    state
+        .images
+        .run(width as u64 * height as u64, move || self_test::check_capture(&checked).map_err(AppError::ImageProcessing))
+        .await
+        .map_err(|e| ("capture", e.parts().2))?;
+
+    let format = ImageFormat::Webp;
+    let encoded = state
+        .images
+        .run(width as u64 * height as u64 * 2, move || {
+            process_image(&screenshot, width, height, &format, &ProcessOptions::default())
+        })
+        .await
+        .map_err(|e| ("encode", e.parts().2))?;
+
+    let entry = CachedData {
+        image_data: encoded.data,
+        url: "self-test".to_string(),
+        title: result.title,
+        description: None,
+        final_url: None,
+        quality: encoded.quality,
+        content_hash: None,
+        paywalled: false,
+        language: None,
+        excerpt: None,
+        provenance: Some(result.provenance),
+        created_at: cache::now_secs(),
+    };
+    let cache_error = |e: anyhow::Error| ("cache", e.to_string());
+    state.cache.put(self_test::NAMESPACE, "page", &entry).await.map_err(cache_error)?;
+    let read = state.cache.get(self_test::NAMESPACE, "page").await.map_err(cache_error)?;
+    if read.map(|r| r.image_data) != Some(entry.image_data) {
+        return Err(("cache", "Cached entry didn't read back as written".to_string()));
+    }
+    Ok(())
+}
+
+/// Publishes the public half of the signing key so consumers can verify responses.
+async fn handle_signing_key(State(state): State<Arc<AppState>>) -> Result<Json<SigningKeyResponse>, AppError> {
+    let signer = state.signer.as_ref().ok_or_else(|| AppError::NotFound("Response signing is not configured".to_string()))?;
+    Ok(Json(SigningKeyResponse {
+        key_id: signer.key_id().to_string(),
+        algorithm: "Ed25519",
+        public_key: general_purpose::STANDARD.encode(signer.public_key()),
+    }))
+}
+
+/// Per-tenant usage counters since startup.
+async fn handle_tenant_usage(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+) -> Result<Json<Vec<TenantUsageResponse>>, AppError> {
+    let tenants = state
+        .auth()
+        .tenants()
+        .iter()
+        .map(|tenant| TenantUsageResponse {
+            name: tenant.name.clone(),
+            namespace: tenant.namespace.clone(),
+            usage: tenant.usage.snapshot(),
+        })
+        .collect();
+    Ok(Json(tenants))
+}
+
+/// Render concurrency and the navigation budget are `null` when renders run on queue workers,
+/// which have their own configuration.
+#[derive(Debug, Serialize)]
+pub struct SettingsResponse {
+    pub max_concurrent_renders: Option<usize>,
+    pub request_timeout_secs: u64,
+    pub navigation_budget_secs: Option<u64>,
+    pub allowed_domains: Vec<String>,
+    pub blocked_domains: Vec<String>,
+    pub log_filter: String,
+}
+
+/// Fields left out are unchanged.
+#[derive(Debug, Deserialize)]
+pub struct SettingsUpdate {
+    max_concurrent_renders: Option<usize>,
+    request_timeout_secs: Option<u64>,
+    navigation_budget_secs: Option<u64>,
+    allowed_domains: Option<Vec<String>>,
+    blocked_domains: Option<Vec<String>>,
+    /// `tracing` filter directives, e.g. `thumbnail_service=debug`.
+    log_filter: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AllowlistEntry {
+    domain: String,
+}
+
+fn current_settings(state: &AppState) -> SettingsResponse {
+    let settings = state.settings.read().unwrap_or_else(|e| e.into_inner());
+    SettingsResponse {
+        max_concurrent_renders: state.generator.local().map(|g| g.concurrency()),
+        request_timeout_secs: settings.request_timeout.as_secs(),
+        navigation_budget_secs: state.generator.local().map(|g| g.budget().navigation.as_secs()),
+        allowed_domains: settings.allowed_domains.clone(),
+        blocked_domains: settings.blocked_domains.clone(),
+        log_filter: state.log.with_current(|filter| filter.to_string()).unwrap_or_default(),
+    }
+}
+
+async fn handle_get_settings(State(state): State<Arc<AppState>>, _: Admin) -> Json<SettingsResponse> {
+    Json(current_settings(&state))
+}
+
+/// Applies runtime setting changes. They last until the process restarts, or for the domain
+/// lists, until the config file is reloaded.
+async fn handle_update_settings(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Json(update): Json<SettingsUpdate>,
+) -> Result<Json<SettingsResponse>, AppError> {
+    info!("Updating runtime settings: {:?}", update);
+    if update.max_concurrent_renders.is_some_and(|n| n == 0) {
+        return Err(AppError::BadRequest("max_concurrent_renders must be positive".to_string()));
+    }
+    if update.request_timeout_secs.is_some_and(|t| t == 0) || update.navigation_budget_secs.is_some_and(|t| t == 0) {
+        return Err(AppError::BadRequest("timeouts must be positive".to_string()));
+    }
+    let local = state.generator.local();
+    if local.is_none() && (update.max_concurrent_renders.is_some() || update.navigation_budget_secs.is_some()) {
+        return Err(AppError::BadRequest("Render concurrency and budgets are configured on the queue workers".to_string()));
+    }
+    let log_filter = update
+        .log_filter
+        .as_deref()
+        .map(EnvFilter::try_new)
+        .transpose()
+        .map_err(|e| AppError::BadRequest(format!("Invalid log_filter: {}", e)))?;
+
+    if let Some(generator) = local {
+        if let Some(limit) = update.max_concurrent_renders {
+            generator.set_concurrency(limit);
+        }
+        if let Some(secs) = update.navigation_budget_secs {
+            generator.set_navigation_budget(Duration::from_secs(secs));
+        }
+    }
+    {
+        let mut settings = state.settings.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(secs) = update.request_timeout_secs {
+            settings.request_timeout = Duration::from_secs(secs);
+        }
+        if let Some(domains) = update.allowed_domains {
+            settings.allowed_domains = lowercase(&domains);
+        }
+        if let Some(domains) = update.blocked_domains {
+            settings.blocked_domains = lowercase(&domains);
+        }
+    }
+    if let Some(filter) = log_filter {
+        state.log.reload(filter).map_err(|e| AppError::Internal(format!("Failed to set log filter: {}", e)))?;
+    }
+
+    Ok(Json(current_settings(&state)))
+}
+
+async fn handle_allowlist_add(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Json(entry): Json<AllowlistEntry>,
+) -> Result<Json<SettingsResponse>, AppError> {
+    let domain = entry.domain.trim().to_ascii_lowercase();
+    if domain.is_empty() {
+        return Err(AppError::BadRequest("domain cannot be empty".to_string()));
+    }
+    {
+        let mut settings = state.settings.write().unwrap_or_else(|e| e.into_inner());
+        if !settings.allowed_domains.contains(&domain) {
+            info!("Adding {} to the allowlist", domain);
+            settings.allowed_domains.push(domain);
+        }
+    }
+    Ok(Json(current_settings(&state)))
+}
+
+async fn handle_allowlist_remove(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Path(domain): Path<String>,
+) -> Json<SettingsResponse> {
+    let domain = domain.to_ascii_lowercase();
+    state.settings.write().unwrap_or_else(|e| e.into_inner()).allowed_domains.retain(|d| *d != domain);
+    info!("Removed {} from the allowlist", domain);
+    Json(current_settings(&state))
+}
+
+fn lowercase(domains: &[String]) -> Vec<String> {
+    domains.iter().map(|d| d.trim().to_ascii_lowercase()).collect()
+}
+
+/// What a config reload applied.
+#[derive(Debug, Serialize)]
+pub struct ReloadResponse {
+    pub tenants: usize,
+    pub api_keys: usize,
+    pub sites: usize,
+    pub sessions: usize,
+    pub allowed_domains: usize,
+    pub blocked_domains: usize,
+}
+
+/// Re-reads the config file and applies its domain lists, site and session profiles, and API
+/// keys. Other settings need a restart. An unreadable or invalid file changes nothing.
+fn reload_config(state: &AppState) -> anyhow::Result<ReloadResponse> {
+    let path = Config::path().ok_or_else(|| anyhow::anyhow!("There is no config file to reload"))?;
+    let config = Config::from_file(&path)?;
+
+    let auth = Arc::new(Auth::reloaded(&config.auth, Some(&state.auth())));
+    let response = ReloadResponse {
+        tenants: auth.tenants().len(),
+        api_keys: auth.key_count(),
+        sites: config.sites.len(),
+        sessions: config.sessions.len(),
+        allowed_domains: config.access.allowed_domains.len(),
+        blocked_domains: config.access.blocked_domains.len(),
+    };
+    {
+        let mut settings = state.settings.write().unwrap_or_else(|e| e.into_inner());
+        settings.allowed_domains = lowercase(&config.access.allowed_domains);
+        settings.blocked_domains = lowercase(&config.access.blocked_domains);
+    }
+    *state.sites.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config.sites);
+    *state.sessions.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config.sessions);
+    *state.auth.write().unwrap_or_else(|e| e.into_inner()) = auth;
+    info!("Reloaded config from {:?}: {:?}", path, response);
+    Ok(response)
+}
+
+async fn handle_reload(State(state): State<Arc<AppState>>, _: Admin) -> Result<Json<ReloadResponse>, AppError> {
+    reload_config(&state)
+        .map(Json)
+        .map_err(|e| AppError::BadRequest(format!("Config not reloaded: {}", e)))
+}
+
+/// Reloads the config when its file's modification time changes or, on Unix, on `SIGHUP`.
+async fn watch_config(state: Arc<AppState>) {
+    let modified = || Config::path().and_then(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok());
+    let watching = state.config.reload.interval_secs > 0;
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config.reload.interval_secs.max(1)));
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            None
+        }
+    };
+
+    let mut last = modified();
+    loop {
+        #[cfg(unix)]
+        let hangup_received = async {
+            match hangup.as_mut() {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_received = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = interval.tick(), if watching => {
+                let current = modified();
+                if current.is_none() || current == last {
+                    continue;
+                }
+                last = current;
+            }
+            _ = hangup_received => info!("Reloading config on SIGHUP"),
+        }
+        if let Err(e) = reload_config(&state) {
+            error!("Failed to reload config: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DebugDomQuery {
+    url: String,
+    #[serde(default = "default_width")]
+    width: u32,
+    #[serde(default = "default_height")]
+    height: u32,
+    javascript: Option<bool>,
+}
+
+/// Loads a page as a thumbnail render would and returns its settled DOM and layout, to see why
+/// a site's thumbnail comes out blank or covered.
+async fn handle_debug_dom(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Query(query): Query<DebugDomQuery>,
+) -> Result<Json<DomSnapshot>, AppError> {
+    info!("GET /debug/dom for {}", query.url);
+    if let Renderer::Unavailable(reason) = &state.generator {
+        return Err(AppError::BrowserUnavailable(format!("DOM inspection needs a browser: {}", reason)));
+    }
+    let Some(generator) = state.generator.local() else {
+        return Err(AppError::BadRequest("DOM inspection needs a local browser and is unavailable in queue mode".to_string()));
+    };
+
+    let host = Url::parse(&query.url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .and_then(|u| u.host_str().map(str::to_string))
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid URL: {}", query.url)))?;
+    check_host_allowed(&state, None, &host)?;
+    let limits = &state.config.limits;
+    if query.width == 0 || query.height == 0 || query.width > limits.max_width || query.height > limits.max_height {
+        return Err(AppError::InvalidParameter("invalid_dimensions", "width and height are outside the configured limits".to_string()));
+    }
+
+    let mut options = RenderOptions {
+        disable_javascript: query.javascript == Some(false),
+        ignore_certificate_errors: state.config.render.ignore_certificate_errors,
+        consent: state.config.render.consent,
+        ..RenderOptions::default()
+    };
+    options.redirects.hosts = host_rules(&state, None);
+    if let Some(profile) = site_profile(&state, &host) {
+        apply_site_profile(&mut options, &profile, query.width, query.height);
+    }
+    let request_timeout = state.settings.read().unwrap_or_else(|e| e.into_inner()).request_timeout;
+    let result = match tokio::time::timeout(request_timeout, generator.inspect(&query.url, query.width, query.height, &options)).await {
+        Ok(Ok(snapshot)) => Ok(snapshot),
+        Ok(Err(e)) => match e.downcast::<RenderError>() {
+            Ok(render_error) => Err(render_failure(&render_error)),
+            Err(e) => Err(AppError::ThumbnailGeneration(format!("Failed to load page: {}", e))),
+        },
+        Err(_) => Err(AppError::Timeout),
+    };
+    let outcome = match &result {
+        Ok(snapshot) => Ok((AuditOutcome::Rendered, snapshot.final_url.clone(), snapshot.html.len() as u64)),
+        Err(e) => Err(e),
+    };
+    audit(&state, Requester::ADMIN, "debug_dom", &query.url, outcome);
+    result.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheEntriesQuery {
+    /// Matched against `namespace:key`.
+    #[serde(default)]
+    prefix: String,
+    /// `next_cursor` from the previous page.
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheEntriesResponse {
+    pub entries: Vec<EntryInfo>,
+    /// Pass as `cursor` to get the next page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Pages through cache entries in key order, for inspecting what occupies the cache.
+async fn handle_cache_entries(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Query(query): Query<CacheEntriesQuery>,
+) -> Result<Json<CacheEntriesResponse>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_CACHE_PAGE);
+    if limit == 0 || limit > MAX_CACHE_PAGE {
+        return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_CACHE_PAGE)));
+    }
+    // Cursors are opaque so clients don't need to escape the keys inside them.
+    let after = query
+        .cursor
+        .map(|cursor| {
+            general_purpose::URL_SAFE_NO_PAD
+                .decode(cursor)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| AppError::BadRequest("Invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let (entries, next) = state.cache.list(&query.prefix, after.as_deref(), limit)?;
+    Ok(Json(CacheEntriesResponse {
+        entries,
+        next_cursor: next.map(|key| general_purpose::URL_SAFE_NO_PAD.encode(key)),
+    }))
+}
+
+/// A thumbnail rendered elsewhere, to be stored as if this service had rendered it.
+#[derive(Debug, Deserialize)]
+pub struct PutCacheRequest {
+    /// The `/thumbnail` parameters the image answers; they pick the cache key.
+    request: ThumbnailRequest,
+    /// Store under this tenant's cache namespace instead of the default one.
+    tenant: Option<String>,
+    /// Base64, in `request.format` and no larger than `request.width` by `request.height`.
+    image_data: String,
+    title: Option<String>,
+    description: Option<String>,
+    final_url: Option<String>,
+    #[serde(default)]
+    paywalled: bool,
+    language: Option<String>,
+    excerpt: Option<String>,
+    /// Unix seconds of the original capture; defaults to now.
+    created_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PutCacheResponse {
+    pub namespace: String,
+    pub key: String,
+    pub size: usize,
+    pub created_at: u64,
+}
+
+/// Seeds the cache with a thumbnail rendered elsewhere, such as by a system being migrated from,
+/// so it's served without rendering. It replaces any entry under the same key.
+async fn handle_put_cache(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Json(body): Json<PutCacheRequest>,
+) -> Result<Json<PutCacheResponse>, AppError> {
+    let mut params = body.request;
+    normalize_url(&state, &mut params);
+    validate_request(&state, &params)?;
+    if params.version.is_some() {
+        return Err(AppError::BadRequest("version can't be set when seeding the cache".to_string()));
+    }
+    let namespace = match &body.tenant {
+        Some(name) => {
+            let auth = state.auth();
+            let tenant = auth.tenants().iter().find(|t| t.name == *name).cloned();
+            tenant.ok_or_else(|| AppError::BadRequest(format!("Unknown tenant: {}", name)))?.namespace.clone()
+        }
+        None => state.config.cache.namespace.clone(),
+    };
+
+    let image = general_purpose::STANDARD
+        .decode(body.image_data.trim())
+        .map_err(|e| AppError::BadRequest(format!("image_data isn't valid base64: {}", e)))?;
+    let (format, decoder) = match image::guess_format(&image) {
+        Ok(decoder @ image::ImageFormat::WebP) => (ImageFormat::Webp, decoder),
+        Ok(decoder @ image::ImageFormat::Jpeg) => (ImageFormat::Jpeg, decoder),
+        Ok(decoder @ image::ImageFormat::Png) => (ImageFormat::Png, decoder),
+        _ => return Err(AppError::BadRequest("image_data isn't a WebP, JPEG, or PNG image".to_string())),
+    };
+    if format != params.format {
+        return Err(AppError::BadRequest(format!(
+            "image_data is {} but the request asks for {}",
+            format.as_str(),
+            params.format.as_str()
+        )));
+    }
+    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&image), decoder)
+        .into_dimensions()
+        .map_err(|e| AppError::BadRequest(format!("image_data can't be read: {}", e)))?;
+    if width > params.width || height > params.height {
+        return Err(AppError::BadRequest(format!(
+            "image_data is {}x{}, larger than the requested {}x{}",
+            width, height, params.width, params.height
+        )));
+    }
+
+    // Stripped of whatever it came with, and given the metadata a render would get.
+    let created_at = body.created_at.unwrap_or_else(cache::now_secs);
+    let image = metadata::rewrite(&image, format, &embedded_metadata(&state, &params.url, created_at))
+        .map_err(|e| AppError::BadRequest(format!("image_data can't be read: {}", e)))?;
+    let key = build_cache_key(&state, &build_process_options(&state, &params), &params);
+    let data = CachedData {
+        image_data: image,
+        final_url: body.final_url.filter(|url| *url != params.url),
+        url: params.url,
+        title: body.title,
+        description: body.description,
+        quality: None,
+        content_hash: None,
+        paywalled: body.paywalled,
+        language: body.language,
+        excerpt: body.excerpt,
+        provenance: None,
+        created_at,
+    };
+    state.cache.put(&namespace, &key, &data).await?;
+    info!("Seeded cache entry {}:{} ({} bytes)", namespace, key, data.image_data.len());
+    Ok(Json(PutCacheResponse {
+        namespace,
+        key,
+        size: data.image_data.len(),
+        created_at: data.created_at,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    /// Unix seconds, `YYYY-MM-DD` or RFC 3339; defaults to the beginning of time.
+    from: Option<String>,
+    /// Exclusive end, in the same formats; defaults to now.
+    to: Option<String>,
+    tenant: Option<String>,
+    /// `next_cursor` from the previous page.
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `cursor` to get the next page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Pages through the audit log oldest first.
+async fn handle_audit(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditResponse>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_PAGE);
+    if limit == 0 || limit > MAX_AUDIT_PAGE {
+        return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_AUDIT_PAGE)));
+    }
+    let parse = |value: &Option<String>, name: &str, default: u64| match value {
+        Some(value) => usage::parse_time(value).ok_or_else(|| AppError::BadRequest(format!("Invalid {}: {}", name, value))),
+        None => Ok(default),
+    };
+    let from = parse(&query.from, "from", 0)?;
+    let to = parse(&query.to, "to", cache::now_secs() + 1)?;
+    let after = query
+        .cursor
+        .map(|cursor| general_purpose::URL_SAFE_NO_PAD.decode(cursor).map_err(|_| AppError::BadRequest("Invalid cursor".to_string())))
+        .transpose()?;
+
+    let (entries, next) = state.audit.query(from, to, query.tenant.as_deref(), after.as_deref(), limit)?;
+    Ok(Json(AuditResponse {
+        entries,
+        next_cursor: next.map(|key| general_purpose::URL_SAFE_NO_PAD.encode(key)),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// Unix seconds, `YYYY-MM-DD` or RFC 3339; defaults to the beginning of time.
+    from: Option<String>,
+    /// Exclusive end, in the same formats; defaults to now.
+    to: Option<String>,
+    /// `json` (default) or `csv`.
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    pub from: u64,
+    pub to: u64,
+    pub usage: Vec<usage::UsageRow>,
+}
+
+/// Aggregated usage per tenant and key. The admin key sees every tenant; an API key sees its own.
+async fn handle_usage(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let auth = state.auth();
+    let tenant = if auth.is_admin(&headers) {
+        None
+    } else {
+        match auth.authenticate(&headers) {
+            Ok(Some(key)) => Some(key.tenant),
+            _ => return Err(AppError::Unauthorized("An API key or the admin key is required".to_string())),
+        }
+    };
+
+    let parse = |value: &Option<String>, name: &str, default: u64| match value {
+        Some(value) => usage::parse_time(value).ok_or_else(|| AppError::BadRequest(format!("Invalid {}: {}", name, value))),
+        None => Ok(default),
+    };
+    let from = parse(&query.from, "from", 0)?;
+    let to = parse(&query.to, "to", cache::now_secs() + 1)?;
+
+    let rows = state.usage.query(from, to, tenant.as_ref().map(|t| t.name.as_str()))?;
+    match query.format.as_deref() {
+        None | Some("json") => Ok(Json(UsageResponse { from, to, usage: rows }).into_response()),
+        Some("csv") => Ok((
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"usage.csv\""),
+            ],
+            usage::to_csv(&rows),
+        ).into_response()),
+        Some(other) => Err(AppError::BadRequest(format!("Unsupported usage format: {}", other))),
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    Timeout,
+    BadRequest(String),
+    /// Missing or unknown API key.
+    Unauthorized(String),
+    NotFound(String),
+    /// The caller's tenant has used up its request rate.
+    RateLimited(String),
+    /// A parameter outside configured limits, with a stable machine-readable code.
+    InvalidParameter(&'static str, String),
+    /// Policy forbids fetching the target, e.g. robots.txt or a domain list.
+    Blocked(&'static str, String),
+    /// The target page couldn't be rendered as asked, e.g. it exceeded a budget, an action failed,
+    /// or the image can't be made to fit `max_bytes`.
+    Render(&'static str, String),
+    /// The host failed repeatedly and is not being tried until the cooldown passes.
+    CircuitOpen(String, Duration),
+    /// The request needs a browser and none is running.
+    BrowserUnavailable(String),
+    ThumbnailGeneration(String),
+    ImageProcessing(String),
+    Internal(String),
+    /// Another error, with the stages a `trace` request got through before it.
+    Traced(Box<AppError>, Trace),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Timeout => write!(f, "Timeout"),
+            AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
+            AppError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            AppError::RateLimited(msg) => write!(f, "Rate limited: {}", msg),
+            AppError::InvalidParameter(code, msg) => write!(f, "Invalid parameter ({}): {}", code, msg),
+            AppError::Blocked(code, msg) => write!(f, "Blocked ({}): {}", code, msg),
+            AppError::Render(code, msg) => write!(f, "Render failed ({}): {}", code, msg),
+            AppError::CircuitOpen(host, retry_after) => write!(f, "Circuit open for {} ({:?} remaining)", host, retry_after),
+            AppError::BrowserUnavailable(msg) => write!(f, "Browser unavailable: {}", msg),
+            AppError::ThumbnailGeneration(msg) => write!(f, "Thumbnail generation failed: {}", msg),
+            AppError::ImageProcessing(msg) => write!(f, "Image processing failed: {}", msg),
+            AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
+            AppError::Traced(e, _) => e.fmt(f),
+        }
+    }
 }
 
 impl std::error::Error for AppError {}
 
+impl AppError {
+    /// The error itself, without any trace attached to it.
+    fn untraced(&self) -> &AppError {
+        match self {
+            AppError::Traced(e, _) => e.untraced(),
+            e => e,
+        }
+    }
+
+    /// Whether the page itself couldn't be rendered, as opposed to the request being refused.
+    fn is_render_failure(&self) -> bool {
+        matches!(
+            self.untraced(),
+            AppError::Timeout
+                | AppError::Render(..)
+                | AppError::CircuitOpen(..)
+                | AppError::BrowserUnavailable(_)
+                | AppError::ThumbnailGeneration(_)
+                | AppError::ImageProcessing(_)
+                | AppError::Internal(_)
+        )
+    }
+
+    /// The HTTP status, stable error code, and client-facing message.
+    fn parts(&self) -> (StatusCode, &'static str, String) {
+        match self {
+            AppError::Timeout => (StatusCode::REQUEST_TIMEOUT, "timeout", "Thumbnail generation timed out".to_string()),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, "bad_request", msg.clone()),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, "unauthorized", msg.clone()),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, "not_found", msg.clone()),
+            AppError::RateLimited(msg) => (StatusCode::TOO_MANY_REQUESTS, "rate_limited", msg.clone()),
+            AppError::InvalidParameter(code, msg) => (StatusCode::BAD_REQUEST, *code, msg.clone()),
+            AppError::Blocked(code, msg) => (StatusCode::FORBIDDEN, *code, msg.clone()),
+            AppError::Render(code, msg) => (StatusCode::UNPROCESSABLE_ENTITY, *code, msg.clone()),
+            AppError::CircuitOpen(host, _) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "circuit_open",
+                format!("{} is failing repeatedly and is temporarily not being rendered", host),
+            ),
+            AppError::BrowserUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, "browser_unavailable", msg.clone()),
+            AppError::ThumbnailGeneration(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "generation_failed", msg.clone()),
+            AppError::ImageProcessing(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "processing_failed", msg.clone()),
+            AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "internal", msg.clone()),
+            AppError::Traced(e, _) => e.parts(),
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let (status, message) = match &self {
-            AppError::Timeout => (StatusCode::REQUEST_TIMEOUT, "Thumbnail generation timed out".to_string()),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
-            AppError::ThumbnailGeneration(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-            AppError::ImageProcessing(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-            AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-        };
+        let (status, code, message) = self.parts();
 
         error!("Error response: {} - {}", status, message);
-        (status, Json(serde_json::json!({ "error": message }))).into_response()
+        let mut body = serde_json::json!({ "error": message, "code": code });
+        if let AppError::Traced(_, trace) = &self {
+            body["trace"] = serde_json::to_value(trace).unwrap_or_default();
+        }
+        let mut response = (status, Json(body)).into_response();
+        if let AppError::CircuitOpen(_, retry_after) = self.untraced() {
+            // Round up so clients never retry before the circuit closes.
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+        response
     }
 }
 
@@ -0,0 +1,133 @@
+use chromiumoxide::cdp::browser_protocol::network::{CookieParam, CookieSameSite, SetCookiesParams, TimeSinceEpoch};
+use chromiumoxide::cdp::browser_protocol::page::AddScriptToEvaluateOnNewDocumentParams;
+use chromiumoxide::Page;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+use crate::config::SessionConfig;
+
+/// A logged-in browser state, in the shape of a Playwright `storageState` file so one captured
+/// from a login flow can be used as is.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionState {
+    /// The profile's name, for logs.
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub cookies: Vec<SessionCookie>,
+    /// `localStorage` entries per origin.
+    #[serde(default)]
+    pub origins: Vec<OriginStorage>,
+}
+
+impl std::fmt::Debug for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionState")
+            .field("name", &self.name)
+            .field("cookies", &self.cookies.len())
+            .field("origins", &self.origins.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionCookie {
+    pub name: String,
+    pub value: String,
+    /// A leading dot also matches subdomains.
+    pub domain: String,
+    #[serde(default = "default_cookie_path")]
+    pub path: String,
+    /// Unix seconds; absent or `-1` for a session cookie.
+    pub expires: Option<f64>,
+    #[serde(default, alias = "http_only")]
+    pub http_only: bool,
+    #[serde(default)]
+    pub secure: bool,
+    /// `Strict`, `Lax`, or `None`.
+    #[serde(alias = "same_site")]
+    pub same_site: Option<String>,
+}
+
+fn default_cookie_path() -> String {
+    "/".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OriginStorage {
+    /// E.g. `https://dashboards.example.com`.
+    pub origin: String,
+    #[serde(default)]
+    pub local_storage: Vec<StorageItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageItem {
+    pub name: String,
+    pub value: String,
+}
+
+/// Reads a profile's storage state file, if it has one, and adds its inline cookies. The file
+/// is read on every use, so a re-captured login takes effect without a reload.
+pub async fn load(config: &SessionConfig) -> anyhow::Result<SessionState> {
+    let mut state = match &config.storage_state {
+        Some(path) => {
+            let contents = tokio::fs::read(path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read storage state {:?}: {}", path, e))?;
+            serde_json::from_slice(&contents).map_err(|e| anyhow::anyhow!("Invalid storage state {:?}: {}", path, e))?
+        }
+        None => SessionState::default(),
+    };
+    state.name = config.name.clone();
+    state.cookies.extend(config.cookies.iter().cloned());
+    Ok(state)
+}
+
+/// Puts the session's cookies and `localStorage` into the page's browser context before it
+/// navigates. The page must be in a context of its own, since cookies are shared across one.
+pub async fn apply(page: &Page, state: &SessionState) -> anyhow::Result<()> {
+    if !state.cookies.is_empty() {
+        let cookies = state.cookies.iter().map(cookie_param).collect();
+        timeout(Duration::from_secs(5), page.execute(SetCookiesParams::new(cookies)))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout setting session cookies"))??;
+    }
+    if !state.origins.is_empty() {
+        // Storage can only be written from a document of the origin, so each document writes
+        // its own origin's entries before the page's scripts run.
+        let entries: Vec<(&str, Vec<(&str, &str)>)> = state
+            .origins
+            .iter()
+            .map(|o| (o.origin.as_str(), o.local_storage.iter().map(|i| (i.name.as_str(), i.value.as_str())).collect()))
+            .collect();
+        let script = format!(
+            "(() => {{ const origins = new Map({}); const items = origins.get(location.origin); \
+             if (!items) return; try {{ for (const [name, value] of items) localStorage.setItem(name, value); }} catch (e) {{}} }})();",
+            serde_json::to_string(&entries)?
+        );
+        timeout(Duration::from_secs(5), page.execute(AddScriptToEvaluateOnNewDocumentParams::new(script)))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout setting session storage"))??;
+    }
+    Ok(())
+}
+
+fn cookie_param(cookie: &SessionCookie) -> CookieParam {
+    let mut param = CookieParam::new(cookie.name.clone(), cookie.value.clone());
+    param.domain = Some(cookie.domain.clone());
+    param.path = Some(cookie.path.clone());
+    param.secure = Some(cookie.secure);
+    param.http_only = Some(cookie.http_only);
+    param.expires = cookie.expires.filter(|expires| *expires >= 0.0).map(TimeSinceEpoch::new);
+    param.same_site = match cookie.same_site.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        Some("strict") => Some(CookieSameSite::Strict),
+        Some("lax") => Some(CookieSameSite::Lax),
+        Some("none") => Some(CookieSameSite::None),
+        _ => None,
+    };
+    param
+}
@@ -0,0 +1,68 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::config::SigningConfig;
+
+/// The signed message: this struct serialized as compact JSON, fields in this order.
+#[derive(Debug, Serialize)]
+pub struct SignedPayload<'a> {
+    pub url: &'a str,
+    pub content_type: &'a str,
+    /// Lowercase hex SHA-256 of the image bytes.
+    pub image_sha256: String,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+}
+
+/// Signs thumbnails with an Ed25519 key so consumers can verify them after they pass through
+/// intermediate caches.
+pub struct Signer {
+    key_pair: Ed25519KeyPair,
+    key_id: String,
+}
+
+impl Signer {
+    /// Loads a PKCS#8 PEM key, as produced by `openssl genpkey -algorithm ed25519`.
+    pub fn load(config: &SigningConfig) -> anyhow::Result<Self> {
+        let pem = std::fs::read_to_string(&config.key_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read signing key {:?}: {}", config.key_path, e))?;
+        let der = decode_pem(&pem, "PRIVATE KEY")
+            .ok_or_else(|| anyhow::anyhow!("{:?} is not a PEM private key", config.key_path))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|e| anyhow::anyhow!("Invalid Ed25519 key {:?}: {}", config.key_path, e))?;
+
+        // Without a configured ID, a fingerprint of the public key identifies it.
+        let key_id = config
+            .key_id
+            .clone()
+            .unwrap_or_else(|| format!("{:x}", Sha256::digest(key_pair.public_key().as_ref()))[..16].to_string());
+        info!("Signing responses with Ed25519 key {}", key_id);
+
+        Ok(Self { key_pair, key_id })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    /// Returns the base64 signature over the payload's JSON encoding.
+    pub fn sign(&self, payload: &SignedPayload) -> anyhow::Result<String> {
+        let message = serde_json::to_vec(payload)?;
+        Ok(general_purpose::STANDARD.encode(self.key_pair.sign(&message)))
+    }
+}
+
+fn decode_pem(pem: &str, label: &str) -> Option<Vec<u8>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let body = pem.split(&begin).nth(1)?.split(&end).next()?;
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    general_purpose::STANDARD.decode(body).ok()
+}
@@ -1,79 +1,629 @@
-use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::browser::{Browser, BrowserConfig, HeadlessMode};
+use chromiumoxide::auth::Credentials;
+use chromiumoxide::handler::Handler;
 use chromiumoxide::page::ScreenshotParams;
-use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
-use chromiumoxide::cdp::browser_protocol::emulation::SetDeviceMetricsOverrideParams;
-use chromiumoxide::cdp::browser_protocol::network::SetUserAgentOverrideParams;
+use chromiumoxide::cdp::browser_protocol::page::{CaptureScreenshotFormat, Viewport as ClipRect};
+use chromiumoxide::cdp::browser_protocol::security::SetIgnoreCertificateErrorsParams;
+use chromiumoxide::cdp::browser_protocol::emulation::{
+    MediaFeature, SetDeviceMetricsOverrideParams, SetEmulatedMediaParams, SetScriptExecutionDisabledParams,
+};
+use chromiumoxide::cdp::browser_protocol::network::{
+    EventDataReceived, EventRequestWillBeSent, ResourceType, SetUserAgentOverrideParams,
+};
+use chromiumoxide::Page;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::{Mutex, Semaphore};
-use tokio::time::{timeout, Duration};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Semaphore, SemaphorePermit};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration, Instant};
 use tracing::{info, warn, error};
+use url::Url;
+
+use crate::auth::domain_matches;
+use crate::browser_download;
+use crate::cache::now_secs;
+use crate::config::{self, RenderConfig};
+use crate::consent::{self, ConsentMode};
+use crate::diagnostics::{Diagnostics, DiagnosticsCollector};
+use crate::language;
+use crate::lockdown::{self, CpuMeter};
+use crate::page_pool::{PagePool, PooledPage};
+use crate::paywall;
+use crate::session::{self, SessionState};
+use crate::trace::{Trace, Tracer};
 
 pub struct ThumbnailResult {
     pub image_data: Vec<u8>,
     pub title: Option<String>,
     pub description: Option<String>,
+    /// Where the page ended up after redirects.
+    pub final_url: Option<String>,
+    /// The page's `og:image`, resolved against its URL.
+    pub og_image: Option<String>,
+    /// The page showed a paywall or login wall, or marked its content as paid.
+    pub paywalled: bool,
+    /// The page's primary language as an ISO 639-1 code.
+    pub language: Option<String>,
+    /// The start of the page's main content as plain text.
+    pub excerpt: Option<String>,
+    /// Console errors and failed requests, when asked for.
+    pub diagnostics: Option<Diagnostics>,
+    pub provenance: Provenance,
+    /// Time spent waiting for a render slot and the browser, summed over attempts.
+    pub queue_time: Duration,
+    /// Stage timings of every attempt from a queue worker, when asked for.
+    pub trace: Option<Trace>,
+}
+
+/// Per-request settings for how the target page is loaded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RenderOptions {
+    /// Answered to HTTP basic-auth challenges during the render.
+    pub credentials: Option<BasicAuth>,
+    /// Steps performed on the loaded page before the screenshot.
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// Renders the server-side HTML only, without running the page's scripts.
+    #[serde(default)]
+    pub disable_javascript: bool,
+    /// Loads pages whose TLS certificate doesn't validate instead of showing Chrome's error page.
+    #[serde(default)]
+    pub ignore_certificate_errors: bool,
+    #[serde(default)]
+    pub redirects: RedirectPolicy,
+    /// Collect console errors and failed requests during the render.
+    #[serde(default)]
+    pub diagnostics: bool,
+    /// Settle animations, videos, and timers before capture so repeated renders match.
+    #[serde(default)]
+    pub stabilize: bool,
+    #[serde(default)]
+    pub media: MediaFeatures,
+    /// How to tell that the page has settled after navigation.
+    #[serde(default)]
+    pub wait: WaitStrategy,
+    /// Wait for an element matching this selector before capture.
+    pub wait_for_selector: Option<String>,
+    /// Extra time to wait once the page has settled.
+    #[serde(default)]
+    pub extra_delay_ms: u64,
+    /// Elements hidden before capture.
+    #[serde(default)]
+    pub hide_selectors: Vec<String>,
+    /// Viewport to render at instead of the output size; the capture is scaled afterwards.
+    pub viewport: Option<(u32, u32)>,
+    #[serde(default)]
+    pub consent: ConsentMode,
+    /// Time each stage of the render; local renders record into the caller's tracer instead.
+    #[serde(default)]
+    pub trace: bool,
+    /// Logged-in state applied before navigation; the page gets a browser context of its own.
+    /// Never serialized, so queued jobs carry only the profile's name.
+    #[serde(skip)]
+    pub session: Option<SessionState>,
+    /// Device pixels per CSS pixel to capture at, leaving the downscaling to processing.
+    #[serde(default)]
+    pub oversample: Option<f32>,
+}
+
+/// How a render decides the page has finished loading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WaitStrategy {
+    /// A fixed pause after the load event.
+    #[default]
+    Load,
+    /// Until no resource has finished loading for half a second, for pages that fetch content
+    /// after the load event.
+    NetworkIdle,
+}
+
+/// CSS media features emulated for the page, e.g. to preview accessibility variants.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MediaFeatures {
+    pub reduced_motion: Option<ReducedMotion>,
+    pub contrast: Option<Contrast>,
+    pub forced_colors: Option<ForcedColors>,
+    pub color_scheme: Option<ColorScheme>,
+}
+
+impl MediaFeatures {
+    fn to_cdp(&self) -> Vec<MediaFeature> {
+        [
+            ("prefers-reduced-motion", self.reduced_motion.map(ReducedMotion::as_str)),
+            ("prefers-contrast", self.contrast.map(Contrast::as_str)),
+            ("forced-colors", self.forced_colors.map(ForcedColors::as_str)),
+            ("prefers-color-scheme", self.color_scheme.map(ColorScheme::as_str)),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| MediaFeature::new(name, value)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReducedMotion {
+    Reduce,
+    NoPreference,
+}
+
+impl ReducedMotion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReducedMotion::Reduce => "reduce",
+            ReducedMotion::NoPreference => "no-preference",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Contrast {
+    More,
+    Less,
+    Custom,
+    NoPreference,
+}
+
+impl Contrast {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Contrast::More => "more",
+            Contrast::Less => "less",
+            Contrast::Custom => "custom",
+            Contrast::NoPreference => "no-preference",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForcedColors {
+    Active,
+    None,
+}
+
+impl ForcedColors {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ForcedColors::Active => "active",
+            ForcedColors::None => "none",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
+/// Limits on where a page may send the browser after the initial navigation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectPolicy {
+    /// Main-frame navigations after the first, whether by HTTP redirect, meta refresh, or script.
+    pub max_redirects: u32,
+    /// Whether those navigations may leave the requested origin. Upgrading to HTTPS on the same
+    /// host is always allowed.
+    pub allow_cross_origin: bool,
+    /// Domain rules every main-frame navigation must pass, so a redirect can't reach a host the
+    /// request couldn't.
+    #[serde(default)]
+    pub hosts: HostRules,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            allow_cross_origin: true,
+            hosts: HostRules::default(),
+        }
+    }
+}
+
+/// The global domain lists and the tenant's allowed domains, as patterns for `domain_matches`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostRules {
+    /// Empty allows any host.
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+    /// Empty allows any host.
+    pub tenant_allowed: Vec<String>,
+}
+
+impl HostRules {
+    pub fn allows(&self, host: &str) -> bool {
+        let listed = |patterns: &[String]| patterns.iter().any(|pattern| domain_matches(pattern, host));
+        (self.allowed.is_empty() || listed(&self.allowed))
+            && !listed(&self.blocked)
+            && (self.tenant_allowed.is_empty() || listed(&self.tenant_allowed))
+    }
+}
+
+/// An interaction with the target page, e.g. dismissing a dialog or filling in a search box.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Clicks the first element matching a CSS selector.
+    Click(String),
+    /// Clicks an element to focus it and types the text into it.
+    Type { selector: String, text: String },
+    /// Pauses for the given number of milliseconds.
+    Wait(u64),
+}
+
+/// Typed text may be a password or search query, so it's left out of logs.
+impl std::fmt::Debug for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Click(selector) => f.debug_tuple("Click").field(selector).finish(),
+            Action::Type { selector, .. } => {
+                f.debug_struct("Type").field("selector", selector).field("text", &"<redacted>").finish()
+            }
+            Action::Wait(ms) => f.debug_tuple("Wait").field(ms).finish(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// The settled state of a page, for diagnosing blank or obscured thumbnails.
+#[derive(Debug, Serialize)]
+pub struct DomSnapshot {
+    pub final_url: Option<String>,
+    pub title: Option<String>,
+    pub viewport: Viewport,
+    /// The serialized document after settling and any actions.
+    pub html: String,
+}
+
+/// Layout metrics of the page, in CSS pixels.
+#[derive(Debug, Serialize)]
+pub struct Viewport {
+    pub width: i64,
+    pub height: i64,
+    pub scroll_x: i64,
+    pub scroll_y: i64,
+    /// Size of the whole document, which may extend past the viewport.
+    pub content_width: f64,
+    pub content_height: f64,
+    pub scale: f64,
+}
+
+/// What's known about a full-page capture before its first strip.
+#[derive(Debug)]
+pub struct FullPageInfo {
+    pub width: u32,
+    /// The page's content height, capped at the requested maximum.
+    pub height: u32,
+    pub final_url: Option<String>,
+}
+
+/// The render slot limit, and how many in-use slots are to be removed as they're released
+/// after the limit was lowered.
+struct Concurrency {
+    limit: usize,
+    owed: usize,
+}
+
+/// A render slot and the page checked out with it. Dropping it hands the page back in the
+/// background, so a render abandoned mid-way, e.g. by a client going away, doesn't leak it.
+struct Checkout<'a> {
+    /// Only taken out on drop.
+    permit: Option<SemaphorePermit<'a>>,
+    concurrency: &'a std::sync::Mutex<Concurrency>,
+    /// A handle to `pooled`'s page, which is only taken out on drop.
+    page: Page,
+    pooled: Option<PooledPage>,
+    pages: Arc<PagePool>,
+    visited: String,
+    reusable: bool,
+    /// Only pages whose render finished cleanly are reused; others may be mid-navigation.
+    succeeded: bool,
+}
+
+impl Checkout<'_> {
+    fn page(&self) -> &Page {
+        &self.page
+    }
+
+    fn finish(&mut self, succeeded: bool) {
+        self.succeeded = succeeded;
+    }
+}
+
+impl Drop for Checkout<'_> {
+    /// Resets the page in the background so the response isn't held up; failed pages are replaced.
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            let mut concurrency = self.concurrency.lock().unwrap_or_else(|e| e.into_inner());
+            // Released under the lock, so a concurrent resize sees either the slot or the debt.
+            if concurrency.owed > 0 {
+                concurrency.owed -= 1;
+                permit.forget();
+            } else {
+                drop(permit);
+            }
+        }
+        let Some(pooled) = self.pooled.take() else {
+            return;
+        };
+        let (pages, visited, reusable) = (self.pages.clone(), std::mem::take(&mut self.visited), self.reusable && self.succeeded);
+        tokio::spawn(async move { pages.release(pooled, &visited, reusable).await });
+    }
+}
+
+/// Bounds on a `capture_full_page`.
+pub struct FullPageLimits {
+    /// Longer pages are cut off.
+    pub max_height: u32,
+    /// The capture is abandoned after this, including time spent waiting on the strip receiver.
+    pub deadline: Duration,
+}
+
+/// Where `capture_full_page` delivers the capture as it's taken.
+pub struct StripSender {
+    pub info: oneshot::Sender<FullPageInfo>,
+    /// PNG strips from the top of the page, each as high as the viewport except the last.
+    pub strips: mpsc::Sender<Vec<u8>>,
+}
+
+/// How and by what a thumbnail was rendered, kept with its cache entry so older thumbnails can
+/// be explained and those from a given Chrome found after an upgrade.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Unix seconds when the page was captured.
+    pub rendered_at: u64,
+    /// Version of the service that rendered it, which is the worker's when queued.
+    pub service_version: String,
+    /// Browser product and version as reported over CDP, e.g. `HeadlessChrome/120.0.6099.109`.
+    pub chrome_version: Option<String>,
+    pub viewport_width: u32,
+    pub viewport_height: u32,
+    pub wait: WaitStrategy,
+    /// Time from navigation finishing until the page was ready to capture.
+    pub settle_time_ms: u64,
+}
+
+/// A page that finished loading, ready to be captured.
+struct LoadedPage {
+    info: PageInfo,
+    final_url: Option<String>,
+    collector: Option<DiagnosticsCollector>,
+    settle_time: Duration,
+}
+
+/// What a page says about itself once loaded.
+struct PageInfo {
+    title: Option<String>,
+    description: Option<String>,
+    og_image: Option<String>,
+    paywalled: bool,
+    language: Option<String>,
+    excerpt: Option<String>,
+    /// When navigation finished and settling began.
+    navigated_at: Instant,
+}
+
+/// Render failures caused by the target page itself; these are not retried.
+#[derive(Debug, thiserror::Error, Serialize, Deserialize)]
+pub enum RenderError {
+    #[error("page downloaded more than {0} bytes")]
+    TooManyBytes(u64),
+    #[error("page made more than {0} requests")]
+    TooManyRequests(u64),
+    #[error("page did not finish loading within {0:?}")]
+    NavigationTimeout(Duration),
+    #[error("{0}")]
+    ActionFailed(String),
+    #[error("page redirected more than {0} times")]
+    TooManyRedirects(u32),
+    #[error("page redirected to another origin: {0}")]
+    CrossOriginRedirect(String),
+    #[error("page used more than {0:?} of CPU time")]
+    TooMuchCpu(Duration),
+    #[error("page redirected to {0}, which is not an allowed domain")]
+    DisallowedRedirect(String),
+    #[error("capture took longer than {0:?}")]
+    CaptureTimeout(Duration),
+}
+
+impl RenderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RenderError::TooManyBytes(_) => "page_byte_budget_exceeded",
+            RenderError::TooManyRequests(_) => "page_request_budget_exceeded",
+            RenderError::NavigationTimeout(_) => "page_navigation_budget_exceeded",
+            RenderError::ActionFailed(_) => "action_failed",
+            RenderError::TooManyRedirects(_) => "too_many_redirects",
+            RenderError::CrossOriginRedirect(_) => "cross_origin_redirect",
+            RenderError::TooMuchCpu(_) => "page_cpu_budget_exceeded",
+            RenderError::DisallowedRedirect(_) => "domain_not_allowed",
+            RenderError::CaptureTimeout(_) => "full_page_timeout",
+        }
+    }
+}
+
+/// Limits on what a single target page may consume; zero disables a limit.
+#[derive(Debug, Clone)]
+pub struct PageBudget {
+    pub max_bytes: u64,
+    pub max_requests: u64,
+    pub navigation: Duration,
+    /// Main-thread CPU time the page's scripts and layout may use.
+    pub max_cpu: Duration,
+}
+
+/// A change in the browser's availability.
+#[derive(Debug, Clone)]
+pub enum BrowserEvent {
+    /// A launched browser exited; it isn't restarted.
+    Exited,
+    /// The connection to a remote browser dropped and is being retried.
+    Disconnected,
+    /// A remote browser is back after being gone for the given time.
+    Reconnected { downtime: Duration },
 }
 
 pub struct ThumbnailGenerator {
     browser: Arc<Mutex<Browser>>,
+    /// Reported by the browser at launch and after each reconnect.
+    chrome_version: Arc<RwLock<Option<String>>>,
+    pages: Arc<PagePool>,
     semaphore: Arc<Semaphore>,
+    /// Size of `semaphore`, adjustable at runtime.
+    concurrency: std::sync::Mutex<Concurrency>,
+    budget: RwLock<PageBudget>,
+    /// Cleared when a launched browser exits; remote browsers are reconnected instead.
+    running: Arc<AtomicBool>,
+    events: broadcast::Sender<BrowserEvent>,
 }
 
 impl ThumbnailGenerator {
-    pub async fn new() -> anyhow::Result<Self> {
-        let chrome_path = find_chrome()?;
-        info!("Using Chrome at: {:?}", chrome_path);
-
-        let config = BrowserConfig::builder()
-            .chrome_executable(chrome_path)
-            .no_sandbox()
-            .arg("--disable-setuid-sandbox")
-            .arg("--disable-dev-shm-usage")
-            .arg("--disable-accelerated-2d-canvas")
-            .arg("--no-first-run")
-            .arg("--disable-gpu")
-            .arg("--disable-background-timer-throttling")
-            .arg("--disable-renderer-backgrounding")
-            .arg("--disable-backgrounding-occluded-windows")
-            .arg("--disable-features=TranslateUI")
-            .arg("--disable-component-extensions-with-background-pages")
-            .arg("--disable-blink-features=AutomationControlled")
-            .arg("--user-agent=Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-            .window_size(1920, 1080)
-            .build()
-            .map_err(|e| anyhow::anyhow!("Failed to build browser config: {}", e))?;
-
-        let (browser, mut handler) = Browser::launch(config).await?;
-        let browser = Arc::new(Mutex::new(browser));
-        
-        tokio::spawn(async move {
-            loop {
-                match handler.next().await {
-                    Some(Ok(_)) => continue,
-                    Some(Err(e)) => {
-                        warn!("Browser handler error: {}", e);
-                        tokio::time::sleep(Duration::from_millis(100)).await;
-                    }
-                    None => {
-                        error!("Browser handler stream ended");
-                        break;
-                    }
+    pub async fn new(chrome: &config::BrowserConfig, config: &RenderConfig) -> anyhow::Result<Self> {
+        let (browser, handler) = match &chrome.ws_url {
+            Some(ws_url) => {
+                if !chrome.font_dirs.is_empty() {
+                    warn!("Ignoring font_dirs: fonts for a remote browser must be installed where it runs");
                 }
+                connect(ws_url).await?
             }
-        });
+            None => launch(chrome).await?,
+        };
+        let browser = Arc::new(Mutex::new(browser));
+        tokio::spawn(lockdown::close_popups(browser.clone()));
+        let pages = PagePool::new(browser.clone(), config.page_pool());
+        let chrome_version = Arc::new(RwLock::new(None));
+        let running = Arc::new(AtomicBool::new(true));
+        let (events, _) = broadcast::channel(16);
 
+        match &chrome.ws_url {
+            Some(ws_url) => tokio::spawn(stay_connected(
+                ws_url.clone(),
+                handler,
+                browser.clone(),
+                pages.clone(),
+                chrome_version.clone(),
+                events.clone(),
+            )),
+            None => {
+                let (running, events) = (running.clone(), events.clone());
+                tokio::spawn(async move {
+                    drive(handler).await;
+                    running.store(false, Ordering::Relaxed);
+                    error!("Browser exited; only cached and browserless thumbnails are available until restart");
+                    let _ = events.send(BrowserEvent::Exited);
+                })
+            }
+        };
+        // The handler must be running before the browser can answer.
+        let version = browser_version(&*browser.lock().await).await;
+        info!("Browser version: {}", version.as_deref().unwrap_or("unknown"));
+        *chrome_version.write().unwrap_or_else(|e| e.into_inner()) = version;
+
+        pages.warm();
+
+        let concurrency = config.max_concurrent_renders.max(1);
         Ok(Self {
             browser,
-            semaphore: Arc::new(Semaphore::new(3)),
+            chrome_version,
+            pages,
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+            concurrency: std::sync::Mutex::new(Concurrency { limit: concurrency, owed: 0 }),
+            budget: RwLock::new(config.page_budget()),
+            running,
+            events,
         })
     }
 
-    pub async fn generate(&self, url: &str, width: u32, height: u32) -> anyhow::Result<ThumbnailResult> {
+    /// Whether the browser is still there to render with.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Events for the browser exiting or its connection dropping from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<BrowserEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.lock().unwrap_or_else(|e| e.into_inner()).limit
+    }
+
+    /// Resizes the render slot pool. Shrinking removes idle slots at once and the rest as
+    /// in-flight renders finish.
+    pub fn set_concurrency(&self, limit: usize) {
+        let limit = limit.max(1);
+        let mut current = self.concurrency.lock().unwrap_or_else(|e| e.into_inner());
+        if limit > current.limit {
+            // Slots still owed from an earlier shrink are kept rather than added back.
+            let growth = limit - current.limit;
+            let kept = growth.min(current.owed);
+            current.owed -= kept;
+            self.semaphore.add_permits(growth - kept);
+        } else if limit < current.limit {
+            let excess = current.limit - limit;
+            current.owed += excess - self.semaphore.forget_permits(excess);
+        }
+        info!("Render concurrency set to {}", limit);
+        current.limit = limit;
+    }
+
+    pub fn budget(&self) -> PageBudget {
+        self.budget.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_navigation_budget(&self, navigation: Duration) {
+        self.budget.write().unwrap_or_else(|e| e.into_inner()).navigation = navigation;
+        info!("Navigation budget set to {:?}", navigation);
+    }
+
+    /// Renders a thumbnail, recording its stages in `tracer` as they finish, so a render that
+    /// fails or is cut short by the caller still leaves the stages it got through.
+    pub async fn generate(
+        &self,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        tracer: &Tracer,
+    ) -> anyhow::Result<ThumbnailResult> {
+        let mut queue_time = Duration::ZERO;
         for attempt in 1..=3 {
-            match self.try_generate(url, width, height).await {
-                Ok(result) => return Ok(result),
+            match self.try_generate(url, width, height, options, &mut queue_time, tracer).await {
+                Ok(mut result) => {
+                    result.queue_time = queue_time;
+                    return Ok(result);
+                }
+                Err(e) if e.is::<RenderError>() => return Err(e),
                 Err(e) if attempt < 3 => {
                     warn!("Attempt {} failed for {}: {}, retrying...", attempt, url, e);
                     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -85,24 +635,245 @@ impl ThumbnailGenerator {
         Err(anyhow::anyhow!("All attempts failed"))
     }
 
-    async fn try_generate(&self, url: &str, width: u32, height: u32) -> anyhow::Result<ThumbnailResult> {
-        let _permit = self.semaphore.acquire().await?;
-        
-        let browser = timeout(
+    async fn try_generate(
+        &self,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        queue_time: &mut Duration,
+        tracer: &Tracer,
+    ) -> anyhow::Result<ThumbnailResult> {
+        let waiting = Instant::now();
+        let mut checkout = self.checkout(url, options, queue_time).await?;
+        tracer.record("queue_wait", waiting);
+        let result = self.capture(checkout.page(), url, width, height, options, tracer).await;
+        checkout.finish(result.is_ok());
+        result
+    }
+
+    /// Loads a page as for a thumbnail and returns its settled DOM instead of a screenshot.
+    pub async fn inspect(&self, url: &str, width: u32, height: u32, options: &RenderOptions) -> anyhow::Result<DomSnapshot> {
+        let mut queue_time = Duration::ZERO;
+        let mut checkout = self.checkout(url, options, &mut queue_time).await?;
+        let result = self.snapshot_dom(checkout.page(), url, width, height, options).await;
+        checkout.finish(result.is_ok());
+        result
+    }
+
+    /// Loads a page as for a thumbnail, then captures its whole height in viewport-high strips,
+    /// sending each as soon as it's taken. Stops early, without error, once `output` is dropped.
+    pub async fn capture_full_page(
+        &self,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        limits: FullPageLimits,
+        output: StripSender,
+    ) -> anyhow::Result<()> {
+        let FullPageLimits { max_height, deadline } = limits;
+        let mut queue_time = Duration::ZERO;
+        let mut checkout = self.checkout(url, options, &mut queue_time).await?;
+        let page = checkout.page();
+        // A client that stops reading holds the strip sender up, so the page and slot are only
+        // kept until the deadline.
+        let capture = async {
+            let loaded = self.prepare(page, url, width, height, options, &Tracer::disabled()).await?;
+            let (viewport_width, viewport_height) = options.viewport.unwrap_or((width, height));
+            let metrics = timeout(Duration::from_secs(5), page.layout_metrics())
+                .await
+                .map_err(|_| anyhow::anyhow!("Timeout reading layout metrics"))??;
+            let content_height = (metrics.css_content_size.height.ceil() as u32).clamp(1, max_height);
+
+            let info = FullPageInfo {
+                width: viewport_width,
+                height: content_height,
+                final_url: loaded.final_url,
+            };
+            if output.info.send(info).is_err() {
+                return Ok(());
+            }
+            info!("Capturing {} at {}x{} in strips", url, viewport_width, content_height);
+
+            for top in (0..content_height).step_by(viewport_height as usize) {
+                let clip = ClipRect {
+                    x: 0.0,
+                    y: top as f64,
+                    width: viewport_width as f64,
+                    height: viewport_height.min(content_height - top) as f64,
+                    scale: 1.0,
+                };
+                let strip = timeout(
+                    Duration::from_secs(10),
+                    page.screenshot(
+                        ScreenshotParams::builder()
+                            .format(CaptureScreenshotFormat::Png)
+                            .clip(clip)
+                            .capture_beyond_viewport(true)
+                            .build()
+                    )
+                ).await
+                 .map_err(|_| anyhow::anyhow!("Timeout taking screenshot"))?
+                 .map_err(|e| anyhow::anyhow!("Screenshot failed: {}", e))?;
+                if output.strips.send(strip).await.is_err() {
+                    info!("Stopped capturing {}: nothing is receiving the strips", url);
+                    break;
+                }
+            }
+            anyhow::Ok(())
+        };
+        let result = match timeout(deadline.saturating_sub(queue_time), capture).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("Stopped capturing {} after {:?}", url, deadline);
+                Err(RenderError::CaptureTimeout(deadline).into())
+            }
+        };
+        checkout.finish(result.is_ok());
+        result
+    }
+
+    /// Waits for a render slot and takes a page from the pool, adding the wait to `queue_time`.
+    /// Renders with a session get a page of their own so its login isn't shared.
+    async fn checkout(&self, url: &str, options: &RenderOptions, queue_time: &mut Duration) -> anyhow::Result<Checkout<'_>> {
+        let queued_at = Instant::now();
+        let permit = self.semaphore.acquire().await?;
+
+        // Pages start blank so budget listeners are attached before the target starts loading.
+        let pooled = if options.session.is_some() {
+            self.pages.acquire_isolated().await?
+        } else {
+            self.pages.acquire().await?
+        };
+        *queue_time += queued_at.elapsed();
+        info!("Rendering {} in a pooled page", url);
+        Ok(Checkout {
+            permit: Some(permit),
+            concurrency: &self.concurrency,
+            page: pooled.page.clone(),
+            pooled: Some(pooled),
+            pages: self.pages.clone(),
+            visited: url.to_string(),
+            // Credentials can't be removed from a page once set, so such pages are never reused.
+            reusable: options.credentials.is_none(),
+            succeeded: false,
+        })
+    }
+
+    async fn capture(
+        &self,
+        page: &Page,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        tracer: &Tracer,
+    ) -> anyhow::Result<ThumbnailResult> {
+        let loaded = self.prepare(page, url, width, height, options, tracer).await?;
+
+        let capturing = Instant::now();
+        let screenshot = timeout(
             Duration::from_secs(10),
-            self.browser.lock()
-        ).await.map_err(|_| anyhow::anyhow!("Timeout acquiring browser lock"))?;
+            page.screenshot(
+                ScreenshotParams::builder()
+                    .format(CaptureScreenshotFormat::Png)
+                    .full_page(false)
+                    .build()
+            )
+        ).await
+         .map_err(|_| anyhow::anyhow!("Timeout taking screenshot"))?
+         .map_err(|e| anyhow::anyhow!("Screenshot failed: {}", e))?;
 
-        info!("Creating page for: {}", url);
-        
-        let page = timeout(
-            Duration::from_secs(15),
-            browser.new_page(url)
-        ).await.map_err(|_| anyhow::anyhow!("Timeout creating page"))?
-         .map_err(|e| anyhow::anyhow!("Failed to create page: {}", e))?;
+        if screenshot.is_empty() {
+            return Err(anyhow::anyhow!("Screenshot is empty"));
+        }
+        tracer.record("screenshot", capturing);
+
+        info!("Screenshot captured: {} bytes", screenshot.len());
+
+        Ok(ThumbnailResult {
+            image_data: screenshot,
+            title: loaded.info.title,
+            description: loaded.info.description,
+            final_url: loaded.final_url,
+            og_image: loaded.info.og_image,
+            paywalled: loaded.info.paywalled,
+            language: loaded.info.language,
+            excerpt: loaded.info.excerpt,
+            diagnostics: loaded.collector.map(DiagnosticsCollector::finish),
+            provenance: Provenance {
+                rendered_at: now_secs(),
+                service_version: env!("CARGO_PKG_VERSION").to_string(),
+                chrome_version: self.chrome_version.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                viewport_width: options.viewport.map_or(width, |(w, _)| w),
+                viewport_height: options.viewport.map_or(height, |(_, h)| h),
+                wait: options.wait,
+                settle_time_ms: loaded.settle_time.as_millis() as u64,
+            },
+            queue_time: Duration::ZERO,
+            trace: None,
+        })
+    }
+
+    async fn snapshot_dom(
+        &self,
+        page: &Page,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+    ) -> anyhow::Result<DomSnapshot> {
+        let loaded = self.prepare(page, url, width, height, options, &Tracer::disabled()).await?;
+
+        let html = timeout(Duration::from_secs(10), page.content())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout serializing the DOM"))??;
+        let metrics = timeout(Duration::from_secs(5), page.layout_metrics())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout reading layout metrics"))??;
+
+        Ok(DomSnapshot {
+            final_url: loaded.final_url,
+            title: loaded.info.title,
+            viewport: Viewport {
+                width: metrics.css_layout_viewport.client_width,
+                height: metrics.css_layout_viewport.client_height,
+                scroll_x: metrics.css_layout_viewport.page_x,
+                scroll_y: metrics.css_layout_viewport.page_y,
+                content_width: metrics.css_content_size.width,
+                content_height: metrics.css_content_size.height,
+                scale: metrics.css_visual_viewport.scale,
+            },
+            html,
+        })
+    }
+
+    /// Sets the page up for the request, then loads the URL, waits for it to settle, and runs
+    /// any actions.
+    async fn prepare(
+        &self,
+        page: &Page,
+        url: &str,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        tracer: &Tracer,
+    ) -> anyhow::Result<LoadedPage> {
+        let started = Instant::now();
+        let (width, height) = options.viewport.unwrap_or((width, height));
+        if let Some(auth) = &options.credentials {
+            page.authenticate(Credentials {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            }).await?;
+        }
+        if let Some(session) = &options.session {
+            session::apply(page, session).await?;
+        }
 
         let user_agent = SetUserAgentOverrideParams {
-            user_agent: "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36".to_string(),
+            user_agent: USER_AGENT.to_string(),
             accept_language: Some("en-US,en;q=0.9".to_string()),
             platform: Some("MacIntel".to_string()),
             user_agent_metadata: None,
@@ -112,7 +883,7 @@ impl ThumbnailGenerator {
         let device_metrics = SetDeviceMetricsOverrideParams {
             width: width as i64,
             height: height as i64,
-            device_scale_factor: 1.0,
+            device_scale_factor: options.oversample.unwrap_or(1.0) as f64,
             mobile: false,
             scale: None,
             screen_width: Some(width as i64),
@@ -130,7 +901,76 @@ impl ThumbnailGenerator {
             page.execute(device_metrics)
         ).await.map_err(|_| anyhow::anyhow!("Timeout setting viewport"))??;
 
-        tokio::time::sleep(Duration::from_millis(2000)).await;
+        // Always set, since pooled pages keep whatever the previous render used.
+        timeout(
+            Duration::from_secs(5),
+            page.execute(SetScriptExecutionDisabledParams::new(options.disable_javascript))
+        ).await.map_err(|_| anyhow::anyhow!("Timeout setting script execution"))??;
+        timeout(
+            Duration::from_secs(5),
+            page.execute(SetIgnoreCertificateErrorsParams::new(options.ignore_certificate_errors))
+        ).await.map_err(|_| anyhow::anyhow!("Timeout setting certificate handling"))??;
+        // An empty list clears the previous render's overrides.
+        timeout(
+            Duration::from_secs(5),
+            page.execute(SetEmulatedMediaParams::builder().features(options.media.to_cdp()).build())
+        ).await.map_err(|_| anyhow::anyhow!("Timeout setting media features"))??;
+
+        let budget = self.budget();
+        let mut monitor = BudgetMonitor::start(page, &budget, url, &options.redirects).await?;
+        let collector = if options.diagnostics {
+            Some(DiagnosticsCollector::start(page).await?)
+        } else {
+            None
+        };
+        tracer.record("setup", started);
+        let navigation = timeout(budget.navigation, self.load(page, url, options, tracer));
+        let info = tokio::select! {
+            loaded = navigation => loaded.map_err(|_| RenderError::NavigationTimeout(budget.navigation))??,
+            exceeded = monitor.exceeded() => return Err(exceeded.into()),
+        };
+
+        if !options.actions.is_empty() {
+            tokio::select! {
+                performed = perform_actions(page, &options.actions) => performed?,
+                exceeded = monitor.exceeded() => return Err(exceeded.into()),
+            }
+        }
+
+        if options.stabilize {
+            stabilize(page).await;
+        }
+        clean_up_ui(page).await;
+        tracer.record("settle", info.navigated_at);
+
+        Ok(LoadedPage {
+            settle_time: info.navigated_at.elapsed(),
+            info,
+            final_url: page.url().await.ok().flatten(),
+            collector,
+        })
+    }
+
+    /// Navigates and waits for the page to settle, returning what it says about itself.
+    async fn load(&self, page: &Page, url: &str, options: &RenderOptions, tracer: &Tracer) -> anyhow::Result<PageInfo> {
+        let navigating = Instant::now();
+        page.goto(url).await
+            .map_err(|e| anyhow::anyhow!("Navigation failed: {}", e))?;
+        tracer.record("navigation", navigating);
+        let navigated_at = Instant::now();
+
+        match options.wait {
+            WaitStrategy::Load => tokio::time::sleep(Duration::from_millis(2000)).await,
+            WaitStrategy::NetworkIdle => wait_for_network_idle(page).await,
+        }
+        if let Some(selector) = &options.wait_for_selector {
+            if let Err(e) = wait_for_element(page, selector, SELECTOR_WAIT).await {
+                warn!("Capturing {} without waiting for it: {}", url, e);
+            }
+        }
+        if options.extra_delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(options.extra_delay_ms)).await;
+        }
 
         let title = timeout(Duration::from_secs(5), page.get_title())
             .await
@@ -146,40 +986,37 @@ impl ThumbnailGenerator {
             .and_then(|r| r.value().and_then(|v| v.as_str().map(|s| s.to_string())))
             .filter(|s| !s.is_empty() && s != "null");
 
-        let _ = page.evaluate(r#"
-            document.body.style.overflow = 'hidden';
-            const selectors = ['[class*="cookie"]', '[class*="consent"]', '[id*="cookie"]', '[class*="gdpr"]'];
-            selectors.forEach(sel => {
-                document.querySelectorAll(sel).forEach(el => el.style.display = 'none');
-            });
-        "#).await;
+        let og_image = timeout(Duration::from_secs(5), page.evaluate(r#"
+            (() => {
+                const content = document.querySelector('meta[property="og:image"]')?.content;
+                try { return content ? new URL(content, document.baseURI).href : null; } catch { return null; }
+            })()
+        "#)).await
+            .ok()
+            .and_then(|r| r.ok())
+            .and_then(|r| r.value().and_then(|v| v.as_str().map(|s| s.to_string())));
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        // Before consent handling and hidden selectors, which may remove the wall from view.
+        let paywalled = paywall::detect(page, url).await;
+        let text = language::extract(page, url).await;
 
-        let screenshot = timeout(
-            Duration::from_secs(10),
-            page.screenshot(
-                ScreenshotParams::builder()
-                    .format(CaptureScreenshotFormat::Png)
-                    .full_page(false)
-                    .build()
-            )
-        ).await
-         .map_err(|_| anyhow::anyhow!("Timeout taking screenshot"))?
-         .map_err(|e| anyhow::anyhow!("Screenshot failed: {}", e))?;
-
-        if screenshot.is_empty() {
-            return Err(anyhow::anyhow!("Screenshot is empty"));
+        let _ = page.evaluate("document.body.style.overflow = 'hidden'").await;
+        consent::handle(page, url, options.consent).await;
+        if !options.hide_selectors.is_empty() {
+            hide_elements(page, &options.hide_selectors).await;
         }
 
-        info!("Screenshot captured: {} bytes", screenshot.len());
-
-        let _ = timeout(Duration::from_secs(5), page.close()).await;
+        wait_for_fonts(page, url).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
 
-        Ok(ThumbnailResult {
-            image_data: screenshot,
+        Ok(PageInfo {
             title,
             description,
+            og_image,
+            paywalled,
+            language: text.language,
+            excerpt: text.excerpt,
+            navigated_at,
         })
     }
 
@@ -188,50 +1025,523 @@ impl ThumbnailGenerator {
             Ok(guard) => guard,
             Err(_) => return false,
         };
-        timeout(Duration::from_secs(5), browser.new_page("about:blank"))
+        matches!(timeout(Duration::from_secs(5), browser.version()).await, Ok(Ok(_)))
+    }
+}
+
+async fn browser_version(browser: &Browser) -> Option<String> {
+    match timeout(Duration::from_secs(5), browser.version()).await {
+        Ok(Ok(version)) => Some(version.product),
+        Ok(Err(e)) => {
+            warn!("Failed to read the browser version: {}", e);
+            None
+        }
+        Err(_) => {
+            warn!("Timeout reading the browser version");
+            None
+        }
+    }
+}
+
+/// Starts a local Chrome with the configured executable and flags.
+async fn launch(chrome: &config::BrowserConfig) -> anyhow::Result<(Browser, Handler)> {
+    let chrome_path = match &chrome.chrome_executable {
+        Some(path) if path.exists() => path.clone(),
+        Some(path) => anyhow::bail!("Configured chrome_executable {:?} does not exist", path),
+        None => match find_chrome() {
+            Ok(path) => path,
+            Err(e) if chrome.download.enabled => {
+                warn!("{}", e);
+                browser_download::install(&chrome.download).await?
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    info!("Using Chrome at: {:?} (headless: {:?}, sandbox: {})", chrome_path, chrome.headless, chrome.sandbox);
+
+    let mut builder = BrowserConfig::builder()
+        .chrome_executable(chrome_path)
+        .headless_mode(match chrome.headless {
+            config::HeadlessMode::Old => HeadlessMode::True,
+            config::HeadlessMode::New => HeadlessMode::New,
+            config::HeadlessMode::Off => HeadlessMode::False,
+        });
+    if !chrome.sandbox {
+        builder = builder.no_sandbox().arg("--disable-setuid-sandbox");
+    }
+    if !chrome.font_dirs.is_empty() {
+        let fontconfig = write_fontconfig(&chrome.font_dirs)?;
+        info!("Adding font directories {:?} through {:?}", chrome.font_dirs, fontconfig);
+        builder = builder.env("FONTCONFIG_FILE", fontconfig.to_string_lossy());
+    }
+
+    let browser_config = builder
+        .arg("--disable-dev-shm-usage")
+        .arg("--disable-accelerated-2d-canvas")
+        .arg("--no-first-run")
+        .arg("--disable-gpu")
+        .arg("--hide-scrollbars")
+        .arg("--disable-background-timer-throttling")
+        .arg("--disable-renderer-backgrounding")
+        .arg("--disable-backgrounding-occluded-windows")
+        .arg("--disable-features=TranslateUI")
+        .arg("--disable-component-extensions-with-background-pages")
+        .arg("--disable-blink-features=AutomationControlled")
+        .arg("--user-agent=Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
+        .window_size(1920, 1080)
+        .args(chrome.extra_chrome_args.iter())
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to build browser config: {}", e))?;
+
+    Ok(Browser::launch(browser_config).await?)
+}
+
+/// Writes a fontconfig file that adds `dirs` to the system configuration, for Chrome to load
+/// through `FONTCONFIG_FILE`.
+fn write_fontconfig(dirs: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    let escape = |path: &PathBuf| {
+        path.to_string_lossy().replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    };
+    let mut conf = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE fontconfig SYSTEM \"fonts.dtd\">\n<fontconfig>\n  \
+         <include ignore_missing=\"yes\">/etc/fonts/fonts.conf</include>\n",
+    );
+    for dir in dirs {
+        if !dir.is_dir() {
+            anyhow::bail!("Font directory {:?} does not exist", dir);
+        }
+        conf.push_str(&format!("  <dir>{}</dir>\n", escape(dir)));
+    }
+    conf.push_str("</fontconfig>\n");
+
+    let path = std::env::temp_dir().join(format!("tin-fonts-{}.conf", std::process::id()));
+    std::fs::write(&path, conf).map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Attaches to an already running browser over CDP; `ws_url` may also be its HTTP debugging address.
+async fn connect(ws_url: &str) -> anyhow::Result<(Browser, Handler)> {
+    info!("Connecting to remote browser at {}", ws_url);
+    timeout(Duration::from_secs(15), Browser::connect(ws_url))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timeout connecting to remote browser at {}", ws_url))?
+        .map_err(|e| anyhow::anyhow!("Failed to connect to remote browser at {}: {}", ws_url, e))
+}
+
+/// Processes CDP messages until the connection to the browser ends.
+async fn drive(mut handler: Handler) {
+    loop {
+        match handler.next().await {
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                warn!("Browser handler error: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            None => {
+                error!("Browser handler stream ended");
+                break;
+            }
+        }
+    }
+}
+
+/// Drives a remote browser connection, reconnecting with backoff when it drops, since remote
+/// instances are replaced as their pool scales.
+async fn stay_connected(
+    ws_url: String,
+    mut handler: Handler,
+    browser: Arc<Mutex<Browser>>,
+    pages: Arc<PagePool>,
+    chrome_version: Arc<RwLock<Option<String>>>,
+    events: broadcast::Sender<BrowserEvent>,
+) {
+    loop {
+        drive(handler).await;
+        pages.clear();
+        let dropped = Instant::now();
+        let _ = events.send(BrowserEvent::Disconnected);
+
+        let mut backoff = Duration::from_secs(1);
+        handler = loop {
+            tokio::time::sleep(backoff).await;
+            match connect(&ws_url).await {
+                Ok((new_browser, new_handler)) => {
+                    *browser.lock().await = new_browser;
+                    break new_handler;
+                }
+                Err(e) => {
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                    warn!("{}; retrying in {:?}", e, backoff);
+                }
+            }
+        };
+        info!("Reconnected to remote browser at {}", ws_url);
+        let _ = events.send(BrowserEvent::Reconnected { downtime: dropped.elapsed() });
+        let version = browser_version(&*browser.lock().await).await;
+        *chrome_version.write().unwrap_or_else(|e| e.into_inner()) = version;
+        pages.warm();
+    }
+}
+
+/// Sent by pages and by content probes, so sites answer both the same way.
+pub const USER_AGENT: &str =
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+/// How often a page's CPU time is checked against its budget.
+const CPU_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a profile's `wait_for_selector` may hold up a capture.
+const SELECTOR_WAIT: Duration = Duration::from_secs(10);
+/// How long a capture waits for web fonts, after which it shows whatever fallback is in use.
+const FONTS_WAIT: Duration = Duration::from_secs(3);
+const NETWORK_IDLE: Duration = Duration::from_millis(500);
+const NETWORK_IDLE_MAX: Duration = Duration::from_secs(10);
+
+/// Finishes finite animations and cancels infinite ones, rewinds and pauses media, and clears
+/// pending timers so carousels stop advancing. Transitions started afterwards are disabled too.
+const STABILIZE_SCRIPT: &str = r#"
+    (() => {
+        for (const animation of document.getAnimations()) {
+            const timing = animation.effect && animation.effect.getComputedTiming();
+            if (timing && Number.isFinite(timing.endTime)) {
+                animation.finish();
+            } else {
+                animation.cancel();
+            }
+        }
+        document.querySelectorAll('video, audio').forEach(media => {
+            media.autoplay = false;
+            media.pause();
+            try { media.currentTime = 0; } catch (e) {}
+        });
+        const last = setTimeout(() => {});
+        for (let id = 0; id <= last; id++) {
+            clearTimeout(id);
+            clearInterval(id);
+        }
+        const style = document.createElement('style');
+        style.textContent = '*, *::before, *::after { transition: none !important; caret-color: transparent !important; }';
+        (document.head || document.documentElement).appendChild(style);
+    })()
+"#;
+
+async fn stabilize(page: &Page) {
+    match timeout(Duration::from_secs(5), page.evaluate(STABILIZE_SCRIPT)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!("Failed to stabilize page: {}", e),
+        Err(_) => warn!("Timeout stabilizing page"),
+    }
+    // Let the finished animations and rewound videos paint.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+/// Hides scrollbars, focus rings, text cursors, and selections, which depend on incidental state
+/// such as an autofocused input or a click action rather than on the page itself.
+const HYGIENE_SCRIPT: &str = r#"
+    (() => {
+        const style = document.createElement('style');
+        style.textContent = `
+            ::-webkit-scrollbar { display: none !important; width: 0 !important; height: 0 !important; }
+            * { scrollbar-width: none !important; caret-color: transparent !important; }
+            *:focus, *:focus-visible, *:focus-within { outline: none !important; }
+        `;
+        (document.head || document.documentElement).appendChild(style);
+        if (document.activeElement && document.activeElement !== document.body) document.activeElement.blur();
+        getSelection()?.removeAllRanges();
+    })()
+"#;
+
+async fn clean_up_ui(page: &Page) {
+    match timeout(Duration::from_secs(5), page.evaluate(HYGIENE_SCRIPT)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!("Failed to hide incidental UI: {}", e),
+        Err(_) => warn!("Timeout hiding incidental UI"),
+    }
+}
+
+/// Waits for `document.fonts.ready`, so text isn't captured in a fallback font while web fonts
+/// are still loading.
+async fn wait_for_fonts(page: &Page, url: &str) {
+    match timeout(FONTS_WAIT, page.evaluate("document.fonts.ready.then(() => true)")).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!("Failed to wait for fonts on {}: {}", url, e),
+        Err(_) => warn!("Capturing {} before its fonts finished loading", url),
+    }
+}
+
+/// Polls until no resource has finished loading for `NETWORK_IDLE` or `NETWORK_IDLE_MAX` passes.
+async fn wait_for_network_idle(page: &Page) {
+    let deadline = Instant::now() + NETWORK_IDLE_MAX;
+    let (mut last, mut quiet_since) = (None, Instant::now());
+    while Instant::now() < deadline {
+        let count = page
+            .evaluate("performance.getEntriesByType('resource').length")
             .await
-            .is_ok()
+            .ok()
+            .and_then(|r| r.value().and_then(|v| v.as_u64()));
+        if count != last {
+            (last, quiet_since) = (count, Instant::now());
+        } else if quiet_since.elapsed() >= NETWORK_IDLE {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Hides the elements with a stylesheet, so ones inserted later are hidden too.
+async fn hide_elements(page: &Page, selectors: &[String]) {
+    let css = format!("{} {{ display: none !important; }}", selectors.join(", "));
+    let script = format!(
+        "(() => {{ const style = document.createElement('style'); style.textContent = {}; document.head.appendChild(style); }})()",
+        serde_json::Value::String(css),
+    );
+    if let Err(e) = page.evaluate(script).await {
+        warn!("Failed to hide elements: {}", e);
     }
 }
 
+/// Runs the steps in order, waiting briefly for each selector to appear.
+async fn perform_actions(page: &Page, actions: &[Action]) -> Result<(), RenderError> {
+    for (index, action) in actions.iter().enumerate() {
+        let step = index + 1;
+        let failed = |what: String| RenderError::ActionFailed(format!("action {}: {}", step, what));
+        match action {
+            Action::Click(selector) => {
+                let element = find_element(page, selector).await.map_err(failed)?;
+                element
+                    .click()
+                    .await
+                    .map_err(|e| failed(format!("failed to click {}: {}", selector, e)))?;
+            }
+            Action::Type { selector, text } => {
+                let element = find_element(page, selector).await.map_err(failed)?;
+                element
+                    .click()
+                    .await
+                    .map_err(|e| failed(format!("failed to focus {}: {}", selector, e)))?
+                    .type_str(text)
+                    .await
+                    .map_err(|e| failed(format!("failed to type into {}: {}", selector, e)))?;
+            }
+            Action::Wait(ms) => tokio::time::sleep(Duration::from_millis(*ms)).await,
+        }
+    }
+
+    // Give the page a moment to react to the last step.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    Ok(())
+}
+
+async fn find_element(page: &Page, selector: &str) -> Result<chromiumoxide::Element, String> {
+    wait_for_element(page, selector, Duration::from_secs(5)).await
+}
+
+async fn wait_for_element(page: &Page, selector: &str, within: Duration) -> Result<chromiumoxide::Element, String> {
+    let deadline = Instant::now() + within;
+    loop {
+        match page.find_element(selector).await {
+            Ok(element) => return Ok(element),
+            Err(_) if Instant::now() < deadline => tokio::time::sleep(Duration::from_millis(100)).await,
+            Err(e) => return Err(format!("no element matches {}: {}", selector, e)),
+        }
+    }
+}
+
+/// Watches a page's network events and reports the first budget or redirect limit it exceeds.
+struct BudgetMonitor {
+    exceeded: oneshot::Receiver<RenderError>,
+    task: JoinHandle<()>,
+}
+
+impl BudgetMonitor {
+    async fn start(page: &Page, budget: &PageBudget, url: &str, redirects: &RedirectPolicy) -> anyhow::Result<Self> {
+        let mut requests = page.event_listener::<EventRequestWillBeSent>().await?;
+        let mut data = page.event_listener::<EventDataReceived>().await?;
+        let main_frame = page.mainframe().await?;
+        let cpu = if budget.max_cpu.is_zero() { None } else { Some(CpuMeter::start(page).await?) };
+        let mut cpu_check = tokio::time::interval(CPU_CHECK_INTERVAL);
+        let (max_bytes, max_requests, max_cpu) = (budget.max_bytes, budget.max_requests, budget.max_cpu);
+        let (redirects, requested) = (redirects.clone(), Url::parse(url).ok());
+        let (tx, exceeded) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            let mut request_count = 0u64;
+            let mut byte_count = 0u64;
+            let mut navigations = 0u32;
+            // Time the page left unanswered because a script held its thread counts as CPU time.
+            let mut unresponsive = Duration::ZERO;
+            loop {
+                tokio::select! {
+                    Some(event) = requests.next() => {
+                        request_count += 1;
+                        if max_requests > 0 && request_count > max_requests {
+                            let _ = tx.send(RenderError::TooManyRequests(max_requests));
+                            return;
+                        }
+                        let navigation = event.r#type == Some(ResourceType::Document)
+                            && event.frame_id.is_some()
+                            && event.frame_id == main_frame;
+                        if !navigation {
+                            continue;
+                        }
+                        // The first document request is the navigation itself; later ones are redirects.
+                        navigations += 1;
+                        if navigations - 1 > redirects.max_redirects {
+                            let _ = tx.send(RenderError::TooManyRedirects(redirects.max_redirects));
+                            return;
+                        }
+                        let target = &event.request.url;
+                        // Chrome's own error and blank pages aren't fetched from anywhere.
+                        let host = Url::parse(target)
+                            .ok()
+                            .filter(|u| matches!(u.scheme(), "http" | "https"))
+                            .and_then(|u| u.host_str().map(str::to_string));
+                        if let Some(host) = host.filter(|host| !redirects.hosts.allows(host)) {
+                            let _ = tx.send(RenderError::DisallowedRedirect(host));
+                            return;
+                        }
+                        if navigations > 1
+                            && !redirects.allow_cross_origin
+                            && !requested.as_ref().is_some_and(|from| same_origin(from, target))
+                        {
+                            let _ = tx.send(RenderError::CrossOriginRedirect(target.clone()));
+                            return;
+                        }
+                    }
+                    Some(event) = data.next() => {
+                        byte_count += event.encoded_data_length.max(0) as u64;
+                        if max_bytes > 0 && byte_count > max_bytes {
+                            let _ = tx.send(RenderError::TooManyBytes(max_bytes));
+                            return;
+                        }
+                    }
+                    _ = cpu_check.tick(), if cpu.is_some() => {
+                        let Some(meter) = &cpu else { continue };
+                        let used = match meter.used(CPU_CHECK_INTERVAL).await {
+                            Some(used) => used + unresponsive,
+                            None => {
+                                unresponsive += CPU_CHECK_INTERVAL;
+                                unresponsive
+                            }
+                        };
+                        if used > max_cpu {
+                            let _ = tx.send(RenderError::TooMuchCpu(max_cpu));
+                            return;
+                        }
+                    }
+                    else => return,
+                }
+            }
+        });
+
+        Ok(Self { exceeded, task })
+    }
+
+    /// Resolves once a budget is exceeded; pending forever otherwise.
+    async fn exceeded(&mut self) -> RenderError {
+        match (&mut self.exceeded).await {
+            Ok(e) => e,
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for BudgetMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn same_origin(from: &Url, to: &str) -> bool {
+    let Ok(to) = Url::parse(to) else {
+        return false;
+    };
+    from.origin() == to.origin()
+        || (from.scheme() == "http"
+            && to.scheme() == "https"
+            && from.host_str() == to.host_str()
+            && from.port().is_none()
+            && to.port().is_none())
+}
+
+/// Environment variables that name a browser binary, checked before any install location.
+const CHROME_ENV_VARS: [&str; 2] = ["CHROME_PATH", "CHROME_BIN"];
+
 fn find_chrome() -> anyhow::Result<PathBuf> {
-    let candidates = if cfg!(target_os = "macos") {
-        vec![
+    for var in CHROME_ENV_VARS {
+        if let Some(path) = std::env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from) {
+            if !path.exists() {
+                anyhow::bail!("{} names {:?}, which does not exist", var, path);
+            }
+            return Ok(path);
+        }
+    }
+
+    let candidates: Vec<PathBuf> = if cfg!(target_os = "macos") {
+        [
             "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
             "/Applications/Brave Browser.app/Contents/MacOS/Brave Browser",
             "/Applications/Chromium.app/Contents/MacOS/Chromium",
             "/opt/homebrew/bin/chromium",
             "/usr/local/bin/chromium",
         ]
+        .iter()
+        .map(PathBuf::from)
+        .collect()
     } else if cfg!(target_os = "linux") {
-        vec![
+        let mut paths: Vec<PathBuf> = [
             "/usr/bin/google-chrome",
+            "/usr/bin/google-chrome-stable",
+            "/opt/google/chrome/chrome",
             "/usr/bin/brave",
+            "/usr/bin/brave-browser",
             "/usr/bin/chromium",
             "/usr/bin/chromium-browser",
+            "/snap/bin/chromium",
+            "/var/lib/flatpak/exports/bin/com.google.Chrome",
+            "/var/lib/flatpak/exports/bin/org.chromium.Chromium",
         ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        if let Some(home) = std::env::var_os("HOME") {
+            let exports = PathBuf::from(home).join(".local/share/flatpak/exports/bin");
+            paths.push(exports.join("com.google.Chrome"));
+            paths.push(exports.join("org.chromium.Chromium"));
+        }
+        paths
+    } else if cfg!(target_os = "windows") {
+        let roots = ["ProgramFiles", "ProgramFiles(x86)", "LocalAppData"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let installs = [
+            r"Google\Chrome\Application\chrome.exe",
+            r"BraveSoftware\Brave-Browser\Application\brave.exe",
+            r"Chromium\Application\chrome.exe",
+        ];
+        installs.iter().flat_map(|install| roots.iter().map(move |root| root.join(install))).collect()
     } else {
         vec![]
     };
 
-    for path in &candidates {
-        let p = PathBuf::from(path);
-        if p.exists() {
-            return Ok(p);
-        }
+    if let Some(path) = candidates.into_iter().find(|p| p.exists()) {
+        return Ok(path);
     }
 
-    if let Ok(output) = std::process::Command::new("which")
-        .args(&["google-chrome", "brave", "chromium", "chromium-browser"])
-        .output() 
-    {
+    let (lookup, names) = if cfg!(target_os = "windows") {
+        ("where", ["chrome", "brave", "chromium", "chromium-browser"])
+    } else {
+        ("which", ["google-chrome", "brave", "chromium", "chromium-browser"])
+    };
+    if let Ok(output) = std::process::Command::new(lookup).args(names).output() {
         let stdout = String::from_utf8_lossy(&output.stdout);
         for line in stdout.lines() {
-            if !line.is_empty() {
-                return Ok(PathBuf::from(line));
+            if !line.trim().is_empty() {
+                return Ok(PathBuf::from(line.trim()));
             }
         }
     }
 
-    anyhow::bail!("Could not find Chrome, Brave, or Chromium. Please install a Chromium-based browser.")
+    anyhow::bail!("Could not find Chrome, Brave, or Chromium. Please install a Chromium-based browser, or set browser.download.enabled to fetch one.")
 }
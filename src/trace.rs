@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// When each stage of a request started and how long it took, for `trace` requests.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Trace {
+    /// In the order they started.
+    pub stages: Vec<TraceStage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceStage {
+    /// `cache_lookup`, `host_wait`, `queue_wait`, `setup`, `navigation`, `settle`, `screenshot`,
+    /// `fetch`, `encode`, or `cache_write`.
+    pub stage: String,
+    /// Milliseconds from the start of the request.
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+impl Trace {
+    /// Milliseconds from the start of the request until the last stage ended.
+    fn span_ms(&self) -> u64 {
+        self.stages.iter().map(|s| s.start_ms + s.duration_ms).max().unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}=+{}ms/{}ms", stage.stage, stage.start_ms, stage.duration_ms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects a request's stages as it runs. Recording does nothing unless tracing was asked for,
+/// so stages can be recorded unconditionally.
+pub struct Tracer {
+    origin: Instant,
+    stages: Option<Mutex<Vec<TraceStage>>>,
+}
+
+impl Tracer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            origin: Instant::now(),
+            stages: enabled.then(Mutex::default),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(false)
+    }
+
+    /// Records a stage that began at `started` and ends now.
+    pub fn record(&self, stage: &str, started: impl Into<Instant>) {
+        let Some(stages) = &self.stages else {
+            return;
+        };
+        let started = started.into();
+        let stage = TraceStage {
+            stage: stage.to_string(),
+            start_ms: started.saturating_duration_since(self.origin).as_millis() as u64,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        stages.lock().unwrap_or_else(|e| e.into_inner()).push(stage);
+    }
+
+    /// Adds stages timed elsewhere, such as by the renderer or a queue worker, placed so the
+    /// last of them ends at `ended`.
+    pub fn extend(&self, trace: Trace, ended: Instant) {
+        let Some(stages) = &self.stages else {
+            return;
+        };
+        let ended_ms = ended.saturating_duration_since(self.origin).as_millis() as u64;
+        let offset = ended_ms.saturating_sub(trace.span_ms());
+        let mut stages = stages.lock().unwrap_or_else(|e| e.into_inner());
+        stages.extend(trace.stages.into_iter().map(|stage| TraceStage { start_ms: stage.start_ms + offset, ..stage }));
+    }
+
+    pub fn finish(self) -> Option<Trace> {
+        let mut stages = self.stages?.into_inner().unwrap_or_else(|e| e.into_inner());
+        stages.sort_by_key(|stage| stage.start_ms);
+        Some(Trace { stages })
+    }
+}
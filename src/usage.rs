@@ -0,0 +1,169 @@
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+const BUCKET_SECS: u64 = 3600;
+
+/// Recorded for callers when no tenants are configured.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Counters accumulated per tenant, key and hour.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Counters {
+    renders: u64,
+    cache_hits: u64,
+    errors: u64,
+    bytes_served: u64,
+    render_ms: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.renders += other.renders;
+        self.cache_hits += other.cache_hits;
+        self.errors += other.errors;
+        self.bytes_served += other.bytes_served;
+        self.render_ms += other.render_ms;
+    }
+}
+
+/// The outcome of one thumbnail request, as charged to its caller.
+pub enum UsageEvent {
+    Render { bytes: u64, render_time: Duration },
+    CacheHit { bytes: u64 },
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageRow {
+    pub tenant: String,
+    pub key: String,
+    pub renders: u64,
+    pub cache_hits: u64,
+    pub errors: u64,
+    pub bytes_served: u64,
+    pub render_seconds: f64,
+}
+
+/// Durable usage accounting in hourly buckets, for charging costs back to tenants.
+pub struct UsageStore {
+    tree: Tree,
+}
+
+impl UsageStore {
+    pub fn new(tree: Tree) -> Self {
+        Self { tree }
+    }
+
+    pub fn record(&self, tenant: &str, key_id: &str, at: u64, event: &UsageEvent) -> anyhow::Result<()> {
+        let delta = match event {
+            UsageEvent::Render { bytes, render_time } => Counters {
+                renders: 1,
+                bytes_served: *bytes,
+                render_ms: render_time.as_millis() as u64,
+                ..Counters::default()
+            },
+            UsageEvent::CacheHit { bytes } => Counters { cache_hits: 1, bytes_served: *bytes, ..Counters::default() },
+            UsageEvent::Error => Counters { errors: 1, ..Counters::default() },
+        };
+
+        let key = bucket_key(at / BUCKET_SECS, tenant, key_id);
+        self.tree.update_and_fetch(key, |existing| {
+            let mut counters: Counters = existing.and_then(|b| bincode::deserialize(b).ok()).unwrap_or_default();
+            counters.add(&delta);
+            bincode::serialize(&counters).ok()
+        })?;
+        Ok(())
+    }
+
+    /// Sums usage per tenant and key over `[from, to)`, at hour resolution.
+    pub fn query(&self, from: u64, to: u64, tenant: Option<&str>) -> anyhow::Result<Vec<UsageRow>> {
+        let start = (from / BUCKET_SECS).to_be_bytes();
+        let end = (to.saturating_add(BUCKET_SECS - 1) / BUCKET_SECS).to_be_bytes();
+
+        let mut totals: BTreeMap<(String, String), Counters> = BTreeMap::new();
+        for item in self.tree.range(start..end) {
+            let (key, value) = item?;
+            let Some((row_tenant, row_key)) = parse_bucket_key(&key) else {
+                continue;
+            };
+            if tenant.is_some_and(|t| t != row_tenant) {
+                continue;
+            }
+            let counters: Counters = bincode::deserialize(&value)
+                .map_err(|e| anyhow::anyhow!("Usage record deserialization failed: {}", e))?;
+            totals.entry((row_tenant, row_key)).or_default().add(&counters);
+        }
+
+        Ok(totals
+            .into_iter()
+            .map(|((tenant, key), c)| UsageRow {
+                tenant,
+                key,
+                renders: c.renders,
+                cache_hits: c.cache_hits,
+                errors: c.errors,
+                bytes_served: c.bytes_served,
+                render_seconds: c.render_ms as f64 / 1000.0,
+            })
+            .collect())
+    }
+}
+
+/// Hour first, big-endian, so a time range is a contiguous key range.
+fn bucket_key(hour: u64, tenant: &str, key_id: &str) -> Vec<u8> {
+    let mut key = hour.to_be_bytes().to_vec();
+    key.extend_from_slice(tenant.as_bytes());
+    key.push(0);
+    key.extend_from_slice(key_id.as_bytes());
+    key
+}
+
+fn parse_bucket_key(key: &[u8]) -> Option<(String, String)> {
+    let rest = key.get(8..)?;
+    let split = rest.iter().position(|b| *b == 0)?;
+    Some((
+        String::from_utf8_lossy(&rest[..split]).into_owned(),
+        String::from_utf8_lossy(&rest[split + 1..]).into_owned(),
+    ))
+}
+
+/// Accepts Unix seconds, `YYYY-MM-DD` (midnight UTC) or RFC 3339.
+pub fn parse_time(value: &str) -> Option<u64> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return u64::try_from(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp()).ok();
+    }
+    DateTime::parse_from_rfc3339(value).ok().and_then(|t| u64::try_from(t.timestamp()).ok())
+}
+
+pub fn to_csv(rows: &[UsageRow]) -> String {
+    let mut out = String::from("tenant,key,renders,cache_hits,errors,bytes_served,render_seconds\n");
+    for row in rows {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{:.3}",
+            csv_field(&row.tenant),
+            csv_field(&row.key),
+            row.renders,
+            row.cache_hits,
+            row.errors,
+            row.bytes_served,
+            row.render_seconds,
+        );
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}